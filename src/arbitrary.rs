@@ -0,0 +1,127 @@
+use std::fmt;
+use std::marker::PhantomData;
+
+use quickcheck::{Arbitrary, Gen};
+use serde_json::Value;
+
+use crate::shrink::Shrinker;
+use crate::Chimpsky;
+
+/// Where quickcheck payloads come from. quickcheck builds values from nothing but a `Gen`, so
+/// the schema set has to be reachable statically, usually from a `OnceLock`.
+pub trait PayloadSource: Clone + 'static {
+    fn chimpsky() -> &'static Chimpsky;
+
+    /// Key or name of the object payloads are generated for.
+    fn object() -> &'static str;
+}
+
+/// A payload of the object named by `S`, for use as a quickcheck property argument. Panics when
+/// the object does not exist or cannot be generated, since there is no way to report an error.
+#[derive(Clone)]
+pub struct Payload<S> {
+    pub value: Value,
+    source: PhantomData<fn() -> S>,
+}
+
+impl<S> Payload<S> {
+    pub fn into_inner(self) -> Value {
+        self.value
+    }
+}
+
+impl<S> fmt::Debug for Payload<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.value)
+    }
+}
+
+impl<S: PayloadSource> Arbitrary for Payload<S> {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let generator = S::chimpsky().generator(S::object()).unwrap_or_else(|e| panic!("{}", e));
+        let value = generator.seed(u64::arbitrary(g)).generate();
+        Payload { value, source: PhantomData }
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        let chimpsky = S::chimpsky();
+        let key = chimpsky.object_key(S::object()).unwrap_or_else(|e| panic!("{}", e));
+        let candidates = Shrinker::new(chimpsky.reference_map()).object(&chimpsky.reference_map()[key], &self.value);
+        Box::new(candidates.into_iter().map(|value| Payload { value, source: PhantomData }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::OnceLock;
+
+    use quickcheck::{QuickCheck, TestResult};
+    use serde_json::json;
+
+    use crate::schema_parser::{Schema, SchemaSet};
+    use crate::verifier::Verifier;
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct Message;
+
+    impl PayloadSource for Message {
+        fn chimpsky() -> &'static Chimpsky {
+            static CHIMPSKY: OnceLock<Chimpsky> = OnceLock::new();
+            CHIMPSKY.get_or_init(|| {
+                let mut set = SchemaSet::default();
+                set.add(Schema::new(&json!({
+                    "definitions": {
+                        "message": {
+                            "type": "object",
+                            "properties": {
+                                "id": { "type": "string", "format": "uuid" },
+                                "name": { "type": "string" },
+                                "tags": { "type": "array", "items": { "type": "string" } }
+                            },
+                            "required": ["id"]
+                        }
+                    },
+                    "allOf": [ { "$ref": "#/definitions/message" } ]
+                }), Some("message.json".to_owned()))).unwrap();
+                Chimpsky::from(set)
+            })
+        }
+
+        fn object() -> &'static str {
+            "message"
+        }
+    }
+
+    #[test]
+    fn payloads_satisfy_the_schema() {
+        fn conforms(payload: Payload<Message>) -> bool {
+            let verifier = Verifier::new(&Message::chimpsky().schema_set().documents, "message.json#/definitions/message").unwrap();
+            verifier.verify(&payload.value).is_empty()
+        }
+        QuickCheck::new().tests(20).quickcheck(conforms as fn(Payload<Message>) -> bool);
+    }
+
+    #[test]
+    fn shrinking_keeps_required_fields() {
+        fn no_tags(payload: Payload<Message>) -> TestResult {
+            TestResult::from_bool(payload.value.get("tags").is_none_or(|tags| tags.as_array().unwrap().is_empty()))
+        }
+
+        let result = std::panic::catch_unwind(|| {
+            QuickCheck::new().tests(50).quickcheck(no_tags as fn(Payload<Message>) -> TestResult)
+        });
+        let message = *result.unwrap_err().downcast::<String>().unwrap();
+        assert!(message.contains("\"tags\":[\"\"]"), "{}", message);
+        assert!(!message.contains("\"name\""), "{}", message);
+    }
+
+    #[test]
+    fn shrink_candidates_are_payloads() {
+        let payload = Payload::<Message> { value: json!({ "id": "3b241101-e2bb-4255-8caf-4136c566a962", "name": "ab" }), source: PhantomData };
+        let shrunk = payload.shrink().map(Payload::into_inner).collect::<Vec<_>>();
+        assert_eq!(shrunk[0], json!({ "id": "3b241101-e2bb-4255-8caf-4136c566a962" }));
+        assert!(shrunk.iter().all(|v| v["id"] == payload.value["id"]));
+    }
+}
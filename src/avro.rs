@@ -0,0 +1,527 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use apache_avro::types::Value as AvroValue;
+use chrono::DateTime;
+use serde_json::{json, Value};
+
+use crate::encoders;
+use crate::field_kinds::FieldKind;
+use crate::object_definitions::ObjectDefinition;
+use crate::output::{Framing, OutputFormat, OutputOptions, PayloadSink};
+
+const FREE_FORM_VALUES: &[&str] = &["null", "boolean", "long", "double", "string"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum AvroType {
+    Null,
+    Boolean,
+    Long,
+    Double,
+    String,
+    Uuid,
+    TimestampMillis,
+    /// Objects without properties become maps of scalar values.
+    FreeForm,
+    Array(Box<AvroType>),
+    Union(Vec<AvroType>),
+    /// A named record, keyed by the reference map key of its definition.
+    Record(String),
+}
+
+#[derive(Debug)]
+struct AvroField {
+    name: String,
+    source: String,
+    kind: AvroType,
+    optional: bool,
+}
+
+#[derive(Debug)]
+struct AvroRecord {
+    name: String,
+    fields: Vec<AvroField>,
+}
+
+/// Avro view of object definitions. Every definition reachable from the exported objects
+/// becomes a named record, nullable kinds and optional fields become unions with `null`, and
+/// formats map to logical types.
+pub struct AvroSchema {
+    roots: Vec<String>,
+    records: BTreeMap<String, AvroRecord>,
+    schema: apache_avro::Schema,
+}
+
+impl AvroSchema {
+    pub fn new(keys: &[&str], reference_map: &BTreeMap<String, ObjectDefinition>) -> Result<Self, String> {
+        let mut builder = Builder { reference_map, records: BTreeMap::new(), names: BTreeSet::new() };
+        for key in keys {
+            builder.record(key)?;
+        }
+
+        let mut avro = AvroSchema {
+            roots: keys.iter().map(|k| k.to_string()).collect(),
+            records: builder.records,
+            schema: apache_avro::Schema::Null,
+        };
+
+        let json = avro.to_json();
+        avro.schema = apache_avro::Schema::parse(&json).map_err(|e| format!("invalid avro schema: {}", e))?;
+        Ok(avro)
+    }
+
+    /// The schema as Avro JSON: a single record, or a union of records when several objects are
+    /// exported together. Records are written in full the first time they appear and by name after.
+    pub fn to_json(&self) -> Value {
+        let mut emitted = BTreeSet::new();
+        let mut roots = self.roots.iter()
+            .map(|key| self.type_json(&AvroType::Record(key.to_owned()), &mut emitted))
+            .collect::<Vec<_>>();
+
+        if roots.len() == 1 {
+            roots.remove(0)
+        } else {
+            Value::Array(roots)
+        }
+    }
+
+    /// Converts a generated payload of the first exported object into an Avro value.
+    pub fn to_avro_value(&self, payload: &Value) -> Result<AvroValue, String> {
+        self.value(&AvroType::Record(self.roots[0].to_owned()), payload)
+    }
+
+    pub fn encode_datum(&self, payload: &Value) -> Result<Vec<u8>, String> {
+        let value = self.to_avro_value(payload)?;
+        apache_avro::to_avro_datum(&self.schema, value).map_err(|e| format!("cannot encode payload as avro: {}", e))
+    }
+
+    fn type_json(&self, kind: &AvroType, emitted: &mut BTreeSet<String>) -> Value {
+        match kind {
+            AvroType::Null => json!("null"),
+            AvroType::Boolean => json!("boolean"),
+            AvroType::Long => json!("long"),
+            AvroType::Double => json!("double"),
+            AvroType::String => json!("string"),
+            AvroType::Uuid => json!({ "type": "string", "logicalType": "uuid" }),
+            AvroType::TimestampMillis => json!({ "type": "long", "logicalType": "timestamp-millis" }),
+            AvroType::FreeForm => json!({ "type": "map", "values": FREE_FORM_VALUES }),
+            AvroType::Array(items) => json!({ "type": "array", "items": self.type_json(items, emitted) }),
+            AvroType::Union(kinds) => Value::Array(kinds.iter().map(|k| self.type_json(k, emitted)).collect()),
+            AvroType::Record(key) => {
+                let record = &self.records[key];
+                if !emitted.insert(key.to_owned()) {
+                    return json!(record.name);
+                }
+
+                let fields = record.fields.iter()
+                    .map(|f| {
+                        let mut field = json!({ "name": f.name, "type": self.type_json(&f.kind, emitted) });
+                        if f.optional {
+                            field["default"] = Value::Null;
+                        }
+                        if f.name != f.source {
+                            field["aliases"] = json!([f.source]);
+                        }
+                        field
+                    })
+                    .collect::<Vec<_>>();
+
+                json!({ "type": "record", "name": record.name, "doc": key, "fields": fields })
+            }
+        }
+    }
+
+    fn value(&self, kind: &AvroType, v: &Value) -> Result<AvroValue, String> {
+        let mismatch = || format!("value {} does not fit avro type {:?}", v, kind);
+
+        match kind {
+            AvroType::Null if v.is_null() => Ok(AvroValue::Null),
+            AvroType::Boolean => v.as_bool().map(AvroValue::Boolean).ok_or_else(mismatch),
+            AvroType::Long => v.as_i64().map(AvroValue::Long).ok_or_else(mismatch),
+            AvroType::Double => v.as_f64().map(AvroValue::Double).ok_or_else(mismatch),
+            AvroType::String | AvroType::Uuid => v.as_str().map(|s| AvroValue::String(s.to_owned())).ok_or_else(mismatch),
+            AvroType::TimestampMillis => v.as_str()
+                .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+                .map(|t| AvroValue::TimestampMillis(t.timestamp_millis()))
+                .ok_or_else(mismatch),
+            AvroType::FreeForm => v.as_object()
+                .map(|m| m.iter().map(|(k, v)| (k.to_owned(), free_form_value(v))).collect::<HashMap<_, _>>())
+                .map(AvroValue::Map)
+                .ok_or_else(mismatch),
+            AvroType::Array(items) => v.as_array()
+                .ok_or_else(mismatch)?
+                .iter()
+                .map(|item| self.value(items, item))
+                .collect::<Result<Vec<_>, _>>()
+                .map(AvroValue::Array),
+            AvroType::Union(kinds) => kinds.iter()
+                .enumerate()
+                .find_map(|(index, k)| self.value(k, v).ok().map(|value| AvroValue::Union(index as u32, Box::new(value))))
+                .ok_or_else(mismatch),
+            AvroType::Record(key) => {
+                let object = v.as_object().ok_or_else(mismatch)?;
+                self.records[key].fields.iter()
+                    .map(|f| {
+                        let field = object.get(&f.source).unwrap_or(&Value::Null);
+                        self.value(&f.kind, field).map(|value| (f.name.to_owned(), value))
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(AvroValue::Record)
+            }
+            AvroType::Null => Err(mismatch()),
+        }
+    }
+}
+
+struct Builder<'a> {
+    reference_map: &'a BTreeMap<String, ObjectDefinition>,
+    records: BTreeMap<String, AvroRecord>,
+    names: BTreeSet<String>,
+}
+
+impl Builder<'_> {
+    fn record(&mut self, key: &str) -> Result<(), String> {
+        if self.records.contains_key(key) {
+            return Ok(());
+        }
+
+        let definition = self.reference_map.get(key).ok_or_else(|| format!("cannot resolve {}", key))?;
+        let name = self.unique_name(&definition.name);
+
+        // registered before the fields so recursive references find the record
+        self.records.insert(key.to_owned(), AvroRecord { name, fields: vec![] });
+        let fields = self.fields(definition, definition)?;
+        self.records.get_mut(key).unwrap().fields = fields;
+        Ok(())
+    }
+
+    fn fields(&mut self, definition: &ObjectDefinition, including: &ObjectDefinition) -> Result<Vec<AvroField>, String> {
+        let mut fields: Vec<AvroField> = vec![];
+
+        for r in definition.references.iter().flatten() {
+            let referenced = self.reference_map.get(r).ok_or_else(|| format!("cannot resolve {}", r))?;
+            for field in self.fields(referenced, including)? {
+                if !fields.iter().any(|f| f.source == field.source) {
+                    fields.push(field);
+                }
+            }
+        }
+
+        for field in definition.field_definitions.iter().flatten() {
+            if fields.iter().any(|f| f.source == field.name) {
+                continue;
+            }
+
+            let kind = if field.format.as_deref() == Some("date-time") {
+                AvroType::TimestampMillis
+            } else if field.format.as_deref() == Some("uuid") {
+                AvroType::Uuid
+            } else if field.format.is_some() || field.pattern.is_some() {
+                AvroType::String
+            } else {
+                match &field.kind {
+                    None => AvroType::Null,
+                    Some(kind) => self.kind(kind)?,
+                }
+            };
+
+            let optional = !definition.is_required(&field.name) && !including.is_required(&field.name);
+            fields.push(AvroField {
+                name: sanitize(&field.name),
+                source: field.name.to_owned(),
+                kind: if optional { nullable(kind) } else { kind },
+                optional,
+            });
+        }
+
+        Ok(fields)
+    }
+
+    fn kind(&mut self, kind: &FieldKind) -> Result<AvroType, String> {
+        Ok(match kind {
+            FieldKind::Str | FieldKind::Unknown(_) => AvroType::String,
+            FieldKind::Int => AvroType::Long,
+            FieldKind::Float => AvroType::Double,
+            FieldKind::Bool => AvroType::Boolean,
+            FieldKind::Object => AvroType::FreeForm,
+            FieldKind::Null => AvroType::Null,
+            FieldKind::OneOf(kinds) => union(kinds.iter().map(|k| self.kind(k)).collect::<Result<_, _>>()?),
+            FieldKind::ListOf(kinds) =>
+                AvroType::Array(Box::new(union(kinds.iter().map(|k| self.kind(k)).collect::<Result<_, _>>()?))),
+            FieldKind::Reference(r) => {
+                self.record(r)?;
+                AvroType::Record(r.to_owned())
+            }
+        })
+    }
+
+    fn unique_name(&mut self, name: &str) -> String {
+        let base = sanitize(name);
+        let mut candidate = base.clone();
+        let mut n = 2;
+        while !self.names.insert(candidate.clone()) {
+            candidate = format!("{}_{}", base, n);
+            n += 1;
+        }
+        candidate
+    }
+}
+
+/// A union of the given kinds with nested unions flattened and duplicates dropped, or the kind
+/// itself when only one is left. `null` always comes first so it can be the default.
+fn union(kinds: Vec<AvroType>) -> AvroType {
+    let mut flat: Vec<AvroType> = vec![];
+    for kind in kinds {
+        let members = match kind {
+            AvroType::Union(members) => members,
+            kind => vec![kind],
+        };
+        for member in members {
+            if !flat.contains(&member) {
+                flat.push(member);
+            }
+        }
+    }
+
+    flat.sort_by_key(|k| *k != AvroType::Null);
+    match flat.len() {
+        0 => AvroType::Null,
+        1 => flat.remove(0),
+        _ => AvroType::Union(flat),
+    }
+}
+
+fn nullable(kind: AvroType) -> AvroType {
+    union(vec![AvroType::Null, kind])
+}
+
+fn free_form_value(v: &Value) -> AvroValue {
+    let (index, value) = match v {
+        Value::Null => (0, AvroValue::Null),
+        Value::Bool(b) => (1, AvroValue::Boolean(*b)),
+        Value::Number(n) if n.is_i64() || n.is_u64() => (2, AvroValue::Long(n.as_i64().unwrap_or(i64::MAX))),
+        Value::Number(n) => (3, AvroValue::Double(n.as_f64().unwrap())),
+        Value::String(s) => (4, AvroValue::String(s.to_owned())),
+        v => (4, AvroValue::String(v.to_string())),
+    };
+    AvroValue::Union(index, Box::new(value))
+}
+
+/// Avro names start with a letter or underscore and continue with letters, digits or underscores.
+pub fn sanitize(name: &str) -> String {
+    let mut s = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    if !s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        s.insert(0, '_');
+    }
+    s
+}
+
+/// Payloads a container file holds in one block; each block is written by a short-lived
+/// `apache_avro::Writer` borrowing the schema, so the schema lives as long as the `AvroWriter`.
+const CONTAINER_BLOCK: usize = 1000;
+
+enum AvroOutput {
+    Datum { framing: Framing },
+    Container { marker: [u8; 16], header_written: bool, block: Vec<AvroValue> },
+}
+
+/// Writes payloads as raw Avro datums or as an Avro object container file.
+pub struct AvroWriter {
+    schema: AvroSchema,
+    out: Box<dyn Write>,
+    output: AvroOutput,
+    files: Vec<PathBuf>,
+}
+
+impl AvroWriter {
+    pub fn new(format: OutputFormat,
+               options: &OutputOptions,
+               key: &str,
+               reference_map: &BTreeMap<String, ObjectDefinition>) -> Result<Self, String> {
+        if options.rotate_count.is_some() || options.rotate_size.is_some() {
+            return Err("rotation is not supported for avro output".to_owned());
+        }
+
+        let schema = AvroSchema::new(&[key], reference_map)?;
+
+        let output = match format {
+            OutputFormat::Avro => AvroOutput::Datum { framing: options.framing },
+            OutputFormat::AvroContainer if options.framing != Framing::None =>
+                return Err("avro container files carry their own framing".to_owned()),
+            OutputFormat::AvroContainer =>
+                AvroOutput::Container { marker: rand::random(), header_written: false, block: vec![] },
+            _ => return Err(format!("{:?} is not an avro output format", format)),
+        };
+
+        let (out, files): (Box<dyn Write>, _) = match &options.output {
+            Some(path) if path.is_dir() => return Err("avro output must be written to a file".to_owned()),
+            Some(path) => {
+                let file = File::create(path).map_err(|e| format!("cannot create {}: {}", path.display(), e))?;
+                (Box::new(BufWriter::new(file)), vec![path.to_owned()])
+            }
+            None => (Box::new(BufWriter::new(io::stdout())), vec![]),
+        };
+
+        Ok(AvroWriter { schema, out, output, files })
+    }
+
+    /// Writes the pending container block, preceded by the header when nothing was written yet.
+    fn write_block(&mut self) -> io::Result<()> {
+        if let AvroOutput::Container { marker, header_written, block } = &mut self.output {
+            let mut writer = apache_avro::Writer::builder()
+                .schema(&self.schema.schema)
+                .writer(&mut self.out)
+                .marker(*marker)
+                .has_header(*header_written)
+                .build();
+            writer.extend(block.drain(..)).map_err(|e| io::Error::other(e.to_string()))?;
+            writer.into_inner().map_err(|e| io::Error::other(e.to_string()))?;
+            *header_written = true;
+        }
+        Ok(())
+    }
+}
+
+impl PayloadSink for AvroWriter {
+    fn write(&mut self, payload: &Value) -> io::Result<()> {
+        let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+
+        match &mut self.output {
+            AvroOutput::Datum { framing } => {
+                let bytes = self.schema.encode_datum(payload).map_err(invalid)?;
+                match framing {
+                    Framing::None => self.out.write_all(&bytes),
+                    Framing::LengthPrefixed => self.out.write_all(&encoders::length_prefixed(bytes)),
+                }
+            }
+            AvroOutput::Container { block, .. } => {
+                block.push(self.schema.to_avro_value(payload).map_err(invalid)?);
+                if block.len() >= CONTAINER_BLOCK {
+                    self.write_block()?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<Vec<PathBuf>> {
+        match &self.output {
+            AvroOutput::Container { header_written: false, .. } => self.write_block()?,
+            AvroOutput::Container { block, .. } if !block.is_empty() => self.write_block()?,
+            _ => {}
+        }
+        self.out.flush()?;
+        Ok(self.files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use structopt::StructOpt;
+
+    use crate::object_definitions::parse_definitions;
+
+    use super::*;
+
+    fn reference_map() -> BTreeMap<String, ObjectDefinition> {
+        parse_definitions(&json!({
+            "carried": {
+                "type": "object",
+                "properties": { "version": { "type": "number" }, "parent": { "$ref": "message" } },
+                "required": ["version"]
+            },
+            "message": {
+                "type": "object",
+                "properties": {
+                    "id": { "type": "string", "format": "uuid" },
+                    "at": { "type": "string", "format": "date-time" },
+                    "category-of": { "type": ["string", "null"] },
+                    "carried": { "$ref": "carried" },
+                    "counts": { "type": "array", "items": { "type": "integer" } },
+                    "data": { "type": "object" }
+                },
+                "required": ["id", "at", "category-of", "carried", "counts", "data"]
+            }
+        }))
+    }
+
+    fn payload() -> Value {
+        json!({
+            "id": "550e8400-e29b-41d4-a716-446655440000",
+            "at": "2020-01-01T00:00:01+00:00",
+            "category-of": null,
+            "carried": { "version": 1.0, "parent": null },
+            "counts": [1, 2],
+            "data": { "a": 1, "b": "x", "c": { "nested": true } }
+        })
+    }
+
+    #[test]
+    fn schema_maps_kinds_to_avro_types() {
+        let m = reference_map();
+        let schema = AvroSchema::new(&["message"], &m).unwrap().to_json();
+
+        assert_eq!(schema["name"], "message");
+        let fields = schema["fields"].as_array().unwrap();
+        assert_eq!(fields[0]["type"], json!({ "type": "string", "logicalType": "uuid" }));
+        assert_eq!(fields[1]["type"], json!({ "type": "long", "logicalType": "timestamp-millis" }));
+        assert_eq!(fields[2]["name"], "category_of");
+        assert_eq!(fields[2]["aliases"], json!(["category-of"]));
+        assert_eq!(fields[2]["type"], json!(["null", "string"]));
+        assert_eq!(fields[3]["type"]["type"], "record");
+        assert_eq!(fields[3]["type"]["fields"][1]["type"], json!(["null", "message"]));
+        assert_eq!(fields[3]["type"]["fields"][1]["default"], Value::Null);
+        assert_eq!(fields[4]["type"], json!({ "type": "array", "items": "long" }));
+    }
+
+    #[test]
+    fn payloads_round_trip_through_avro_datums() {
+        let m = reference_map();
+        let avro = AvroSchema::new(&["message"], &m).unwrap();
+        let bytes = avro.encode_datum(&payload()).unwrap();
+
+        let decoded = apache_avro::from_avro_datum(&avro.schema, &mut bytes.as_slice(), None).unwrap();
+        match decoded {
+            AvroValue::Record(fields) => {
+                assert_eq!(fields[1].1, AvroValue::TimestampMillis(1577836801000));
+                assert_eq!(fields[2].1, AvroValue::Union(0, Box::new(AvroValue::Null)));
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn container_files_can_be_read_back() {
+        let m = reference_map();
+        let path = std::env::temp_dir().join(format!("chimpsky-{}.avro", uuid::Uuid::new_v4()));
+        let mut options = OutputOptions::from_iter(&["randomize"]);
+        options.output = Some(path.clone());
+
+        let mut writer: Box<dyn PayloadSink> = Box::new(AvroWriter::new(OutputFormat::AvroContainer, &options, "message", &m).unwrap());
+        for _ in 0..CONTAINER_BLOCK + 2 {
+            writer.write(&payload()).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let reader = apache_avro::Reader::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.collect::<Result<Vec<_>, _>>().unwrap().len(), CONTAINER_BLOCK + 2);
+
+        let writer: Box<dyn PayloadSink> = Box::new(AvroWriter::new(OutputFormat::AvroContainer, &options, "message", &m).unwrap());
+        writer.finish().unwrap();
+        let reader = apache_avro::Reader::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.count(), 0);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn names_are_sanitized() {
+        assert_eq!(sanitize("bigmessage.schema"), "bigmessage_schema");
+        assert_eq!(sanitize("3d-point"), "_3d_point");
+    }
+}
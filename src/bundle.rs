@@ -0,0 +1,257 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde_json::{json, Map, Value};
+
+use crate::schema_parser::{qualify_reference, SchemaSet};
+
+/// Keywords that would change how references inside an inlined schema resolve.
+const DROPPED_KEYWORDS: &[&str] = &["$id", "$schema"];
+
+/// Builds a single schema document for one object, with every schema it refers to copied under
+/// `$defs` and references rewritten to point there.
+pub struct Bundler<'a> {
+    schema_set: &'a SchemaSet,
+    root: String,
+    names: BTreeMap<String, String>,
+    taken: BTreeSet<String>,
+    defs: Map<String, Value>,
+}
+
+impl<'a> Bundler<'a> {
+    pub fn new(schema_set: &'a SchemaSet, key: &str) -> Self {
+        Bundler {
+            schema_set,
+            root: key.to_owned(),
+            names: BTreeMap::new(),
+            taken: BTreeSet::new(),
+            defs: Map::new(),
+        }
+    }
+
+    pub fn bundle(mut self) -> Result<Value, String> {
+        let mut root = self.node(&self.root.to_owned())?;
+        self.rewrite(&mut root, &self.root.to_owned())?;
+
+        let object = root.as_object_mut().unwrap();
+        if !self.defs.is_empty() {
+            object.insert("$defs".to_owned(), Value::Object(std::mem::take(&mut self.defs)));
+        }
+        self.with_schema_keyword(root)
+    }
+
+    /// Replaces every reference with the schema it points to. Fails when a schema refers to
+    /// itself, directly or through others, since it cannot be written out without references.
+    pub fn dereference(self) -> Result<Value, String> {
+        let root = self.node(&self.root)?;
+        let mut stack = vec![self.root.to_owned()];
+        let inlined = self.inline(&root, &self.root, &mut stack)?;
+        self.with_schema_keyword(inlined)
+    }
+
+    fn with_schema_keyword(&self, mut root: Value) -> Result<Value, String> {
+        let document = self.schema_set.documents.get(file_of(&self.root));
+        if let Some(schema) = document.and_then(|d| d.get("$schema")) {
+            let mut object = Map::new();
+            object.insert("$schema".to_owned(), schema.clone());
+            object.extend(root.as_object_mut().unwrap().clone());
+            root = Value::Object(object);
+        }
+        Ok(root)
+    }
+
+    fn node(&self, key: &str) -> Result<Value, String> {
+        let mut node = self.schema_set.definition_node(key)
+            .filter(|node| node.is_object())
+            .ok_or_else(|| format!("cannot resolve {}", key))?
+            .clone();
+
+        let object = node.as_object_mut().unwrap();
+        for keyword in DROPPED_KEYWORDS {
+            object.remove(*keyword);
+        }
+        Ok(node)
+    }
+
+    fn rewrite(&mut self, node: &mut Value, key: &str) -> Result<(), String> {
+        match node {
+            Value::Object(object) => {
+                for (k, v) in object.iter_mut() {
+                    match v {
+                        Value::String(reference) if k == "$ref" => {
+                            let target = qualify_reference(file_of(key), reference);
+                            *reference = self.local_reference(&target)?;
+                        }
+                        v => self.rewrite(v, key)?,
+                    }
+                }
+            }
+            Value::Array(items) => {
+                for item in items {
+                    self.rewrite(item, key)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn local_reference(&mut self, target: &str) -> Result<String, String> {
+        if target == self.root {
+            return Ok("#".to_owned());
+        }
+
+        if let Some(name) = self.names.get(target) {
+            return Ok(format!("#/$defs/{}", name));
+        }
+
+        let base = short_name(target);
+        let mut name = base.to_owned();
+        let mut n = 2;
+        while !self.taken.insert(name.clone()) {
+            name = format!("{}_{}", base, n);
+            n += 1;
+        }
+
+        // named before rewriting so references back to it resolve
+        self.names.insert(target.to_owned(), name.clone());
+        let mut node = self.node(target)?;
+        self.rewrite(&mut node, target)?;
+        self.defs.insert(name.clone(), node);
+        Ok(format!("#/$defs/{}", name))
+    }
+
+    fn inline(&self, node: &Value, key: &str, stack: &mut Vec<String>) -> Result<Value, String> {
+        match node {
+            Value::Object(object) => {
+                if let Some(reference) = object.get("$ref").and_then(|r| r.as_str()) {
+                    let target = qualify_reference(file_of(key), reference);
+                    if stack.contains(&target) {
+                        return Err(format!("cannot dereference {}, {} refers to itself through {}",
+                                           self.root, target, stack.join(" -> ")));
+                    }
+
+                    stack.push(target.to_owned());
+                    let inlined = self.inline(&self.node(&target)?, &target, stack)?;
+                    stack.pop();
+
+                    if object.len() == 1 {
+                        return Ok(inlined);
+                    }
+
+                    // keywords next to a reference still apply, so both are kept
+                    let mut siblings = Map::new();
+                    for (k, v) in object.iter().filter(|(k, _)| *k != "$ref") {
+                        siblings.insert(k.to_owned(), self.inline(v, key, stack)?);
+                    }
+                    let all_of = siblings.entry("allOf").or_insert_with(|| json!([]));
+                    if let Some(all_of) = all_of.as_array_mut() {
+                        all_of.insert(0, inlined);
+                    }
+                    return Ok(Value::Object(siblings));
+                }
+
+                let mut inlined = Map::new();
+                for (k, v) in object {
+                    inlined.insert(k.to_owned(), self.inline(v, key, stack)?);
+                }
+                Ok(Value::Object(inlined))
+            }
+            Value::Array(items) => items.iter()
+                .map(|item| self.inline(item, key, stack))
+                .collect::<Result<Vec<_>, _>>()
+                .map(Value::Array),
+            v => Ok(v.clone()),
+        }
+    }
+}
+
+fn file_of(key: &str) -> &str {
+    key.split('#').next().unwrap()
+}
+
+/// `$defs` names are the last segment of the reference, or the file name for whole documents.
+fn short_name(key: &str) -> &str {
+    match key.split_once('#') {
+        Some((_, fragment)) if !fragment.trim_matches('/').is_empty() => fragment.rsplit('/').next().unwrap(),
+        _ => file_of(key).trim_end_matches(".json"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonschema::Draft;
+
+    use crate::schema_parser::Schema;
+
+    use super::*;
+
+    fn schema_set(recursive: bool) -> SchemaSet {
+        let parent = if recursive { json!({ "$ref": "#/definitions/message" }) } else { json!({ "type": "null" }) };
+        let mut set = SchemaSet::default();
+        set.add(Schema::new(&json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "definitions": {
+                "message": {
+                    "type": "object",
+                    "allOf": [ { "$ref": "#/definitions/base" } ],
+                    "properties": {
+                        "carried": { "$ref": "carried.json#/definitions/carried" },
+                        "items": { "type": "array", "items": { "$ref": "carried.json#/definitions/carried" } },
+                        "parent": parent
+                    },
+                    "required": ["carried"]
+                },
+                "base": { "type": "object", "properties": { "version": { "type": "number" } }, "required": ["version"] }
+            },
+            "allOf": [ { "$ref": "#/definitions/message" } ]
+        }), Some("message.json".to_owned()))).unwrap();
+        set.add(Schema::new(&json!({
+            "$id": "carried.json",
+            "type": "object",
+            "properties": { "id": { "type": "string", "format": "uuid" } },
+            "required": ["id"]
+        }), Some("carried.json".to_owned()))).unwrap();
+        set
+    }
+
+    #[test]
+    fn references_are_rewritten_locally() {
+        let set = schema_set(true);
+        let bundled = Bundler::new(&set, "message.json#/definitions/message").bundle().unwrap();
+
+        assert_eq!(bundled["$schema"], "http://json-schema.org/draft-07/schema#");
+        assert_eq!(bundled["allOf"][0]["$ref"], "#/$defs/base");
+        assert_eq!(bundled["properties"]["carried"]["$ref"], "#/$defs/carried");
+        assert_eq!(bundled["properties"]["items"]["items"]["$ref"], "#/$defs/carried");
+        assert_eq!(bundled["properties"]["parent"]["$ref"], "#");
+
+        let defs = bundled["$defs"].as_object().unwrap();
+        assert_eq!(defs.keys().collect::<Vec<_>>(), vec!["base", "carried"]);
+        assert!(defs["carried"].get("$id").is_none());
+    }
+
+    #[test]
+    fn bundles_validate_generated_payloads() {
+        let set = schema_set(false);
+        let key = "message.json#/definitions/message";
+        let bundled = Bundler::new(&set, key).bundle().unwrap();
+        let validator = jsonschema::options().with_draft(Draft::Draft7).should_validate_formats(true).build(&bundled).unwrap();
+
+        let payload = set.reference_map[key].generate_json(Some(&set.reference_map)).unwrap();
+        assert!(validator.is_valid(&payload));
+        assert!(!validator.is_valid(&json!({ "version": 1 })));
+    }
+
+    #[test]
+    fn dereferencing() {
+        let set = schema_set(false);
+        let inlined = Bundler::new(&set, "message.json#/definitions/message").dereference().unwrap();
+        assert_eq!(inlined["allOf"][0]["properties"]["version"]["type"], "number");
+        assert_eq!(inlined["properties"]["items"]["items"]["required"], json!(["id"]));
+        assert!(!inlined.to_string().contains("$ref"));
+
+        let set = schema_set(true);
+        let e = Bundler::new(&set, "message.json#/definitions/message").dereference().unwrap_err();
+        assert!(e.contains("refers to itself"));
+    }
+}
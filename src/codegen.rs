@@ -0,0 +1,178 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+use crate::object_definitions::ObjectDefinition;
+use crate::rust_codegen::RustModule;
+use crate::typescript_codegen::TypeScriptModules;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CodegenLanguage {
+    Rust,
+    TypeScript,
+}
+
+impl FromStr for CodegenLanguage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rust" => Ok(CodegenLanguage::Rust),
+            "typescript" => Ok(CodegenLanguage::TypeScript),
+            _ => Err(format!("unknown codegen language {}, expected rust or typescript", s)),
+        }
+    }
+}
+
+/// Options only the Rust generator uses.
+pub struct CodegenOptions {
+    /// Emit tests that round-trip generated payloads through the generated types.
+    pub tests: bool,
+    /// Number of generated payloads embedded in each round-trip test.
+    pub test_payloads: u64,
+}
+
+/// Generates source files with types mirroring the objects with the given keys and every object
+/// they refer to, as file names with their contents. Rust types go into a single module while
+/// TypeScript gets one module per schema file.
+pub fn generate(language: CodegenLanguage,
+                keys: &[&str],
+                reference_map: &BTreeMap<String, ObjectDefinition>,
+                options: &CodegenOptions) -> Result<Vec<(String, String)>, String> {
+    match language {
+        CodegenLanguage::Rust => Ok(vec![("chimpsky.rs".to_owned(), RustModule::new(keys, reference_map)?.render(options))]),
+        CodegenLanguage::TypeScript => TypeScriptModules::new(keys, reference_map)?.render(),
+    }
+}
+
+/// Keys of the given objects followed by every object they refer to, directly or transitively,
+/// in the order they are first referred to.
+pub fn reachable_keys(keys: &[&str], reference_map: &BTreeMap<String, ObjectDefinition>) -> Result<Vec<String>, String> {
+    let mut pending = keys.iter().map(|k| k.to_string()).collect::<Vec<_>>();
+
+    let mut index = 0;
+    while index < pending.len() {
+        let key = &pending[index];
+        let definition = reference_map.get(key).ok_or_else(|| format!("cannot resolve {}", key))?;
+
+        let mut targets = definition.references.iter().flatten().map(|r| r.as_str()).collect::<Vec<_>>();
+        for field in definition.field_definitions.iter().flatten() {
+            targets.extend(field.kind.iter().flat_map(|k| k.references()));
+        }
+
+        for target in targets {
+            if !pending.iter().any(|p| p == target) {
+                pending.push(target.to_owned());
+            }
+        }
+        index += 1;
+    }
+
+    Ok(pending)
+}
+
+/// Whether the object with key `from` refers to the object with key `to`, directly or transitively.
+pub fn reaches(from: &str, to: &str, reference_map: &BTreeMap<String, ObjectDefinition>) -> bool {
+    let mut seen = BTreeSet::new();
+    let mut stack = vec![from];
+
+    while let Some(key) = stack.pop() {
+        let definition = match reference_map.get(key) {
+            Some(definition) if seen.insert(key) => definition,
+            _ => continue,
+        };
+
+        for field in definition.field_definitions.iter().flatten() {
+            for target in field.kind.iter().flat_map(|k| k.references()) {
+                if target == to {
+                    return true;
+                }
+                stack.push(target);
+            }
+        }
+        stack.extend(definition.references.iter().flatten().map(|r| r.as_str()));
+    }
+
+    false
+}
+
+/// Unique PascalCase type names for a set of objects, suffixed with a number when two object
+/// names collapse to the same identifier.
+pub fn type_names(keys: &[String], reference_map: &BTreeMap<String, ObjectDefinition>) -> BTreeMap<String, String> {
+    let mut taken = BTreeSet::new();
+    keys.iter()
+        .map(|key| {
+            let base = pascal_case(&reference_map[key].name);
+            let mut candidate = base.clone();
+            let mut n = 2;
+            while !taken.insert(candidate.clone()) {
+                candidate = format!("{}{}", base, n);
+                n += 1;
+            }
+            (key.to_owned(), candidate)
+        })
+        .collect()
+}
+
+/// `bigmessage.schema` becomes `BigmessageSchema`, `a_carried_object` becomes `ACarriedObject`.
+pub fn pascal_case(name: &str) -> String {
+    let mut s = name.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(|part| part[..1].to_ascii_uppercase() + &part[1..])
+        .collect::<String>();
+    if !s.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        s.insert(0, 'T');
+    }
+    s
+}
+
+/// `someDate-of` becomes `some_date_of`; names starting with a digit get an `f` prefix.
+pub fn snake_case(name: &str) -> String {
+    let mut s = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 && !s.ends_with('_') {
+                s.push('_');
+            }
+            s.push(c.to_ascii_lowercase());
+        } else if c.is_ascii_alphanumeric() {
+            s.push(c);
+        } else if !s.ends_with('_') {
+            s.push('_');
+        }
+    }
+    if !s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        s.insert(0, 'f');
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::object_definitions::parse_definitions;
+
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!(pascal_case("bigmessage.schema"), "BigmessageSchema");
+        assert_eq!(pascal_case("a_carried_object"), "ACarriedObject");
+        assert_eq!(pascal_case("3d"), "T3d");
+        assert_eq!(snake_case("someDate-of"), "some_date_of");
+        assert_eq!(snake_case("3d"), "f3d");
+    }
+
+    #[test]
+    fn reachability() {
+        let m = parse_definitions(&json!({
+            "a": { "type": "object", "properties": { "b": { "$ref": "b" } } },
+            "b": { "type": "object", "properties": { "a": { "type": "array", "items": { "$ref": "a" } } } },
+            "c": { "type": "object", "allOf": [ { "$ref": "a" } ] }
+        }));
+
+        assert_eq!(reachable_keys(&["c"], &m).unwrap(), vec!["c", "a", "b"]);
+        assert!(reaches("b", "b", &m));
+        assert!(!reaches("a", "c", &m));
+    }
+}
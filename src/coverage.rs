@@ -0,0 +1,265 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+
+use rand::seq::SliceRandom;
+
+use crate::field_definitions::FieldDefinition;
+use crate::field_kinds::FieldKind;
+use crate::object_definitions::ObjectDefinition;
+use crate::random_values;
+
+thread_local! {
+    static ACTIVE: RefCell<Option<Coverage>> = const { RefCell::new(None) };
+}
+
+/// Choice points are identified by the address of what generation decides on, which stays put
+/// for as long as the reference map is borrowed: a `OneOf` or `ListOf` kind, or an optional field
+/// together with the object including it.
+type PointId = (usize, usize);
+
+#[derive(Debug)]
+pub struct Point {
+    pub label: String,
+    pub branches: Vec<String>,
+    pub hits: Vec<u64>,
+}
+
+impl Point {
+    pub fn is_covered(&self) -> bool {
+        self.hits.iter().all(|hits| *hits > 0)
+    }
+}
+
+/// Every choice generating an object can make: which alternative of a field with several types,
+/// whether an optional field is present and whether an array is empty. While installed with
+/// `with_coverage`, generation takes the branch produced the least so far at each point.
+#[derive(Debug, Default)]
+pub struct Coverage {
+    points: Vec<Point>,
+    index: BTreeMap<PointId, usize>,
+}
+
+impl Coverage {
+    pub fn new(key: &str, reference_map: &BTreeMap<String, ObjectDefinition>) -> Self {
+        let mut coverage = Coverage::default();
+        coverage.object(key, reference_map, &mut BTreeSet::new());
+        coverage
+    }
+
+    pub fn points(&self) -> &[Point] {
+        &self.points
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.points.iter().all(|p| p.is_covered())
+    }
+
+    /// Branches produced at least once, and all branches.
+    pub fn covered(&self) -> (usize, usize) {
+        let hit = self.points.iter().flat_map(|p| &p.hits).filter(|hits| **hits > 0).count();
+        let total = self.points.iter().map(|p| p.branches.len()).sum();
+        (hit, total)
+    }
+
+    pub fn render_text(&self) -> String {
+        let (hit, total) = self.covered();
+        let mut out = format!("coverage: {}/{} branches\n", hit, total);
+        for point in &self.points {
+            let branches = point.branches.iter()
+                .zip(&point.hits)
+                .map(|(branch, hits)| format!("{} {}", branch, hits))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let marker = if point.is_covered() { "" } else { " (uncovered)" };
+            out += &format!("    {}: {}{}\n", point.label, branches, marker);
+        }
+        out
+    }
+
+    fn object(&mut self, key: &str, reference_map: &BTreeMap<String, ObjectDefinition>, visited: &mut BTreeSet<String>) {
+        if !visited.insert(key.to_owned()) {
+            return;
+        }
+        let definition = match reference_map.get(key) {
+            Some(definition) => definition,
+            None => return,
+        };
+
+        for (field, optional) in definition.all_fields(reference_map).unwrap_or_default() {
+            let label = format!("{} field {}", key, field.name);
+            if optional {
+                self.add(optional_point(definition, field), &label, vec!["present".to_owned(), "absent".to_owned()]);
+            }
+
+            // these are generated without looking at the kind
+            let by_kind = field.const_value.is_none()
+                && field.enum_values.as_ref().is_none_or(|values| values.is_empty())
+                && field.format.is_none()
+                && field.pattern.is_none()
+                && field.sequence.is_none();
+            if let Some(kind) = field.kind.as_ref().filter(|_| by_kind) {
+                self.kind(kind, &label, reference_map, visited);
+            }
+        }
+    }
+
+    fn kind(&mut self, kind: &FieldKind, label: &str, reference_map: &BTreeMap<String, ObjectDefinition>, visited: &mut BTreeSet<String>) {
+        match kind {
+            FieldKind::OneOf(kinds) => {
+                self.add(kind_point(kind), label, kinds.iter().map(|k| k.to_string()).collect());
+                for k in kinds {
+                    self.kind(k, label, reference_map, visited);
+                }
+            }
+            FieldKind::ListOf(items) if !items.is_empty() => {
+                self.add(kind_point(kind), label, vec!["empty".to_owned(), "non-empty".to_owned()]);
+                for item in items {
+                    self.kind(item, &format!("{}[]", label), reference_map, visited);
+                }
+            }
+            FieldKind::Reference(r) => self.object(r, reference_map, visited),
+            _ => {}
+        }
+    }
+
+    fn add(&mut self, id: PointId, label: &str, branches: Vec<String>) {
+        if !self.index.contains_key(&id) {
+            self.index.insert(id, self.points.len());
+            let hits = vec![0; branches.len()];
+            self.points.push(Point { label: label.to_owned(), branches, hits });
+        }
+    }
+
+    fn steer(&mut self, id: PointId) -> Option<usize> {
+        let point = &mut self.points[*self.index.get(&id)?];
+        let least = *point.hits.iter().min()?;
+        let candidates = (0..point.hits.len()).filter(|i| point.hits[*i] == least).collect::<Vec<_>>();
+        let branch = *candidates.choose(&mut random_values::rng())?;
+        point.hits[branch] += 1;
+        Some(branch)
+    }
+}
+
+/// Steers the generation `f` does with `coverage` when it holds one, recording the branches taken.
+pub fn with_coverage<T>(coverage: &mut Option<Coverage>, f: impl FnOnce() -> T) -> T {
+    ACTIVE.with(|active| std::mem::swap(&mut *active.borrow_mut(), coverage));
+    let result = f();
+    ACTIVE.with(|active| std::mem::swap(&mut *active.borrow_mut(), coverage));
+    result
+}
+
+/// Branch to take for a `OneOf` or `ListOf` kind, `None` when generation is not being steered.
+pub fn steer_kind(kind: &FieldKind) -> Option<usize> {
+    steer(kind_point(kind))
+}
+
+/// Optional fields of `definition` to leave out of the object being generated.
+pub fn omitted_fields(definition: &ObjectDefinition, reference_map: Option<&BTreeMap<String, ObjectDefinition>>) -> Vec<String> {
+    let active = ACTIVE.with(|active| active.borrow().is_some());
+    let fields = match reference_map.filter(|_| active).map(|reference_map| definition.all_fields(reference_map)) {
+        Some(Ok(fields)) => fields,
+        _ => return vec![],
+    };
+
+    fields.into_iter()
+        .filter(|(field, optional)| *optional && steer(optional_point(definition, field)) == Some(1))
+        .map(|(field, _)| field.name.to_owned())
+        .collect()
+}
+
+fn steer(id: PointId) -> Option<usize> {
+    ACTIVE.with(|active| active.borrow_mut().as_mut().and_then(|coverage| coverage.steer(id)))
+}
+
+fn kind_point(kind: &FieldKind) -> PointId {
+    (kind as *const FieldKind as usize, 0)
+}
+
+fn optional_point(definition: &ObjectDefinition, field: &FieldDefinition) -> PointId {
+    (definition as *const ObjectDefinition as usize, field as *const FieldDefinition as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::schema_parser::{Schema, SchemaSet};
+    use crate::Chimpsky;
+
+    use super::*;
+
+    const KEY: &str = "message.json#/definitions/message";
+
+    fn chimpsky() -> Chimpsky {
+        let mut set = SchemaSet::default();
+        set.add(Schema::new(&json!({
+            "definitions": {
+                "message": {
+                    "type": "object",
+                    "allOf": [ { "$ref": "#/definitions/base" } ],
+                    "properties": {
+                        "state": { "type": ["string", "integer", "boolean", "null"] },
+                        "tags": { "type": "array", "items": { "type": ["string", "null"] } },
+                        "carried": { "$ref": "#/definitions/carried" },
+                        "fixed": { "type": ["string", "null"], "const": "x" }
+                    },
+                    "required": ["state", "tags", "fixed"]
+                },
+                "base": { "type": "object", "properties": { "version": { "type": "integer" } } },
+                "carried": { "type": "object", "properties": { "note": { "type": ["string", "null"] } }, "required": ["note"] }
+            },
+            "allOf": [ { "$ref": "#/definitions/message" } ]
+        }), Some("message.json".to_owned()))).unwrap();
+        Chimpsky::from(set)
+    }
+
+    #[test]
+    fn choice_points() {
+        let chimpsky = chimpsky();
+        let coverage = Coverage::new(KEY, chimpsky.reference_map());
+        let points = coverage.points().iter()
+            .map(|p| format!("{}: {}", p.label.trim_start_matches("message.json#/definitions/"), p.branches.join(", ")))
+            .collect::<Vec<_>>();
+        assert_eq!(points, vec![
+            "message field version: present, absent",
+            "message field state: string, integer, boolean, null",
+            "message field tags: empty, non-empty",
+            "message field tags[]: string, null",
+            "message field carried: present, absent",
+            "carried field note: string, null",
+        ]);
+        assert_eq!(coverage.covered(), (0, 14));
+    }
+
+    #[test]
+    fn steering_covers_every_branch() {
+        let chimpsky = chimpsky();
+        let mut generator = chimpsky.generator(KEY).unwrap().cover();
+        let payloads = (0..4).map(|_| generator.generate()).collect::<Vec<_>>();
+
+        let coverage = generator.coverage().unwrap();
+        assert!(coverage.is_complete(), "{}", coverage.render_text());
+        assert!(payloads.iter().any(|p| p.get("carried").is_none()));
+        assert!(payloads.iter().any(|p| p["tags"] == json!([])));
+        assert!(payloads.iter().any(|p| p["state"].is_boolean()));
+        assert!(payloads.iter().all(|p| p["fixed"] == "x"));
+    }
+
+    #[test]
+    fn report() {
+        let chimpsky = chimpsky();
+        let mut generator = chimpsky.generator(KEY).unwrap().cover();
+        generator.generate();
+
+        let report = generator.coverage().unwrap().render_text();
+        assert!(report.starts_with("coverage: "));
+        assert!(report.contains("    message.json#/definitions/message field state: "));
+        assert!(report.contains("(uncovered)\n"));
+    }
+
+    #[test]
+    fn unsteered_generation_is_untouched() {
+        let kind = FieldKind::OneOf(vec![FieldKind::Str, FieldKind::Null]);
+        assert_eq!(steer_kind(&kind), None);
+    }
+}
@@ -0,0 +1,374 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use crate::codegen;
+use crate::field_definitions::FieldDefinition;
+use crate::overrides::Overrides;
+use crate::random_values;
+use crate::{Chimpsky, Error, Generator};
+
+thread_local! {
+    static ACTIVE: RefCell<Option<Pools>> = const { RefCell::new(None) };
+}
+
+/// A field holding the value of a field of another generated object, like an order's
+/// `customer_id` holding the `id` of one of the customers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForeignKey {
+    pub object: String,
+    /// JSON pointer into payloads of `object`.
+    pub field: String,
+}
+
+impl ForeignKey {
+    /// Fields are JSON pointers, `id` being read as `/id`, which is also the default.
+    pub fn new(object: &str, field: Option<&str>) -> Self {
+        let field = field.unwrap_or("id");
+        let field = if field.starts_with('/') { field.to_owned() } else { format!("/{}", field) };
+        ForeignKey { object: object.to_owned(), field }
+    }
+
+    /// The `x-chimpsky-ref` keyword, the name of an object or `{ "object": ..., "field": ... }`.
+    pub fn of(field: &FieldDefinition) -> Result<Option<Self>, String> {
+        let keyword = match &field.foreign_key {
+            None => return Ok(None),
+            Some(keyword) => keyword,
+        };
+        let invalid = || "x-chimpsky-ref must be an object name or a table with object and field".to_owned();
+
+        match keyword {
+            Value::String(object) => Ok(Some(ForeignKey::new(object, None))),
+            Value::Object(spec) => {
+                if let Some(unknown) = spec.keys().find(|k| *k != "object" && *k != "field") {
+                    return Err(format!("unknown key {} in x-chimpsky-ref", unknown));
+                }
+                let object = spec.get("object").and_then(|o| o.as_str()).ok_or_else(invalid)?;
+                let field = match spec.get("field") {
+                    None => None,
+                    Some(field) => Some(field.as_str().ok_or_else(invalid)?),
+                };
+                Ok(Some(ForeignKey::new(object, field)))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Values of referenced fields in the payloads generated so far, by object key and pointer.
+#[derive(Debug, Default)]
+struct Pools {
+    /// Reference keys of the objects foreign keys name, which may be definition names.
+    keys: BTreeMap<String, String>,
+    values: BTreeMap<(String, String), Vec<Value>>,
+}
+
+impl Pools {
+    fn collect(&mut self, key: &str, payload: &Value) {
+        for ((_, field), values) in self.values.iter_mut().filter(|((object, _), _)| object == key) {
+            if let Some(value) = payload.pointer(field) {
+                values.push(value.clone());
+            }
+        }
+    }
+
+    fn draw(&self, foreign_key: &ForeignKey) -> Option<Value> {
+        let key = self.keys.get(&foreign_key.object)?;
+        let values = self.values.get(&(key.to_owned(), foreign_key.field.to_owned()))?;
+        if values.is_empty() {
+            return None;
+        }
+        Some(random_values::element_from_collection(values).clone())
+    }
+}
+
+/// Value of a foreign key drawn from the payloads generated so far, `None` outside a dataset or
+/// before any payload of the referenced object was generated.
+pub fn draw(foreign_key: &ForeignKey) -> Option<Value> {
+    ACTIVE.with(|active| active.borrow().as_ref().and_then(|pools| pools.draw(foreign_key)))
+}
+
+/// Value of the `x-chimpsky-ref` keyword of `field`, see `draw`. `Dataset::new` rejects
+/// malformed keywords, and outside a dataset nothing is drawn, so they are never read there.
+pub fn draw_for(field: &FieldDefinition) -> Option<Value> {
+    ACTIVE.with(|active| {
+        let active = active.borrow();
+        let pools = active.as_ref()?;
+        let foreign_key = ForeignKey::of(field).expect("x-chimpsky-ref is checked when the dataset is built")?;
+        pools.draw(&foreign_key)
+    })
+}
+
+/// Payloads of several objects generated together, so that foreign keys refer to payloads that
+/// are part of the dataset. Objects are generated one after the other, each after the objects
+/// its foreign keys refer to.
+pub struct Dataset<'a> {
+    chimpsky: &'a Chimpsky,
+    parts: Vec<(Generator<'a>, u64)>,
+    /// Indexes into `parts` in the order they are generated.
+    order: Vec<usize>,
+    pools: Option<Pools>,
+    overrides: Option<Overrides>,
+    current: usize,
+    emitted: u64,
+}
+
+impl<'a> Dataset<'a> {
+    /// Generators with the number of payloads to generate with each. Fails when a foreign key
+    /// refers to an object that is not generated in the dataset, is not a valid `x-chimpsky-ref`,
+    /// or objects refer to each other in a cycle. An object may refer to itself, drawing from its
+    /// earlier payloads.
+    pub fn new(chimpsky: &'a Chimpsky, parts: Vec<(Generator<'a>, u64)>) -> Result<Self, Error> {
+        let mut dataset = Dataset { chimpsky, parts, order: vec![], pools: None, overrides: None, current: 0, emitted: 0 };
+        dataset.plan()?;
+        Ok(dataset)
+    }
+
+    /// Generates every object with `overrides` in place of the overrides of its generator, so
+    /// that sequences and unique values carry on across objects. Fails like `new` for the
+    /// foreign keys `overrides` configures.
+    pub fn overrides(mut self, overrides: Overrides) -> Result<Self, Error> {
+        self.overrides = Some(overrides);
+        self.plan()?;
+        Ok(self)
+    }
+
+    pub fn generators(&self) -> impl Iterator<Item = &Generator<'a>> {
+        self.order.iter().map(move |i| &self.parts[*i].0)
+    }
+
+    fn plan(&mut self) -> Result<(), Error> {
+        let (chimpsky, parts) = (self.chimpsky, &self.parts);
+        let mut pools = Pools::default();
+        let mut depends_on: Vec<Vec<usize>> = vec![];
+
+        for (generator, _) in parts {
+            let configured = match &self.overrides {
+                Some(overrides) => overrides.foreign_keys(),
+                None => generator.overrides_foreign_keys(),
+            };
+            let mut dependencies = vec![];
+            for foreign_key in foreign_keys(chimpsky, generator.key(), configured)? {
+                let key = chimpsky.object_key(&foreign_key.object)?;
+                let target = parts.iter().position(|(g, _)| g.key() == key).ok_or_else(|| {
+                    Error::InvalidDataset(format!("{} refers to {}, which is not part of the dataset", generator.key(), key))
+                })?;
+                if parts[target].1 == 0 && key != generator.key() {
+                    return Err(Error::InvalidDataset(format!("{} refers to {}, of which no payloads are generated", generator.key(), key)));
+                }
+
+                pools.keys.insert(foreign_key.object.to_owned(), key.to_owned());
+                pools.values.entry((key.to_owned(), foreign_key.field.to_owned())).or_default();
+                if key != generator.key() {
+                    dependencies.push(target);
+                }
+            }
+            depends_on.push(dependencies);
+        }
+
+        self.order = dependency_order(&depends_on).ok_or_else(|| {
+            let keys = parts.iter().map(|(g, _)| g.key()).collect::<Vec<_>>().join(", ");
+            Error::InvalidDataset(format!("objects refer to each other in a cycle: {}", keys))
+        })?;
+        self.pools = Some(pools);
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for Dataset<'a> {
+    /// Reference key of the object and the payload.
+    type Item = (&'a str, Value);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.emitted >= self.parts[*self.order.get(self.current)?].1 {
+            self.current += 1;
+            self.emitted = 0;
+        }
+
+        let generator = &mut self.parts[self.order[self.current]].0;
+        let shared = self.overrides.is_some();
+        if shared {
+            std::mem::swap(&mut generator.overrides, &mut self.overrides);
+        }
+        let payload = with_pools(&mut self.pools, || generator.generate());
+        if shared {
+            std::mem::swap(&mut generator.overrides, &mut self.overrides);
+        }
+        self.pools.as_mut().unwrap().collect(generator.key(), &payload);
+        self.emitted += 1;
+        Some((generator.key(), payload))
+    }
+}
+
+fn with_pools<T>(pools: &mut Option<Pools>, f: impl FnOnce() -> T) -> T {
+    ACTIVE.with(|active| std::mem::swap(&mut *active.borrow_mut(), pools));
+    let result = f();
+    ACTIVE.with(|active| std::mem::swap(&mut *active.borrow_mut(), pools));
+    result
+}
+
+/// Foreign keys declared in the schema of everything payloads of the object embed, and the ones
+/// configured through overrides.
+fn foreign_keys(chimpsky: &Chimpsky, key: &str, configured: Vec<ForeignKey>) -> Result<Vec<ForeignKey>, Error> {
+    let reference_map = chimpsky.reference_map();
+    let keys = codegen::reachable_keys(&[key], reference_map).map_err(Error::InvalidDataset)?;
+
+    let mut foreign_keys = configured;
+    for field in keys.iter().flat_map(|k| reference_map[k].field_definitions.iter().flatten()) {
+        let foreign_key = ForeignKey::of(field)
+            .map_err(|e| Error::InvalidDataset(format!("field {}: {}", field.name, e)))?;
+        foreign_keys.extend(foreign_key);
+    }
+    Ok(foreign_keys)
+}
+
+/// Indexes of the parts with every part after the ones it depends on, keeping the given order
+/// where dependencies allow. `None` when there is a cycle.
+fn dependency_order(depends_on: &[Vec<usize>]) -> Option<Vec<usize>> {
+    let mut order: Vec<usize> = vec![];
+    while order.len() < depends_on.len() {
+        let next = (0..depends_on.len())
+            .find(|i| !order.contains(i) && depends_on[*i].iter().all(|d| order.contains(d)))?;
+        order.push(next);
+    }
+    Some(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::schema_parser::{Schema, SchemaSet};
+
+    use super::*;
+
+    fn chimpsky() -> Chimpsky {
+        let mut set = SchemaSet::default();
+        set.add(Schema::new(&json!({
+            "definitions": {
+                "customer": {
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "string", "format": "uuid" },
+                        "address": { "$ref": "#/definitions/address" }
+                    },
+                    "required": ["id", "address"]
+                },
+                "address": {
+                    "type": "object",
+                    "properties": { "city": { "type": "string" } },
+                    "required": ["city"]
+                },
+                "order": {
+                    "type": "object",
+                    "properties": {
+                        "number": { "type": "integer", "x-chimpsky-sequence": true },
+                        "customer_id": { "type": "string", "x-chimpsky-ref": "customer" },
+                        "city": { "type": "string" },
+                        "previous": { "type": "integer" }
+                    },
+                    "required": ["number", "customer_id", "city", "previous"]
+                },
+                "ticket": {
+                    "type": "object",
+                    "properties": { "holder": { "$ref": "#/definitions/holder" } },
+                    "required": ["holder"]
+                },
+                "holder": {
+                    "type": "object",
+                    "properties": { "customer_id": { "type": "string", "x-chimpsky-ref": { "object": "customer", "key": "id" } } },
+                    "required": ["customer_id"]
+                }
+            }
+        }), Some("shop.json".to_owned()))).unwrap();
+        Chimpsky::from(set)
+    }
+
+    #[test]
+    fn foreign_keys_refer_to_generated_payloads() {
+        let chimpsky = chimpsky();
+        let overrides = Overrides::from_config(&json!({ "fields": {
+            "city": { "ref": "customer", "field": "/address/city" },
+            "previous": { "ref": "order", "field": "number" }
+        } }), std::path::Path::new("")).unwrap();
+        let parts = vec![
+            (chimpsky.generator("order").unwrap().overrides(overrides), 20),
+            (chimpsky.generator("customer").unwrap(), 5),
+        ];
+        let records = Dataset::new(&chimpsky, parts).unwrap().collect::<Vec<_>>();
+
+        let objects = records.iter().map(|(key, _)| *key).collect::<Vec<_>>();
+        assert_eq!(objects[..5], ["shop.json#/definitions/customer"; 5]);
+        assert_eq!(objects[5..], ["shop.json#/definitions/order"; 20]);
+
+        let customers = records[..5].iter().map(|(_, c)| &c["id"]).collect::<Vec<_>>();
+        let cities = records[..5].iter().map(|(_, c)| &c["address"]["city"]).collect::<Vec<_>>();
+        for (i, (_, order)) in records[5..].iter().enumerate() {
+            assert!(customers.contains(&&order["customer_id"]));
+            assert!(cities.contains(&&order["city"]));
+            if i > 0 {
+                assert!(order["previous"].as_u64().unwrap() <= i as u64);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_datasets() {
+        let chimpsky = chimpsky();
+        let error = |parts: Vec<(Generator, u64)>| Dataset::new(&chimpsky, parts).err().unwrap().to_string();
+
+        assert_eq!(error(vec![(chimpsky.generator("order").unwrap(), 1)]),
+                   "shop.json#/definitions/order refers to shop.json#/definitions/customer, which is not part of the dataset");
+        assert_eq!(error(vec![(chimpsky.generator("order").unwrap(), 1), (chimpsky.generator("customer").unwrap(), 0)]),
+                   "shop.json#/definitions/order refers to shop.json#/definitions/customer, of which no payloads are generated");
+
+        let overrides = Overrides::from_config(&json!({ "fields": { "id": { "ref": "order", "field": "customer_id" } } }),
+                                               std::path::Path::new("")).unwrap();
+        let cycle = vec![(chimpsky.generator("order").unwrap(), 1), (chimpsky.generator("customer").unwrap().overrides(overrides), 1)];
+        assert!(error(cycle).starts_with("objects refer to each other in a cycle"));
+    }
+
+    #[test]
+    fn overrides_are_shared_by_every_object() {
+        let chimpsky = chimpsky();
+        let parts = vec![(chimpsky.generator("order").unwrap(), 6), (chimpsky.generator("customer").unwrap(), 3)];
+        let overrides = Overrides::from_config(&json!({ "fields": {
+            "id": { "sequence": true, "format": "c-{}" },
+            "number": { "sequence": true, "start": 100 },
+            "city": { "ref": "customer", "field": "/address/city" }
+        } }), std::path::Path::new("")).unwrap();
+        let records = Dataset::new(&chimpsky, parts).unwrap().overrides(overrides).unwrap().collect::<Vec<_>>();
+
+        let ids = records[..3].iter().map(|(_, c)| c["id"].as_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(ids, ["c-1", "c-2", "c-3"]);
+        let cities = records[..3].iter().map(|(_, c)| &c["address"]["city"]).collect::<Vec<_>>();
+        for (i, (_, order)) in records[3..].iter().enumerate() {
+            assert_eq!(order["number"], 100 + i as u64);
+            assert!(cities.contains(&&order["city"]));
+        }
+
+        let parts = vec![(chimpsky.generator("customer").unwrap(), 1)];
+        let overrides = Overrides::from_config(&json!({ "fields": { "city": { "ref": "order", "field": "number" } } }),
+                                               std::path::Path::new("")).unwrap();
+        assert!(Dataset::new(&chimpsky, parts).unwrap().overrides(overrides).is_err());
+    }
+
+    #[test]
+    fn malformed_keywords_are_rejected() {
+        let chimpsky = chimpsky();
+        let parts = vec![(chimpsky.generator("ticket").unwrap(), 1), (chimpsky.generator("customer").unwrap(), 1)];
+        assert_eq!(Dataset::new(&chimpsky, parts).err().unwrap().to_string(),
+                   "field customer_id: unknown key key in x-chimpsky-ref");
+    }
+
+    #[test]
+    fn keywords() {
+        let field = |keyword: Value| FieldDefinition::new("f", &json!({ "type": "string", "x-chimpsky-ref": keyword }));
+        assert_eq!(ForeignKey::of(&field(json!("customer"))), Ok(Some(ForeignKey::new("customer", Some("/id")))));
+        assert_eq!(ForeignKey::of(&field(json!({ "object": "customer", "field": "code" }))),
+                   Ok(Some(ForeignKey { object: "customer".to_owned(), field: "/code".to_owned() })));
+        assert!(ForeignKey::of(&field(json!(1))).is_err());
+        assert!(ForeignKey::of(&field(json!({ "object": "customer", "key": "code" }))).is_err());
+    }
+}
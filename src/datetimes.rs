@@ -0,0 +1,287 @@
+use std::cell::RefCell;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, SecondsFormat, TimeZone, Utc};
+use rand::Rng;
+
+use crate::random_values::{element_from_collection, rng};
+
+thread_local! {
+    static ACTIVE: RefCell<Option<DateTimes>> = const { RefCell::new(None) };
+}
+
+static NOW: OnceLock<DateTime<Utc>> = OnceLock::new();
+static DEFAULT: OnceLock<DateTimes> = OnceLock::new();
+
+pub const DEFAULT_FROM: &str = "-365d";
+pub const DEFAULT_TO: &str = "now";
+
+/// Offsets in minutes east of UTC that values are written in when offsets vary.
+const OFFSETS: &[i32] = &[-480, -300, -180, 0, 60, 120, 330, 480, 540, 600];
+
+/// Where in their range date-times fall.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeDistribution {
+    Uniform,
+    /// Most values close to the end of the range, fewer the further back.
+    Recent,
+    /// The moment of generation, whatever the range.
+    Now,
+}
+
+impl FromStr for TimeDistribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uniform" => Ok(TimeDistribution::Uniform),
+            "recent" => Ok(TimeDistribution::Recent),
+            "now" => Ok(TimeDistribution::Now),
+            _ => Err(format!("unknown distribution {}, expected uniform, recent or now", s)),
+        }
+    }
+}
+
+/// How values of the `date-time`, `date` and `time` formats are generated. Without settings
+/// installed by `with_datetimes`, they fall within the last year, in UTC and to the second.
+#[derive(Debug, Clone)]
+pub struct DateTimes {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    distribution: TimeDistribution,
+    offsets: bool,
+    fractional_seconds: bool,
+}
+
+impl Default for DateTimes {
+    fn default() -> Self {
+        DateTimes::parse(DEFAULT_FROM, DEFAULT_TO).unwrap()
+    }
+}
+
+impl DateTimes {
+    pub fn new(from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Self, String> {
+        if from > to {
+            return Err(format!("date-time range starts at {} after it ends at {}", from.to_rfc3339(), to.to_rfc3339()));
+        }
+        Ok(DateTimes { from, to, distribution: TimeDistribution::Uniform, offsets: false, fractional_seconds: false })
+    }
+
+    /// A range between two bounds as `parse_instant` reads them. The end is relative to `now`,
+    /// a relative start like `-7d` to the end, so that the range only depends on the current
+    /// time when its end does.
+    pub fn parse(from: &str, to: &str) -> Result<Self, String> {
+        let to = parse_instant(to, now())?;
+        let anchor = if from.starts_with(['-', '+']) { to } else { now() };
+        DateTimes::new(parse_instant(from, anchor)?, to)
+    }
+
+    pub fn distribution(mut self, distribution: TimeDistribution) -> Self {
+        self.distribution = distribution;
+        self
+    }
+
+    /// Writes values in a variety of UTC offsets instead of UTC alone.
+    pub fn offsets(mut self) -> Self {
+        self.offsets = true;
+        self
+    }
+
+    /// Adds milliseconds to date-times and times.
+    pub fn fractional_seconds(mut self) -> Self {
+        self.fractional_seconds = true;
+        self
+    }
+
+    fn instant(&self) -> DateTime<FixedOffset> {
+        let span = (self.to - self.from).num_milliseconds();
+        let instant = match self.distribution {
+            TimeDistribution::Uniform => self.from + Duration::milliseconds(rng().gen_range(0, span + 1)),
+            TimeDistribution::Recent => {
+                let back: f64 = rng().gen();
+                self.to - Duration::milliseconds((back.powi(3) * span as f64) as i64)
+            }
+            TimeDistribution::Now => Utc::now(),
+        };
+        let instant = if self.fractional_seconds {
+            instant
+        } else {
+            Utc.timestamp_opt(instant.timestamp(), 0).unwrap()
+        };
+
+        let offset = if self.offsets { *element_from_collection(OFFSETS) } else { 0 };
+        instant.with_timezone(&FixedOffset::east_opt(offset * 60).unwrap())
+    }
+
+    fn seconds_format(&self) -> SecondsFormat {
+        if self.fractional_seconds { SecondsFormat::Millis } else { SecondsFormat::Secs }
+    }
+}
+
+/// An RFC 3339 date-time, a `YYYY-MM-DD` date meaning its midnight in UTC, `now`, or a time
+/// relative to `now` such as `-7d` or `+12h`, see `parse_duration`.
+pub fn parse_instant(s: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    if s == "now" {
+        return Ok(now);
+    }
+    if let Ok(instant) = DateTime::parse_from_rfc3339(s) {
+        return Ok(instant.with_timezone(&Utc));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()));
+    }
+
+    let invalid = || format!("{} is not a date-time, a date, now or a relative time like -7d", s);
+    let duration = match s.strip_prefix(['-', '+']) {
+        Some(duration) if duration.starts_with(|c: char| c.is_ascii_digit()) => parse_duration(duration)?,
+        _ => return Err(invalid()),
+    };
+    let duration = if s.starts_with('-') { -duration } else { duration };
+    now.checked_add_signed(duration).ok_or_else(|| format!("{} is too far from {}", s, now.to_rfc3339()))
+}
+
+/// A whole number of milliseconds, seconds, minutes, hours, days or weeks, like `500ms` or `5m`.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("{} is not a duration like 5m, in ms, s, m, h, d or w", s);
+    let unit_at = s.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+    let (amount, unit) = s.split_at(unit_at);
+    let amount = amount.parse::<i64>().map_err(|_| invalid())?;
+    let duration = match unit {
+        "ms" => Duration::try_milliseconds(amount),
+        "s" => Duration::try_seconds(amount),
+        "m" => Duration::try_minutes(amount),
+        "h" => Duration::try_hours(amount),
+        "d" => Duration::try_days(amount),
+        "w" => Duration::try_weeks(amount),
+        _ => return Err(invalid()),
+    };
+    duration.ok_or_else(|| format!("{} is too long a duration", s))
+}
+
+/// The current time as of the first call, so that the date-times of a run, and the sequences
+/// starting at `now`, are all relative to the same instant.
+pub fn now() -> DateTime<Utc> {
+    *NOW.get_or_init(Utc::now)
+}
+
+/// Generates date-times, dates and times the way `datetimes` describes while running `f`.
+pub fn with_datetimes<T>(datetimes: &mut Option<DateTimes>, f: impl FnOnce() -> T) -> T {
+    ACTIVE.with(|active| std::mem::swap(&mut *active.borrow_mut(), datetimes));
+    let result = f();
+    ACTIVE.with(|active| std::mem::swap(&mut *active.borrow_mut(), datetimes));
+    result
+}
+
+fn with_active<T>(f: impl FnOnce(&DateTimes) -> T) -> T {
+    ACTIVE.with(|active| match active.borrow().as_ref() {
+        Some(datetimes) => f(datetimes),
+        None => f(DEFAULT.get_or_init(DateTimes::default)),
+    })
+}
+
+pub fn date_time() -> String {
+    with_active(|datetimes| datetimes.instant().to_rfc3339_opts(datetimes.seconds_format(), false))
+}
+
+pub fn date() -> String {
+    with_active(|datetimes| datetimes.instant().format("%Y-%m-%d").to_string())
+}
+
+pub fn time() -> String {
+    with_active(|datetimes| {
+        let format = if datetimes.fractional_seconds { "%H:%M:%S%.3f%:z" } else { "%H:%M:%S%:z" };
+        datetimes.instant().format(format).to_string()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::schema_parser::{Schema, SchemaSet};
+    use crate::verifier::Verifier;
+    use crate::Chimpsky;
+
+    use super::*;
+
+    #[test]
+    fn instants() {
+        let now = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        assert_eq!(parse_instant("now", now), Ok(now));
+        assert_eq!(parse_instant("-7d", now), Ok(Utc.with_ymd_and_hms(2024, 4, 24, 12, 0, 0).unwrap()));
+        assert_eq!(parse_instant("+90m", now), Ok(Utc.with_ymd_and_hms(2024, 5, 1, 13, 30, 0).unwrap()));
+        assert_eq!(parse_instant("2024-01-01", now), Ok(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()));
+        assert_eq!(parse_instant("2024-01-01T06:00:00+02:00", now), Ok(Utc.with_ymd_and_hms(2024, 1, 1, 4, 0, 0).unwrap()));
+        assert_eq!(parse_instant("7d", now), Err("7d is not a date-time, a date, now or a relative time like -7d".to_owned()));
+        assert!(parse_instant("-7y", now).is_err());
+        assert!(parse_instant("-", now).is_err());
+        assert_eq!(parse_duration("250ms"), Ok(Duration::milliseconds(250)));
+        assert!(parse_duration("m").is_err());
+        assert_eq!(parse_instant("-999999999999d", now), Err("999999999999d is too long a duration".to_owned()));
+        assert!(parse_instant("-99999999999d", now).unwrap_err().starts_with("-99999999999d is too far from"));
+        assert!(DateTimes::parse("now", "-1d").is_err());
+        assert!("sometimes".parse::<TimeDistribution>().is_err());
+    }
+
+    #[test]
+    fn values_stay_in_range() {
+        let from = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let to = Utc.with_ymd_and_hms(2024, 1, 31, 0, 0, 0).unwrap();
+        let mut datetimes = Some(DateTimes::new(from, to).unwrap().distribution(TimeDistribution::Recent).offsets());
+
+        let values = with_datetimes(&mut datetimes, || (0..200).map(|_| date_time()).collect::<Vec<_>>());
+        let instants = values.iter().map(|v| DateTime::parse_from_rfc3339(v).unwrap()).collect::<Vec<_>>();
+        assert!(instants.iter().all(|i| *i >= from && *i <= to));
+        assert!(instants.iter().any(|i| i.offset().local_minus_utc() != 0));
+        let last_week = instants.iter().filter(|i| **i >= to - Duration::days(7)).count();
+        assert!(last_week > 100, "{} of 200 in the last week", last_week);
+        assert!(values.iter().all(|v| !v.contains('.')));
+    }
+
+    #[test]
+    fn relative_starts_count_back_from_the_end() {
+        let datetimes = DateTimes::parse("-7d", "2024-01-08").unwrap();
+        assert_eq!(datetimes.from, Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap());
+        assert_eq!(DateTimes::parse(DEFAULT_FROM, DEFAULT_TO).unwrap().to, now());
+    }
+
+    #[test]
+    fn default_range_is_the_last_year() {
+        let now = Utc::now();
+        for _ in 0..100 {
+            let instant = DateTime::parse_from_rfc3339(&date_time()).unwrap();
+            assert!(instant > now - Duration::days(366) && instant <= Utc::now());
+            assert_eq!(instant.offset().local_minus_utc(), 0);
+        }
+    }
+
+    #[test]
+    fn formats_verify() {
+        let mut set = SchemaSet::default();
+        set.add(Schema::new(&json!({
+            "definitions": {
+                "event": {
+                    "type": "object",
+                    "properties": {
+                        "at": { "type": "string", "format": "date-time" },
+                        "on": { "type": "string", "format": "date" },
+                        "opens": { "type": "string", "format": "time" }
+                    },
+                    "required": ["at", "on", "opens"]
+                }
+            },
+            "allOf": [ { "$ref": "#/definitions/event" } ]
+        }), Some("event.json".to_owned()))).unwrap();
+        let chimpsky = Chimpsky::from(set);
+        let key = "event.json#/definitions/event";
+        let verifier = Verifier::new(&chimpsky.schema_set().documents, key).unwrap();
+
+        let datetimes = DateTimes::parse("2020-02-01", "2020-02-29").unwrap().offsets().fractional_seconds();
+        for payload in chimpsky.generator(key).unwrap().datetimes(datetimes).take(20) {
+            assert_eq!(verifier.verify(&payload), vec![]);
+            assert!(payload["on"].as_str().unwrap().starts_with("2020-0"));
+            assert!(payload["at"].as_str().unwrap().contains('.'));
+        }
+    }
+}
@@ -0,0 +1,374 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::field_definitions::FieldDefinition;
+use crate::field_kinds::FieldKind;
+use crate::object_definitions::ObjectDefinition;
+
+/// One difference between two versions of a schema directory. Producers write payloads the
+/// way the old schemas describe them and break when the new schemas reject those payloads.
+/// Consumers are written against the old schemas and break when the new schemas allow payloads
+/// they do not expect.
+#[derive(Debug, Serialize)]
+pub struct Change {
+    pub rule: &'static str,
+    pub object: String,
+    pub field: Option<String>,
+    pub message: String,
+    pub breaks_producers: bool,
+    pub breaks_consumers: bool,
+}
+
+impl Change {
+    pub fn is_breaking(&self) -> bool {
+        self.breaks_producers || self.breaks_consumers
+    }
+
+    fn impact(&self) -> &'static str {
+        match (self.breaks_producers, self.breaks_consumers) {
+            (true, true) => "breaks producers and consumers",
+            (true, false) => "breaks producers",
+            (false, true) => "breaks consumers",
+            (false, false) => "compatible",
+        }
+    }
+}
+
+/// Compares the objects of both reference maps matched by their keys, breaking changes first.
+pub fn diff(old: &BTreeMap<String, ObjectDefinition>, new: &BTreeMap<String, ObjectDefinition>) -> Result<Vec<Change>, String> {
+    let mut changes = vec![];
+
+    for key in old.keys().filter(|key| !new.contains_key(*key)) {
+        changes.push(change("object-removed", key, None, "object was removed".to_owned(), true, true));
+    }
+    for key in new.keys().filter(|key| !old.contains_key(*key)) {
+        changes.push(change("object-added", key, None, "object was added".to_owned(), false, false));
+    }
+
+    for (key, old_definition) in old {
+        if let Some(new_definition) = new.get(key) {
+            diff_fields(key, old_definition.all_fields(old)?, new_definition.all_fields(new)?, &mut changes);
+        }
+    }
+
+    changes.sort_by(|a, b| (b.is_breaking(), &a.object, &a.field, a.rule).cmp(&(a.is_breaking(), &b.object, &b.field, b.rule)));
+    Ok(changes)
+}
+
+pub fn render_text(changes: &[Change]) -> String {
+    changes.iter()
+        .map(|c| {
+            let location = match &c.field {
+                Some(field) => format!("{} field {}", c.object, field),
+                None => c.object.to_owned(),
+            };
+            let status = if c.is_breaking() { "breaking" } else { "compatible" };
+            if c.is_breaking() {
+                format!("{}[{}] {}: {}, {}\n", status, c.rule, location, c.message, c.impact())
+            } else {
+                format!("{}[{}] {}: {}\n", status, c.rule, location, c.message)
+            }
+        })
+        .collect()
+}
+
+pub fn render_json(changes: &[Change]) -> String {
+    serde_json::to_string_pretty(changes).unwrap()
+}
+
+fn diff_fields(key: &str, old: Vec<(&FieldDefinition, bool)>, new: Vec<(&FieldDefinition, bool)>, changes: &mut Vec<Change>) {
+    for (old_field, old_optional) in &old {
+        let name = Some(old_field.name.as_str());
+        let (new_field, new_optional) = match new.iter().find(|(f, _)| f.name == old_field.name) {
+            Some(found) => found,
+            None => {
+                changes.push(change("field-removed", key, name, "field was removed".to_owned(), false, true));
+                continue;
+            }
+        };
+
+        if *old_optional && !new_optional {
+            changes.push(change("field-required", key, name, "field became required".to_owned(), true, false));
+        } else if !old_optional && *new_optional {
+            changes.push(change("field-optional", key, name, "field became optional".to_owned(), false, true));
+        }
+
+        diff_kinds(key, &old_field.name, kinds(old_field.kind.as_ref()), kinds(new_field.kind.as_ref()), changes);
+        diff_values(key, &old_field.name, allowed_values(old_field), allowed_values(new_field), changes);
+        diff_constraint(key, &old_field.name, "pattern", &old_field.pattern, &new_field.pattern, changes);
+        diff_constraint(key, &old_field.name, "format", &old_field.format, &new_field.format, changes);
+    }
+
+    for (new_field, new_optional) in &new {
+        if !old.iter().any(|(f, _)| f.name == new_field.name) {
+            let name = Some(new_field.name.as_str());
+            if *new_optional {
+                changes.push(change("field-added", key, name, "optional field was added".to_owned(), false, false));
+            } else {
+                changes.push(change("required-field-added", key, name, "required field was added".to_owned(), true, false));
+            }
+        }
+    }
+}
+
+/// Compares the types of a field, then the types of its array items under `field[]`. `None`
+/// stands for a field without a type, which accepts anything.
+fn diff_kinds(key: &str, field: &str, old: Option<Vec<&FieldKind>>, new: Option<Vec<&FieldKind>>, changes: &mut Vec<Change>) {
+    let (old_types, new_types) = (old.as_ref().map(|k| types(k)), new.as_ref().map(|k| types(k)));
+    let name = Some(field);
+
+    match (&old_types, &new_types) {
+        (None, None) => {}
+        (None, Some(new_types)) => {
+            let message = format!("type was restricted to {}", join(new_types));
+            changes.push(change("type-narrowed", key, name, message, true, false));
+        }
+        (Some(_), None) => {
+            changes.push(change("type-widened", key, name, "type restriction was removed".to_owned(), false, true));
+        }
+        (Some(old_types), Some(new_types)) => {
+            let dropped = old_types.iter().filter(|t| !covers(new_types, t)).cloned().collect::<BTreeSet<_>>();
+            let added = new_types.iter().filter(|t| !covers(old_types, t)).cloned().collect::<BTreeSet<_>>();
+            if !dropped.is_empty() {
+                let message = format!("type no longer accepts {}", join(&dropped));
+                changes.push(change("type-narrowed", key, name, message, true, false));
+            }
+            if !added.is_empty() {
+                let message = format!("type now also accepts {}", join(&added));
+                changes.push(change("type-widened", key, name, message, false, true));
+            }
+        }
+    }
+
+    let (old_items, new_items) = (old.as_ref().and_then(|k| items(k)), new.as_ref().and_then(|k| items(k)));
+    if let (Some(old_items), Some(new_items)) = (old_items, new_items) {
+        diff_kinds(key, &format!("{}[]", field), old_items, new_items, changes);
+    }
+}
+
+fn diff_values(key: &str, field: &str, old: Option<Vec<&Value>>, new: Option<Vec<&Value>>, changes: &mut Vec<Change>) {
+    let name = Some(field);
+    match (old, new) {
+        (None, None) => {}
+        (None, Some(new)) => {
+            let message = format!("values were restricted to {}", join_values(&new));
+            changes.push(change("enum-value-dropped", key, name, message, true, false));
+        }
+        (Some(_), None) => {
+            changes.push(change("enum-value-added", key, name, "value restriction was removed".to_owned(), false, true));
+        }
+        (Some(old), Some(new)) => {
+            let dropped = old.iter().filter(|v| !new.contains(v)).cloned().collect::<Vec<_>>();
+            let added = new.iter().filter(|v| !old.contains(v)).cloned().collect::<Vec<_>>();
+            if !dropped.is_empty() {
+                let message = format!("values {} were dropped", join_values(&dropped));
+                changes.push(change("enum-value-dropped", key, name, message, true, false));
+            }
+            if !added.is_empty() {
+                let message = format!("values {} were added", join_values(&added));
+                changes.push(change("enum-value-added", key, name, message, false, true));
+            }
+        }
+    }
+}
+
+/// Neither of two different patterns or formats is known to accept everything the other one
+/// does, so changing one breaks both sides.
+fn diff_constraint(key: &str, field: &str, keyword: &str, old: &Option<String>, new: &Option<String>, changes: &mut Vec<Change>) {
+    let rule = if keyword == "pattern" { "pattern-changed" } else { "format-changed" };
+    let (message, producers, consumers) = match (old, new) {
+        (Some(old), Some(new)) if old != new => (format!("{} changed from {} to {}", keyword, old, new), true, true),
+        (None, Some(new)) => (format!("{} {} was added", keyword, new), true, false),
+        (Some(old), None) => (format!("{} {} was removed", keyword, old), false, true),
+        _ => return,
+    };
+    changes.push(change(rule, key, Some(field), message, producers, consumers));
+}
+
+fn change(rule: &'static str, object: &str, field: Option<&str>, message: String, breaks_producers: bool, breaks_consumers: bool) -> Change {
+    Change {
+        rule,
+        object: object.to_owned(),
+        field: field.map(|f| f.to_owned()),
+        message,
+        breaks_producers,
+        breaks_consumers,
+    }
+}
+
+fn kinds(kind: Option<&FieldKind>) -> Option<Vec<&FieldKind>> {
+    kind.map(|kind| match kind {
+        FieldKind::OneOf(kinds) => kinds.iter().collect(),
+        kind => vec![kind],
+    })
+}
+
+/// Item kinds of the arrays among `kinds`, `None` when there are no arrays or their items
+/// can be anything.
+fn items<'a>(field_kinds: &[&'a FieldKind]) -> Option<Option<Vec<&'a FieldKind>>> {
+    let lists = field_kinds.iter()
+        .filter_map(|kind| match kind {
+            FieldKind::ListOf(items) => Some(items),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    if lists.is_empty() {
+        None
+    } else if lists.iter().any(|items| items.is_empty()) {
+        Some(None)
+    } else {
+        Some(Some(lists.into_iter().flatten().flat_map(|item| kinds(Some(item)).unwrap()).collect()))
+    }
+}
+
+fn types(kinds: &[&FieldKind]) -> BTreeSet<String> {
+    kinds.iter()
+        .map(|kind| match kind {
+            FieldKind::Str => "string".to_owned(),
+            FieldKind::Int => "integer".to_owned(),
+            FieldKind::Float => "number".to_owned(),
+            FieldKind::Bool => "boolean".to_owned(),
+            FieldKind::Object => "object".to_owned(),
+            FieldKind::Null => "null".to_owned(),
+            FieldKind::ListOf(_) => "array".to_owned(),
+            FieldKind::OneOf(_) => "oneOf".to_owned(),
+            FieldKind::Reference(r) => r.to_owned(),
+            FieldKind::Unknown(t) => t.to_owned(),
+        })
+        .collect()
+}
+
+/// Numbers accept every integer.
+fn covers(types: &BTreeSet<String>, t: &str) -> bool {
+    types.contains(t) || (t == "integer" && types.contains("number"))
+}
+
+/// `const` is an enum with a single value.
+fn allowed_values(field: &FieldDefinition) -> Option<Vec<&Value>> {
+    match (&field.const_value, &field.enum_values) {
+        (Some(value), _) => Some(vec![value]),
+        (None, Some(values)) => Some(values.iter().collect()),
+        (None, None) => None,
+    }
+}
+
+fn join(types: &BTreeSet<String>) -> String {
+    types.iter().cloned().collect::<Vec<_>>().join(", ")
+}
+
+fn join_values(values: &[&Value]) -> String {
+    values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::schema_parser::{Schema, SchemaSet};
+
+    use super::*;
+
+    fn schema_set(message: Value) -> SchemaSet {
+        let mut set = SchemaSet::default();
+        set.add(Schema::new(&json!({
+            "definitions": { "message": message },
+            "allOf": [ { "$ref": "#/definitions/message" } ]
+        }), Some("message.json".to_owned()))).unwrap();
+        set
+    }
+
+    fn old() -> SchemaSet {
+        schema_set(json!({
+            "type": "object",
+            "properties": {
+                "id": { "type": "string", "format": "uuid" },
+                "amount": { "type": "integer" },
+                "state": { "type": "string", "enum": ["on", "off"] },
+                "code": { "type": "string", "pattern": "^[A-Z]+$" },
+                "tags": { "type": "array", "items": { "type": "string" } },
+                "note": { "type": "string" },
+                "gone": { "type": "boolean" }
+            },
+            "required": ["id", "amount", "gone"]
+        }))
+    }
+
+    fn changes(new: Value) -> Vec<Change> {
+        diff(&old().reference_map, &schema_set(new).reference_map).unwrap()
+    }
+
+    fn summary(changes: &[Change]) -> Vec<(&str, Option<&str>, bool, bool)> {
+        changes.iter()
+            .map(|c| (c.rule, c.field.as_deref(), c.breaks_producers, c.breaks_consumers))
+            .collect()
+    }
+
+    #[test]
+    fn unchanged_schemas_have_no_changes() {
+        let old = old();
+        assert!(diff(&old.reference_map, &old.reference_map).unwrap().is_empty());
+    }
+
+    #[test]
+    fn breaking_changes() {
+        let changes = changes(json!({
+            "type": "object",
+            "properties": {
+                "id": { "type": "string", "format": "uuid" },
+                "amount": { "type": "number" },
+                "state": { "type": "string", "enum": ["on"] },
+                "code": { "type": "string", "pattern": "^[A-Z0-9]+$" },
+                "tags": { "type": "array", "items": { "type": ["string", "integer"] } },
+                "note": { "type": "string" },
+                "added": { "type": "string" }
+            },
+            "required": ["id", "amount", "note", "added"]
+        }));
+
+        let summary = summary(&changes);
+        assert!(summary.contains(&("field-removed", Some("gone"), false, true)));
+        assert!(summary.contains(&("type-widened", Some("amount"), false, true)));
+        assert!(summary.contains(&("enum-value-dropped", Some("state"), true, false)));
+        assert!(summary.contains(&("pattern-changed", Some("code"), true, true)));
+        assert!(summary.contains(&("type-widened", Some("tags[]"), false, true)));
+        assert!(summary.contains(&("field-required", Some("note"), true, false)));
+        assert!(summary.contains(&("required-field-added", Some("added"), true, false)));
+        assert!(changes.iter().all(|c| c.is_breaking()));
+    }
+
+    #[test]
+    fn narrowing_and_compatible_changes() {
+        let changes = changes(json!({
+            "type": "object",
+            "properties": {
+                "id": { "type": "string", "format": "uuid" },
+                "amount": { "type": "integer" },
+                "state": { "type": ["string", "null"], "enum": ["on", "off", null] },
+                "code": { "type": "string", "pattern": "^[A-Z]+$" },
+                "tags": { "type": "array", "items": { "type": "string" } },
+                "note": { "type": "string" },
+                "gone": { "type": "null" },
+                "added": { "type": "string" }
+            },
+            "required": ["id", "amount", "gone"]
+        }));
+
+        let summary = summary(&changes);
+        assert_eq!(summary[0], ("type-narrowed", Some("gone"), true, false));
+        assert!(summary.contains(&("type-widened", Some("gone"), false, true)));
+        assert!(summary.contains(&("enum-value-added", Some("state"), false, true)));
+        assert_eq!(summary.last().unwrap(), &("field-added", Some("added"), false, false));
+        assert!(render_text(&changes).ends_with("compatible[field-added] message.json#/definitions/message field added: optional field was added\n"));
+    }
+
+    #[test]
+    fn removed_objects_break_both_sides() {
+        let changes = diff(&old().reference_map, &BTreeMap::new()).unwrap();
+        assert_eq!(summary(&changes), vec![("object-removed", None, true, true)]);
+        assert_eq!(render_text(&changes),
+                   "breaking[object-removed] message.json#/definitions/message: object was removed, breaks producers and consumers\n");
+    }
+}
@@ -0,0 +1,426 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+use serde_json::Value;
+
+use crate::field_definitions::FieldDefinition;
+use crate::field_kinds::FieldKind;
+use crate::graph::DependencyGraph;
+use crate::object_definitions::ObjectDefinition;
+use crate::schema_parser::SchemaSet;
+
+/// Keywords shown as the type of a field rather than among its constraints.
+const TYPE_KEYWORDS: &[&str] = &["type", "$ref", "items", "title", "description", "$comment", "examples"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DocsFormat {
+    Markdown,
+    Html,
+}
+
+impl FromStr for DocsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "markdown" => Ok(DocsFormat::Markdown),
+            "html" => Ok(DocsFormat::Html),
+            _ => Err(format!("unknown docs format {}, expected markdown or html", s)),
+        }
+    }
+}
+
+impl DocsFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            DocsFormat::Markdown => "md",
+            DocsFormat::Html => "html",
+        }
+    }
+}
+
+/// Pieces of a field type, so references can be rendered as links in either format.
+#[derive(Debug, PartialEq)]
+enum Part {
+    Text(String),
+    Link(String),
+}
+
+struct FieldDoc {
+    name: String,
+    kind: Vec<Part>,
+    required: bool,
+    constraints: Vec<String>,
+    description: Option<String>,
+}
+
+struct Page {
+    key: String,
+    name: String,
+    description: Option<String>,
+    includes: Vec<String>,
+    fields: Vec<FieldDoc>,
+    referrers: Vec<(String, String)>,
+    example: Option<Value>,
+}
+
+/// Documentation pages for every object of a schema set, plus an `index` page listing them, as
+/// file names with their contents.
+pub fn render(schema_set: &SchemaSet, format: DocsFormat) -> Vec<(String, String)> {
+    let reference_map = &schema_set.reference_map;
+    let graph = DependencyGraph::new(reference_map);
+    let slugs = slugs(reference_map);
+    let link = |key: &str| slugs.get(key).map(|slug| format!("{}.{}", slug, format.extension()));
+
+    let pages = reference_map.keys().map(|key| page(schema_set, &graph, key)).collect::<Vec<_>>();
+
+    let mut files = vec![(format!("index.{}", format.extension()), match format {
+        DocsFormat::Markdown => index_markdown(&pages, &link),
+        DocsFormat::Html => index_html(&pages, &link),
+    })];
+
+    for page in &pages {
+        let content = match format {
+            DocsFormat::Markdown => page_markdown(page, &link),
+            DocsFormat::Html => page_html(page, &link),
+        };
+        files.push((link(&page.key).unwrap(), content));
+    }
+
+    files
+}
+
+fn page(schema_set: &SchemaSet, graph: &DependencyGraph, key: &str) -> Page {
+    let reference_map = &schema_set.reference_map;
+    let definition = &reference_map[key];
+
+    let fields = match definition.all_fields(reference_map) {
+        Ok(fields) => fields,
+        // fields of unresolved includes are left out, the include itself is listed as dangling
+        Err(_) => definition.field_definitions.iter().flatten().map(|f| (f, !definition.is_required(&f.name))).collect(),
+    };
+
+    Page {
+        key: key.to_owned(),
+        name: definition.name.to_owned(),
+        description: schema_set.definition_node(key).and_then(description),
+        includes: definition.references.clone().unwrap_or_default(),
+        fields: fields.into_iter()
+            .map(|(field, optional)| {
+                let node = property_node(schema_set, key, &field.name);
+                FieldDoc {
+                    name: field.name.to_owned(),
+                    kind: field_type(field),
+                    required: !optional,
+                    constraints: node.map(constraints).unwrap_or_default(),
+                    description: node.and_then(description),
+                }
+            })
+            .collect(),
+        referrers: graph.referrers(key).into_iter().map(|e| (e.from.to_owned(), e.via.to_owned())).collect(),
+        example: Some(key).filter(|k| crate::can_generate(k, reference_map)).and_then(|_| definition.generate_json(Some(reference_map))),
+    }
+}
+
+/// The schema node declaring a field, looked up in the object and then in the objects it
+/// includes through `allOf`.
+fn property_node<'a>(schema_set: &'a SchemaSet, key: &str, field: &str) -> Option<&'a Value> {
+    let node = schema_set.definition_node(key)?;
+    let own = node.get("properties")
+        .into_iter()
+        .chain(node.get("allOf").and_then(|a| a.as_array()).into_iter().flatten().filter_map(|m| m.get("properties")))
+        .find_map(|properties| properties.get(field));
+
+    own.or_else(|| {
+        schema_set.reference_map.get(key)?
+            .references.iter().flatten()
+            .find_map(|r| property_node(schema_set, r, field))
+    })
+}
+
+fn description(node: &Value) -> Option<String> {
+    node.get("description").or_else(|| node.get("title")).and_then(|d| d.as_str()).map(|d| d.to_owned())
+}
+
+fn constraints(node: &Value) -> Vec<String> {
+    node.as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(k, _)| !TYPE_KEYWORDS.contains(&k.as_str()))
+        .map(|(k, v)| format!("{}: {}", k, v.as_str().map(|s| s.to_owned()).unwrap_or_else(|| v.to_string())))
+        .collect()
+}
+
+fn field_type(field: &FieldDefinition) -> Vec<Part> {
+    match &field.kind {
+        None => vec![Part::Text("any".to_owned())],
+        Some(kind) => kind_parts(kind),
+    }
+}
+
+fn kind_parts(kind: &FieldKind) -> Vec<Part> {
+    match kind {
+        FieldKind::Reference(r) => vec![Part::Link(r.to_owned())],
+        FieldKind::OneOf(kinds) => joined(kinds, " | "),
+        FieldKind::ListOf(kinds) => {
+            let mut parts = vec![Part::Text("array of ".to_owned())];
+            parts.extend(joined(kinds, " | "));
+            parts
+        }
+        kind => vec![Part::Text(kind.to_string())],
+    }
+}
+
+fn joined(kinds: &[FieldKind], separator: &str) -> Vec<Part> {
+    let mut parts = vec![];
+    for (i, kind) in kinds.iter().enumerate() {
+        if i > 0 {
+            parts.push(Part::Text(separator.to_owned()));
+        }
+        parts.extend(kind_parts(kind));
+    }
+    parts
+}
+
+/// Page names are object names, prefixed with their schema file when several files use the name.
+fn slugs(reference_map: &BTreeMap<String, ObjectDefinition>) -> BTreeMap<String, String> {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for definition in reference_map.values() {
+        *counts.entry(&definition.name).or_default() += 1;
+    }
+
+    let mut taken = BTreeSet::new();
+    reference_map.iter()
+        .map(|(key, definition)| {
+            let name = if counts[definition.name.as_str()] > 1 {
+                format!("{}-{}", key.split('#').next().unwrap().trim_end_matches(".json"), definition.name)
+            } else {
+                definition.name.to_owned()
+            };
+            let base = name.chars()
+                .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' { c } else { '-' })
+                .collect::<String>();
+
+            let mut slug = base.clone();
+            let mut n = 2;
+            while !taken.insert(slug.clone()) {
+                slug = format!("{}-{}", base, n);
+                n += 1;
+            }
+            (key.to_owned(), slug)
+        })
+        .collect()
+}
+
+fn markdown_cell(s: &str) -> String {
+    s.replace('|', "\\|").replace('\n', " ")
+}
+
+fn markdown_type(parts: &[Part], link: &dyn Fn(&str) -> Option<String>) -> String {
+    parts.iter()
+        .map(|part| match part {
+            Part::Text(text) => markdown_cell(text),
+            Part::Link(key) => match link(key) {
+                Some(target) => format!("[{}]({})", short_name(key), target),
+                None => format!("{} (unresolved)", key),
+            },
+        })
+        .collect()
+}
+
+fn index_markdown(pages: &[Page], link: &dyn Fn(&str) -> Option<String>) -> String {
+    let mut out = String::from("# Objects\n\n| Object | Key | Description |\n|---|---|---|\n");
+    for page in pages {
+        out += &format!("| [{}]({}) | `{}` | {} |\n",
+                        page.name, link(&page.key).unwrap(), page.key,
+                        markdown_cell(page.description.as_deref().unwrap_or("")));
+    }
+    out
+}
+
+fn page_markdown(page: &Page, link: &dyn Fn(&str) -> Option<String>) -> String {
+    let mut out = format!("# {}\n\n`{}`\n\n", page.name, page.key);
+    if let Some(description) = &page.description {
+        out += &format!("{}\n\n", description);
+    }
+
+    if !page.includes.is_empty() {
+        let includes = page.includes.iter().map(|r| markdown_type(&[Part::Link(r.to_owned())], link)).collect::<Vec<_>>();
+        out += &format!("Includes {}.\n\n", includes.join(", "));
+    }
+
+    out += "## Fields\n\n| Field | Type | Required | Constraints | Description |\n|---|---|---|---|---|\n";
+    for field in &page.fields {
+        let constraints = field.constraints.iter().map(|c| format!("`{}`", markdown_cell(c))).collect::<Vec<_>>();
+        out += &format!("| `{}` | {} | {} | {} | {} |\n",
+                        field.name, markdown_type(&field.kind, link), if field.required { "yes" } else { "no" },
+                        constraints.join(", "), markdown_cell(field.description.as_deref().unwrap_or("")));
+    }
+
+    if !page.referrers.is_empty() {
+        out += "\n## Referenced by\n\n";
+        for (from, via) in &page.referrers {
+            out += &format!("- {} via `{}`\n", markdown_type(&[Part::Link(from.to_owned())], link), via);
+        }
+    }
+
+    out += "\n## Example\n\n";
+    match &page.example {
+        Some(example) => out += &format!("```json\n{}\n```\n", serde_json::to_string_pretty(example).unwrap()),
+        None => out += "No example, the object uses references, types or formats chimpsky cannot generate.\n",
+    }
+
+    out
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn html_type(parts: &[Part], link: &dyn Fn(&str) -> Option<String>) -> String {
+    parts.iter()
+        .map(|part| match part {
+            Part::Text(text) => escape_html(text),
+            Part::Link(key) => match link(key) {
+                Some(target) => format!("<a href=\"{}\">{}</a>", escape_html(&target), escape_html(short_name(key))),
+                None => format!("{} (unresolved)", escape_html(key)),
+            },
+        })
+        .collect()
+}
+
+fn html_document(title: &str, body: &str) -> String {
+    format!("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n\
+             body {{ font-family: sans-serif; max-width: 60em; margin: 2em auto; }}\n\
+             table {{ border-collapse: collapse; }}\n\
+             th, td {{ border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; vertical-align: top; }}\n\
+             pre {{ background: #f4f4f4; padding: 1em; }}\n\
+             </style>\n</head>\n<body>\n{}</body>\n</html>\n",
+            escape_html(title), body)
+}
+
+fn index_html(pages: &[Page], link: &dyn Fn(&str) -> Option<String>) -> String {
+    let mut body = String::from("<h1>Objects</h1>\n<table>\n<tr><th>Object</th><th>Key</th><th>Description</th></tr>\n");
+    for page in pages {
+        body += &format!("<tr><td><a href=\"{}\">{}</a></td><td><code>{}</code></td><td>{}</td></tr>\n",
+                         escape_html(&link(&page.key).unwrap()), escape_html(&page.name), escape_html(&page.key),
+                         escape_html(page.description.as_deref().unwrap_or("")));
+    }
+    body += "</table>\n";
+    html_document("Objects", &body)
+}
+
+fn page_html(page: &Page, link: &dyn Fn(&str) -> Option<String>) -> String {
+    let mut body = format!("<p><a href=\"index.html\">Objects</a></p>\n<h1>{}</h1>\n<p><code>{}</code></p>\n",
+                           escape_html(&page.name), escape_html(&page.key));
+    if let Some(description) = &page.description {
+        body += &format!("<p>{}</p>\n", escape_html(description));
+    }
+
+    if !page.includes.is_empty() {
+        let includes = page.includes.iter().map(|r| html_type(&[Part::Link(r.to_owned())], link)).collect::<Vec<_>>();
+        body += &format!("<p>Includes {}.</p>\n", includes.join(", "));
+    }
+
+    body += "<h2>Fields</h2>\n<table>\n<tr><th>Field</th><th>Type</th><th>Required</th><th>Constraints</th><th>Description</th></tr>\n";
+    for field in &page.fields {
+        let constraints = field.constraints.iter().map(|c| format!("<code>{}</code>", escape_html(c))).collect::<Vec<_>>();
+        body += &format!("<tr><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                         escape_html(&field.name), html_type(&field.kind, link), if field.required { "yes" } else { "no" },
+                         constraints.join("<br>"), escape_html(field.description.as_deref().unwrap_or("")));
+    }
+    body += "</table>\n";
+
+    if !page.referrers.is_empty() {
+        body += "<h2>Referenced by</h2>\n<ul>\n";
+        for (from, via) in &page.referrers {
+            body += &format!("<li>{} via <code>{}</code></li>\n", html_type(&[Part::Link(from.to_owned())], link), escape_html(via));
+        }
+        body += "</ul>\n";
+    }
+
+    body += "<h2>Example</h2>\n";
+    match &page.example {
+        Some(example) => body += &format!("<pre>{}</pre>\n", escape_html(&serde_json::to_string_pretty(example).unwrap())),
+        None => body += "<p>No example, the object uses references, types or formats chimpsky cannot generate.</p>\n",
+    }
+
+    html_document(&page.name, &body)
+}
+
+fn short_name(key: &str) -> &str {
+    key.rsplit('/').next().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::schema_parser::Schema;
+
+    use super::*;
+
+    fn schema_set() -> SchemaSet {
+        let mut set = SchemaSet::default();
+        set.add(Schema::new(&json!({
+            "definitions": {
+                "message": {
+                    "type": "object",
+                    "description": "A message | with a pipe",
+                    "properties": {
+                        "version": { "type": "number", "minimum": 1, "description": "schema version" },
+                        "carried": { "$ref": "carried.json#/definitions/carried" },
+                        "amount": { "type": "decimal" }
+                    },
+                    "required": ["version"]
+                },
+                "thread": {
+                    "type": "object",
+                    "properties": { "reply": { "$ref": "#/definitions/thread" } },
+                    "required": ["reply"]
+                }
+            },
+            "allOf": [ { "$ref": "#/definitions/message" } ]
+        }), Some("message.json".to_owned()))).unwrap();
+        set.add(Schema::new(&json!({
+            "type": "object",
+            "properties": { "id": { "type": "string", "format": "uuid" } },
+            "required": ["id"]
+        }), Some("carried.json".to_owned()))).unwrap();
+        set
+    }
+
+    fn file<'a>(files: &'a [(String, String)], name: &str) -> &'a str {
+        &files.iter().find(|(f, _)| f == name).unwrap().1
+    }
+
+    #[test]
+    fn markdown_pages() {
+        let files = render(&schema_set(), DocsFormat::Markdown);
+        let names = files.iter().map(|(f, _)| f.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["index.md", "carried.md", "message.md", "thread.md"]);
+
+        let message = file(&files, "message.md");
+        assert!(message.contains("A message | with a pipe\n"));
+        assert!(message.contains("| `version` | number | yes | `minimum: 1` | schema version |\n"));
+        assert!(message.contains("| `carried` | [carried](carried.md) | no |  |  |\n"));
+        assert!(message.contains("No example"));
+        assert!(file(&files, "thread.md").contains("No example"));
+
+        let carried = file(&files, "carried.md");
+        assert!(carried.contains("| `id` | string | yes | `format: uuid` |  |\n"));
+        assert!(carried.contains("- [message](message.md) via `carried`\n"));
+        assert!(carried.contains("```json\n{\n  \"id\": "));
+
+        assert!(file(&files, "index.md").contains("| [message](message.md) | `message.json#/definitions/message` | A message \\| with a pipe |\n"));
+    }
+
+    #[test]
+    fn html_pages() {
+        let files = render(&schema_set(), DocsFormat::Html);
+        let message = file(&files, "message.html");
+        assert!(message.starts_with("<!DOCTYPE html>"));
+        assert!(message.contains("<td><a href=\"carried.html\">carried</a></td>"));
+        assert!(file(&files, "carried.html").contains("<pre>{\n  &quot;id&quot;: "));
+    }
+}
@@ -0,0 +1,74 @@
+use serde_json::Value;
+
+/// Binary encodings of generated payloads. Numbers keep the distinction serde_json makes between
+/// integers and floats, so `FieldKind::Int` values are written as integers and `FieldKind::Float`
+/// values as floats even when they happen to have no fractional part.
+pub fn to_messagepack(payload: &Value) -> Result<Vec<u8>, String> {
+    rmp_serde::to_vec_named(payload).map_err(|e| format!("cannot encode payload as MessagePack: {}", e))
+}
+
+pub fn to_cbor(payload: &Value) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+    ciborium::ser::into_writer(payload, &mut bytes).map_err(|e| format!("cannot encode payload as CBOR: {}", e))?;
+    Ok(bytes)
+}
+
+/// BSON can only hold documents at the top level, so payloads that are not objects are rejected.
+pub fn to_bson(payload: &Value) -> Result<Vec<u8>, String> {
+    bson::to_vec(payload).map_err(|e| format!("cannot encode payload as BSON: {}", e))
+}
+
+/// Prefixes an encoded payload with its length as a big endian u32, so a stream of payloads can
+/// be split back into documents without decoding them.
+pub fn length_prefixed(bytes: Vec<u8>) -> Vec<u8> {
+    let mut framed = (bytes.len() as u32).to_be_bytes().to_vec();
+    framed.extend(bytes);
+    framed
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn payload() -> Value {
+        json!({ "count": 3, "ratio": 2.0, "name": "x", "missing": null, "tags": ["a"] })
+    }
+
+    #[test]
+    fn messagepack_keeps_ints_and_floats() {
+        let bytes = to_messagepack(&payload()).unwrap();
+        let decoded: rmpv::Value = rmpv::decode::read_value(&mut bytes.as_slice()).unwrap();
+        let map = decoded.as_map().unwrap();
+        assert!(map[0].1.is_u64());
+        assert!(map[1].1.is_f64());
+
+        let back: Value = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(back, payload());
+    }
+
+    #[test]
+    fn cbor_keeps_ints_and_floats() {
+        let bytes = to_cbor(&payload()).unwrap();
+        let decoded: ciborium::value::Value = ciborium::de::from_reader(bytes.as_slice()).unwrap();
+        let map = decoded.as_map().unwrap();
+        assert!(map[0].1.is_integer());
+        assert!(map[1].1.is_float());
+    }
+
+    #[test]
+    fn bson_documents() {
+        let bytes = to_bson(&payload()).unwrap();
+        let document = bson::Document::from_reader(bytes.as_slice()).unwrap();
+        assert_eq!(document.get_i64("count").unwrap(), 3);
+        assert_eq!(document.get_f64("ratio").unwrap(), 2.0);
+
+        assert!(to_bson(&json!([1, 2])).is_err());
+    }
+
+    #[test]
+    fn framing() {
+        assert_eq!(length_prefixed(vec![7, 8, 9]), vec![0, 0, 0, 3, 7, 8, 9]);
+    }
+}
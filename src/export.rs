@@ -0,0 +1,52 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use crate::avro::AvroSchema;
+use crate::object_definitions::ObjectDefinition;
+use crate::proto::ProtoSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportTarget {
+    Avro,
+    Proto,
+}
+
+impl FromStr for ExportTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "avro" => Ok(ExportTarget::Avro),
+            "proto" => Ok(ExportTarget::Proto),
+            _ => Err(format!("unknown export target {}, expected avro or proto", s)),
+        }
+    }
+}
+
+/// A field whose JSON representation the exported schema cannot reproduce exactly.
+#[derive(Debug)]
+pub struct Loss {
+    pub object: String,
+    pub field: String,
+    pub message: String,
+}
+
+pub struct Exported {
+    pub schema: String,
+    pub losses: Vec<Loss>,
+}
+
+/// Converts the objects with the given keys, and everything they refer to, into a schema of
+/// another serialization system.
+pub fn export(target: ExportTarget, keys: &[&str], reference_map: &BTreeMap<String, ObjectDefinition>) -> Result<Exported, String> {
+    match target {
+        ExportTarget::Avro => {
+            let schema = AvroSchema::new(keys, reference_map)?;
+            Ok(Exported { schema: serde_json::to_string_pretty(&schema.to_json()).unwrap() + "\n", losses: vec![] })
+        }
+        ExportTarget::Proto => {
+            let schema = ProtoSchema::new(keys, reference_map)?;
+            Ok(Exported { schema: schema.render(), losses: schema.losses })
+        }
+    }
+}
@@ -1,10 +1,58 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use serde_json::{json, Value};
 
+use crate::dataset;
 use crate::field_kinds::FieldKind;
 use crate::object_definitions::ObjectDefinition;
+use crate::overrides;
 use crate::random_values;
+use crate::realistic;
+
+/// Keywords that only annotate a schema and never influence generated values.
+pub const ANNOTATION_KEYWORDS: &[&str] = &["title", "description", "$comment", "examples", "$schema", "$id", "x-proto-number"];
+
+/// Values of `format` that chimpsky can generate.
+pub const SUPPORTED_FORMATS: &[&str] = &["uuid", "date-time", "date", "time", "hex-string"];
+
+/// Values of `format` that chimpsky only generates in realistic mode.
+pub const REALISTIC_FORMATS: &[&str] = &["email", "uri", "ipv4"];
+
+const SUPPORTED_KEYWORDS: &[&str] = &["type", "format", "pattern", "$ref", "items", "enum", "const", "minimum", "maximum", "x-chimpsky-sequence", "x-chimpsky-ref"];
+
+/// Inclusive bounds of the numbers of a field, from its `minimum` and `maximum` keywords.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Bounds {
+    pub minimum: Option<f64>,
+    pub maximum: Option<f64>,
+}
+
+impl Bounds {
+    pub fn is_set(self) -> bool {
+        self.minimum.is_some() || self.maximum.is_some()
+    }
+
+    pub fn contains(self, n: f64) -> bool {
+        self.minimum.is_none_or(|minimum| n >= minimum) && self.maximum.is_none_or(|maximum| n <= maximum)
+    }
+
+    /// The range numbers are generated in. Where a bound is missing, the range starts at zero or
+    /// is `width` wide, like the range of fields without bounds.
+    pub fn range(self, width: f64) -> (f64, f64) {
+        let low = match (self.minimum, self.maximum) {
+            (Some(minimum), _) => minimum,
+            (None, Some(maximum)) if maximum < 0.0 => maximum - width,
+            (None, _) => 0.0,
+        };
+        (low, self.maximum.unwrap_or(low + width))
+    }
+
+    /// The number closest to zero within the bounds.
+    pub fn target(self) -> f64 {
+        let (low, high) = (self.minimum.unwrap_or(f64::MIN), self.maximum.unwrap_or(f64::MAX));
+        0.0_f64.max(low).min(high)
+    }
+}
 
 #[derive(Debug)]
 pub struct FieldDefinition {
@@ -12,6 +60,19 @@ pub struct FieldDefinition {
     pub format: Option<String>,
     pub pattern: Option<String>,
     pub kind: Option<FieldKind>,
+    pub enum_values: Option<Vec<Value>>,
+    pub const_value: Option<Value>,
+    pub bounds: Bounds,
+    /// The `x-chimpsky-sequence` keyword, see `overrides::sequence_of`.
+    pub sequence: Option<Value>,
+    /// The `x-chimpsky-ref` keyword, see `dataset::ForeignKey::of`.
+    pub foreign_key: Option<Value>,
+    /// The `x-proto-number` keyword, see `proto::ProtoSchema`.
+    pub proto_number: Option<Value>,
+    /// Key of the definition declaring the field, known once its schema is exported into a
+    /// reference map.
+    pub owner: String,
+    pub unsupported: Vec<String>,
 }
 
 impl FieldDefinition {
@@ -24,6 +85,14 @@ impl FieldDefinition {
             format: None,
             pattern: None,
             kind: None,
+            enum_values: None,
+            const_value: None,
+            bounds: Bounds::default(),
+            sequence: None,
+            foreign_key: None,
+            proto_number: None,
+            owner: String::new(),
+            unsupported: vec![],
         };
 
         for (k, v) in node {
@@ -42,14 +111,64 @@ impl FieldDefinition {
             if k == "pattern" {
                 fd.pattern = Some(v.as_str().unwrap().to_owned());
             }
+
+            if k == "enum" {
+                fd.enum_values = Some(v.as_array().unwrap().to_owned());
+            }
+
+            if k == "const" {
+                fd.const_value = Some(v.to_owned());
+            }
+
+            if k == "minimum" {
+                fd.bounds.minimum = v.as_f64();
+            }
+
+            if k == "maximum" {
+                fd.bounds.maximum = v.as_f64();
+            }
+
+            if k == "x-chimpsky-sequence" {
+                fd.sequence = Some(v.to_owned());
+            }
+
+            if k == "x-chimpsky-ref" {
+                fd.foreign_key = Some(v.to_owned());
+            }
+
+            if k == "x-proto-number" {
+                fd.proto_number = Some(v.to_owned());
+            }
+
+            if !SUPPORTED_KEYWORDS.contains(&k.as_str()) && !ANNOTATION_KEYWORDS.contains(&k.as_str()) {
+                fd.unsupported.push(k.to_owned());
+            }
         }
 
         fd
     }
 
-    pub fn generate_json_elements(&self, reference_map: Option<&HashMap<String, ObjectDefinition>>) -> (String, Value) {
+    /// Values configured through `overrides`, sequences and foreign keys win over the ones the
+    /// schema describes.
+    pub fn generate_json_elements(&self, reference_map: Option<&BTreeMap<String, ObjectDefinition>>) -> (String, Value) {
+        let foreign_key = || dataset::draw_for(self);
+        overrides::within(&self.name, || match overrides::generate(self).or_else(foreign_key) {
+            Some(value) => (self.name.to_owned(), value),
+            None => realistic::within(&self.name, || self.generate_from_schema(reference_map)),
+        })
+    }
+
+    fn generate_from_schema(&self, reference_map: Option<&BTreeMap<String, ObjectDefinition>>) -> (String, Value) {
         let name = self.name.to_owned();
 
+        if let Some(value) = &self.const_value {
+            return (name, value.clone());
+        }
+
+        if let Some(values) = self.enum_values.as_ref().filter(|values| !values.is_empty()) {
+            return (name, random_values::element_from_collection(values).clone());
+        }
+
         if let Some(format) = &self.format {
             return self.generate_by_format(format);
         }
@@ -65,7 +184,7 @@ impl FieldDefinition {
         let v = match self.kind.as_ref() {
             None => json!(()),
             Some(k) => {
-                random_values::value_of_kind(k, reference_map)
+                random_values::value_of_kind_within(k, self.bounds, reference_map)
             }
         };
 
@@ -77,8 +196,14 @@ impl FieldDefinition {
         match format {
             "uuid" => (name, json!(random_values::uuid4())),
             "date-time" => (name, json!(random_values::datetime())),
+            "date" => (name, json!(random_values::date())),
+            "time" => (name, json!(random_values::time())),
             "hex-string" => (name, json!(random_values::string())),
-            _ => panic!(format!("unsupported format {}", format))
+            "email" | "uri" | "ipv4" => match realistic::format(format) {
+                Some(value) => (name, json!(value)),
+                None => panic!("format {} is only generated in realistic mode", format),
+            },
+            _ => panic!("unsupported format {}", format)
         }
     }
 
@@ -86,12 +211,12 @@ impl FieldDefinition {
         (self.name.to_owned(), json!(random_values::string_matching_pattern(pattern)))
     }
 
-    fn generate_by_reference(&self, reference: &str, reference_map: Option<&HashMap<String, ObjectDefinition>>) -> (String, Value) {
+    fn generate_by_reference(&self, reference: &str, reference_map: Option<&BTreeMap<String, ObjectDefinition>>) -> (String, Value) {
         match reference_map {
-            None => panic!(format!("cannot resolve reference {} without a reference map", reference)),
+            None => panic!("cannot resolve reference {} without a reference map", reference),
             Some(refmap) => {
-                let definition = refmap.get(reference).expect(format!("cannot resolve {}", reference).as_str());
-                return (self.name.to_owned(), definition.generate_json(reference_map).unwrap());
+                let definition = refmap.get(reference).unwrap_or_else(|| panic!("cannot resolve {}", reference));
+                (self.name.to_owned(), definition.generate_json(reference_map).unwrap())
             }
         }
     }
@@ -153,6 +278,60 @@ mod tests {
         }
     }
 
+    #[test]
+    fn unsupported_keywords_are_collected() {
+        let v: Value = serde_json::from_str(r#"
+        {
+            "version": { "type": "number", "multipleOf": 2, "exclusiveMaximum": 9, "description": "schema version" }
+         }
+        "#).unwrap();
+        let fds = parse_field_definitions(&v);
+        let mut unsupported = fds[0].unsupported.clone();
+        unsupported.sort();
+        assert_eq!(unsupported, vec!["exclusiveMaximum", "multipleOf"]);
+    }
+
+    #[test]
+    fn numbers_stay_within_bounds() {
+        let v: Value = serde_json::from_str(r#"
+        {
+            "version": { "type": "integer", "minimum": 1, "maximum": 1 },
+            "level": { "type": ["number", "string"], "minimum": -2.5, "maximum": -0.5 },
+            "depth": { "type": "integer", "maximum": -20 }
+        }
+        "#).unwrap();
+        let fds = parse_field_definitions(&v);
+        assert!(fds.iter().all(|fd| fd.unsupported.is_empty()));
+        assert_eq!(fds[1].bounds, Bounds { minimum: Some(-2.5), maximum: Some(-0.5) });
+        assert_eq!(fds[1].bounds.target(), -0.5);
+
+        for _ in 0..50 {
+            assert_eq!(fds[0].generate_json_elements(None).1, json!(1));
+            let level = fds[1].generate_json_elements(None).1;
+            assert!(level.is_string() || (-2.5..=-0.5).contains(&level.as_f64().unwrap()), "{}", level);
+            assert!((-10020..=-20).contains(&fds[2].generate_json_elements(None).1.as_i64().unwrap()));
+        }
+    }
+
+    #[test]
+    fn enum_values_are_generated() {
+        let v: Value = serde_json::from_str(r#" { "state": { "type": "string", "enum": ["on", "off"] } } "#).unwrap();
+        let fds = parse_field_definitions(&v);
+        assert!(fds[0].unsupported.is_empty());
+
+        for _ in 0..20 {
+            let (_, value) = fds[0].generate_json_elements(None);
+            assert!(value == "on" || value == "off");
+        }
+    }
+
+    #[test]
+    fn const_value_is_generated() {
+        let v: Value = serde_json::from_str(r#" { "kind": { "type": "string", "const": "event" } } "#).unwrap();
+        let fds = parse_field_definitions(&v);
+        assert_eq!(fds[0].generate_json_elements(None).1, "event");
+    }
+
     #[test]
     fn string_kind_parsing() {
         let v: Value = serde_json::from_str(r#" { "some-id": { "type": "integer" } } "#).unwrap();
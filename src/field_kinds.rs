@@ -1,5 +1,9 @@
+use std::fmt;
+
 use serde_json::{Map, Value};
 
+use crate::schema_parser::qualify_reference;
+
 #[derive(Debug)]
 pub enum FieldKind {
     Str,
@@ -11,17 +15,18 @@ pub enum FieldKind {
     OneOf(Vec<FieldKind>),
     Reference(String),
     ListOf(Vec<FieldKind>),
+    Unknown(String),
 }
 
 impl FieldKind {
     pub fn new(v: &Value, parent_node: &Map<String, Value>) -> Self {
         match v {
             Value::String(s) if s == "array" => Self::parse_array_definition(parent_node),
-            Value::String(s) => FieldKind::match_value(&s),
+            Value::String(s) => FieldKind::match_value(s),
             Value::Array(v) => {
                 let field_names = v.iter()
                     .map(|v| v.as_str().unwrap())
-                    .map(FieldKind::match_value)
+                    .map(|s| if s == "array" { Self::parse_array_definition(parent_node) } else { FieldKind::match_value(s) })
                     .collect();
                 FieldKind::OneOf(field_names)
             }
@@ -29,6 +34,39 @@ impl FieldKind {
         }
     }
 
+    pub fn qualify_references(&mut self, filename: &str) {
+        match self {
+            FieldKind::Reference(r) => *r = qualify_reference(filename, r),
+            FieldKind::OneOf(kinds) | FieldKind::ListOf(kinds) =>
+                kinds.iter_mut().for_each(|k| k.qualify_references(filename)),
+            _ => {}
+        }
+    }
+
+    /// Type names anywhere inside this kind that chimpsky does not recognise.
+    pub fn unknown_types(&self) -> Vec<&str> {
+        match self {
+            FieldKind::Unknown(s) => vec![s.as_str()],
+            FieldKind::OneOf(kinds) | FieldKind::ListOf(kinds) =>
+                kinds.iter().flat_map(|k| k.unknown_types()).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// References to other definitions anywhere inside this kind.
+    pub fn references(&self) -> Vec<&str> {
+        match self {
+            FieldKind::Reference(r) => vec![r.as_str()],
+            FieldKind::OneOf(kinds) | FieldKind::ListOf(kinds) =>
+                kinds.iter().flat_map(|k| k.references()).collect(),
+            _ => vec![],
+        }
+    }
+
+    pub fn is_known_type(s: &str) -> bool {
+        s == "array" || !matches!(Self::match_value(s), FieldKind::Unknown(_))
+    }
+
     fn match_value(s: &str) -> FieldKind {
         match s {
             "string" => FieldKind::Str,
@@ -37,21 +75,21 @@ impl FieldKind {
             "boolean" => FieldKind::Bool,
             "object" => FieldKind::Object,
             "null" => FieldKind::Null,
-            _ => panic!(format!("unknown type {}", s))
+            _ => FieldKind::Unknown(s.to_owned())
         }
     }
 
     fn parse_array_definition(node: &Map<String, Value>) -> FieldKind {
-        let items_node = node["items"].as_object().unwrap();
+        let empty = Map::new();
+        let items_node = node.get("items").and_then(|items| items.as_object()).unwrap_or(&empty);
         let mut kinds = vec![];
         for (k, v) in items_node {
-            let v = v.as_str().unwrap();
             if k == "type" {
-                kinds.push(Self::match_value(v));
+                kinds.push(Self::new(v, items_node));
             }
 
             if k == "$ref" {
-                kinds.push(FieldKind::Reference(v.to_owned()));
+                kinds.push(FieldKind::Reference(v.as_str().unwrap().to_owned()));
             }
         }
 
@@ -59,6 +97,27 @@ impl FieldKind {
     }
 }
 
+impl fmt::Display for FieldKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FieldKind::Str => write!(f, "string"),
+            FieldKind::Int => write!(f, "integer"),
+            FieldKind::Float => write!(f, "number"),
+            FieldKind::Bool => write!(f, "boolean"),
+            FieldKind::Object => write!(f, "object"),
+            FieldKind::Null => write!(f, "null"),
+            FieldKind::OneOf(kinds) => write!(f, "{}", join_kinds(kinds)),
+            FieldKind::Reference(r) => write!(f, "$ref {}", r),
+            FieldKind::ListOf(kinds) => write!(f, "array of {}", join_kinds(kinds)),
+            FieldKind::Unknown(s) => write!(f, "unknown type {}", s),
+        }
+    }
+}
+
+fn join_kinds(kinds: &[FieldKind]) -> String {
+    kinds.iter().map(|k| k.to_string()).collect::<Vec<_>>().join(" | ")
+}
+
 #[cfg(test)]
 mod tests {
     use serde_json::json;
@@ -81,6 +140,21 @@ mod tests {
         assert!(matches!(k, FieldKind::Bool));
     }
 
+    #[test]
+    fn display_kinds() {
+        let k = FieldKind::ListOf(vec![FieldKind::OneOf(vec![FieldKind::Str, FieldKind::Null])]);
+        assert_eq!(k.to_string(), "array of string | null");
+        assert_eq!(FieldKind::Reference("a#/b".to_owned()).to_string(), "$ref a#/b");
+    }
+
+    #[test]
+    fn unknown_def() {
+        let t = json!(["string", "decimal"]);
+        let v = json!({"type": t});
+        let k = FieldKind::new(&t, v.as_object().unwrap());
+        assert_eq!(k.unknown_types(), vec!["decimal"]);
+    }
+
     #[test]
     fn list_def() {
         let t = json!("array");
@@ -98,4 +172,15 @@ mod tests {
             _ => panic!()
         }
     }
+
+    #[test]
+    fn nested_list_defs() {
+        let t = json!(["array", "null"]);
+        let v = json!({
+            "type": t,
+            "items": { "type": "array", "items": { "type": ["string", "integer"] } }
+        });
+        let k = FieldKind::new(&t, v.as_object().unwrap());
+        assert_eq!(k.to_string(), "array of array of string | integer | null");
+    }
 }
\ No newline at end of file
@@ -0,0 +1,503 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use parquet::basic::{ConvertedType, Repetition, Type as PhysicalType};
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::types::Type;
+use serde_json::Value;
+
+use crate::field_kinds::FieldKind;
+use crate::object_definitions::ObjectDefinition;
+use crate::output::{OutputFormat, OutputOptions, PayloadSink};
+
+const ROW_GROUP_SIZE: usize = 10000;
+
+/// How arrays are laid out when a payload is flattened into columns.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArrayMode {
+    /// One row per array element, repeating the other columns.
+    Explode,
+    /// The whole array as a JSON encoded cell.
+    Json,
+    /// One column per array position, `name.0`, `name.1`, ...
+    Index,
+}
+
+impl FromStr for ArrayMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "explode" => Ok(ArrayMode::Explode),
+            "json" => Ok(ArrayMode::Json),
+            "index" => Ok(ArrayMode::Index),
+            _ => Err(format!("unknown array mode {}, expected explode, json or index", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnKind {
+    Int,
+    Float,
+    Bool,
+    Text,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub kind: ColumnKind,
+}
+
+/// The shape of an object definition as far as flattening is concerned. References are
+/// expanded into nested objects so their fields become dot separated columns.
+#[derive(Debug)]
+enum Node {
+    Leaf(ColumnKind),
+    Object(Vec<(String, Node)>),
+    Array(ColumnKind),
+}
+
+pub struct Flattener {
+    fields: Vec<(String, Node)>,
+    array_mode: ArrayMode,
+    array_columns: usize,
+}
+
+impl Flattener {
+    pub fn new(definition: &ObjectDefinition,
+               reference_map: &BTreeMap<String, ObjectDefinition>,
+               array_mode: ArrayMode,
+               array_columns: usize) -> Self {
+        Flattener {
+            fields: object_fields(definition, reference_map, &mut vec![]),
+            array_mode,
+            array_columns,
+        }
+    }
+
+    /// Columns come from the definition rather than from any particular payload, so every
+    /// payload of the same object flattens to the same header.
+    pub fn columns(&self) -> Vec<Column> {
+        let mut columns = vec![];
+        self.collect_columns(&self.fields, "", &mut columns);
+        columns
+    }
+
+    /// Flattens a payload into one row, or several when arrays are exploded. Cells are `None`
+    /// where the payload has no value.
+    pub fn rows(&self, payload: &Value) -> Vec<Vec<Option<Value>>> {
+        self.object_rows(&self.fields, Some(payload))
+    }
+
+    fn collect_columns(&self, fields: &[(String, Node)], prefix: &str, columns: &mut Vec<Column>) {
+        for (name, node) in fields {
+            let name = format!("{}{}", prefix, name);
+            match node {
+                Node::Leaf(kind) => columns.push(Column { name, kind: *kind }),
+                Node::Object(fields) => self.collect_columns(fields, &format!("{}.", name), columns),
+                Node::Array(kind) => match self.array_mode {
+                    ArrayMode::Json => columns.push(Column { name, kind: ColumnKind::Text }),
+                    ArrayMode::Explode => columns.push(Column { name, kind: *kind }),
+                    ArrayMode::Index => (0..self.array_columns)
+                        .for_each(|i| columns.push(Column { name: format!("{}.{}", name, i), kind: *kind })),
+                },
+            }
+        }
+    }
+
+    fn object_rows(&self, fields: &[(String, Node)], value: Option<&Value>) -> Vec<Vec<Option<Value>>> {
+        let mut rows = vec![vec![]];
+
+        for (name, node) in fields {
+            let field = value.and_then(|v| v.get(name)).filter(|v| !v.is_null());
+            let parts = match node {
+                Node::Leaf(_) => vec![vec![field.cloned()]],
+                Node::Object(fields) => self.object_rows(fields, field),
+                Node::Array(_) => self.array_rows(field),
+            };
+
+            rows = rows.into_iter()
+                .flat_map(|row| parts.iter().map(move |part| {
+                    let mut row = row.clone();
+                    row.extend(part.iter().cloned());
+                    row
+                }))
+                .collect();
+        }
+
+        rows
+    }
+
+    fn array_rows(&self, value: Option<&Value>) -> Vec<Vec<Option<Value>>> {
+        let items = value.and_then(|v| v.as_array());
+        match self.array_mode {
+            ArrayMode::Json => vec![vec![value.cloned()]],
+            ArrayMode::Index => vec![(0..self.array_columns)
+                .map(|i| items.and_then(|items| items.get(i)).cloned())
+                .collect()],
+            ArrayMode::Explode => match items {
+                Some(items) if !items.is_empty() => items.iter().map(|v| vec![Some(v.clone())]).collect(),
+                _ => vec![vec![None]],
+            },
+        }
+    }
+}
+
+fn object_fields(definition: &ObjectDefinition,
+                 reference_map: &BTreeMap<String, ObjectDefinition>,
+                 stack: &mut Vec<String>) -> Vec<(String, Node)> {
+    let mut fields: Vec<(String, Node)> = vec![];
+
+    for r in definition.references.iter().flatten() {
+        if let Some(referenced) = reference_map.get(r).filter(|_| !stack.contains(r)) {
+            stack.push(r.to_owned());
+            fields.extend(object_fields(referenced, reference_map, stack));
+            stack.pop();
+        }
+    }
+
+    for field in definition.field_definitions.iter().flatten() {
+        if fields.iter().any(|(name, _)| *name == field.name) {
+            continue;
+        }
+
+        let node = if field.format.is_some() || field.pattern.is_some() {
+            Node::Leaf(ColumnKind::Text)
+        } else {
+            match &field.kind {
+                None => Node::Leaf(ColumnKind::Text),
+                Some(kind) => kind_node(kind, reference_map, stack),
+            }
+        };
+        fields.push((field.name.to_owned(), node));
+    }
+
+    fields
+}
+
+fn kind_node(kind: &FieldKind, reference_map: &BTreeMap<String, ObjectDefinition>, stack: &mut Vec<String>) -> Node {
+    match kind {
+        FieldKind::Reference(r) => match reference_map.get(r) {
+            Some(referenced) if !stack.contains(r) => {
+                stack.push(r.to_owned());
+                let fields = object_fields(referenced, reference_map, stack);
+                stack.pop();
+                Node::Object(fields)
+            }
+            _ => Node::Leaf(ColumnKind::Text),
+        },
+        FieldKind::ListOf(kinds) => Node::Array(common_kind(kinds)),
+        kind => Node::Leaf(column_kind(kind)),
+    }
+}
+
+fn column_kind(kind: &FieldKind) -> ColumnKind {
+    match kind {
+        FieldKind::Int => ColumnKind::Int,
+        FieldKind::Float => ColumnKind::Float,
+        FieldKind::Bool => ColumnKind::Bool,
+        FieldKind::OneOf(kinds) => {
+            let kinds = kinds.iter().filter(|k| !matches!(k, FieldKind::Null)).collect::<Vec<_>>();
+            match kinds.as_slice() {
+                [kind] => column_kind(kind),
+                _ => ColumnKind::Text,
+            }
+        }
+        _ => ColumnKind::Text,
+    }
+}
+
+fn common_kind(kinds: &[FieldKind]) -> ColumnKind {
+    match kinds {
+        [kind] => column_kind(kind),
+        _ => ColumnKind::Text,
+    }
+}
+
+/// Text form of a cell, with objects and arrays JSON encoded and missing values left empty.
+pub fn cell_text(cell: &Option<Value>) -> String {
+    match cell {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => s.to_owned(),
+        Some(v) => v.to_string(),
+    }
+}
+
+enum TableOutput {
+    Csv(csv::Writer<Box<dyn Write>>),
+    Parquet { writer: SerializedFileWriter<Box<dyn Write + Send>>, rows: Vec<Vec<Option<Value>>> },
+}
+
+/// Writes payloads as rows of a CSV or Parquet table whose columns are derived from the
+/// object definition.
+pub struct TableWriter {
+    flattener: Flattener,
+    columns: Vec<Column>,
+    output: TableOutput,
+    files: Vec<PathBuf>,
+}
+
+impl TableWriter {
+    pub fn new(format: OutputFormat,
+               options: &OutputOptions,
+               definition: &ObjectDefinition,
+               reference_map: &BTreeMap<String, ObjectDefinition>) -> Result<Self, String> {
+        if options.rotate_count.is_some() || options.rotate_size.is_some() {
+            return Err("rotation is not supported for csv and parquet output".to_owned());
+        }
+
+        let flattener = Flattener::new(definition, reference_map, options.array_mode, options.array_columns);
+        let columns = flattener.columns();
+
+        let (out, files): (Box<dyn Write + Send>, _) = match &options.output {
+            Some(path) if path.is_dir() => return Err("csv and parquet output must be written to a file".to_owned()),
+            Some(path) => {
+                let file = File::create(path).map_err(|e| format!("cannot create {}: {}", path.display(), e))?;
+                (Box::new(BufWriter::new(file)), vec![path.to_owned()])
+            }
+            None => (Box::new(BufWriter::new(io::stdout())), vec![]),
+        };
+
+        let output = match format {
+            OutputFormat::Csv => {
+                let out: Box<dyn Write> = out;
+                let mut writer = csv::Writer::from_writer(out);
+                writer.write_record(columns.iter().map(|c| &c.name)).map_err(|e| e.to_string())?;
+                TableOutput::Csv(writer)
+            }
+            OutputFormat::Parquet => {
+                let schema = Arc::new(parquet_schema(&columns).map_err(|e| e.to_string())?);
+                let writer = SerializedFileWriter::new(out, schema, Arc::new(WriterProperties::builder().build()))
+                    .map_err(|e| e.to_string())?;
+                TableOutput::Parquet { writer, rows: vec![] }
+            }
+            _ => return Err(format!("{:?} is not a tabular output format", format)),
+        };
+
+        Ok(TableWriter { flattener, columns, output, files })
+    }
+
+    fn flush_row_group(writer: &mut SerializedFileWriter<Box<dyn Write + Send>>,
+                       columns: &[Column],
+                       rows: &mut Vec<Vec<Option<Value>>>) -> parquet::errors::Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        let mut row_group = writer.next_row_group()?;
+        for (index, column) in columns.iter().enumerate() {
+            let mut column_writer = row_group.next_column()?.unwrap();
+            let cells = rows.iter().map(|row| row[index].as_ref().filter(|v| !v.is_null()));
+            let levels = cells.clone().map(|c| c.is_some() as i16).collect::<Vec<_>>();
+            // cells were checked against their column in `write`
+            let present = cells.flatten();
+
+            match column.kind {
+                ColumnKind::Int => {
+                    let values = present.map(|v| v.as_i64().unwrap()).collect::<Vec<_>>();
+                    column_writer.typed::<Int64Type>().write_batch(&values, Some(&levels), None)?;
+                }
+                ColumnKind::Float => {
+                    let values = present.map(|v| v.as_f64().unwrap()).collect::<Vec<_>>();
+                    column_writer.typed::<DoubleType>().write_batch(&values, Some(&levels), None)?;
+                }
+                ColumnKind::Bool => {
+                    let values = present.map(|v| v.as_bool().unwrap()).collect::<Vec<_>>();
+                    column_writer.typed::<BoolType>().write_batch(&values, Some(&levels), None)?;
+                }
+                ColumnKind::Text => {
+                    let values = present.map(|v| ByteArray::from(cell_text(&Some(v.clone())).as_str())).collect::<Vec<_>>();
+                    column_writer.typed::<ByteArrayType>().write_batch(&values, Some(&levels), None)?;
+                }
+            }
+            column_writer.close()?;
+        }
+        row_group.close()?;
+
+        rows.clear();
+        Ok(())
+    }
+}
+
+impl PayloadSink for TableWriter {
+    fn write(&mut self, payload: &Value) -> io::Result<()> {
+        let rows = self.flattener.rows(payload);
+
+        match &mut self.output {
+            TableOutput::Csv(writer) => {
+                for row in rows {
+                    writer.write_record(row.iter().map(cell_text))?;
+                }
+            }
+            TableOutput::Parquet { writer, rows: buffered } => {
+                for row in &rows {
+                    check_types(&self.columns, row).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                }
+                buffered.extend(rows);
+                if buffered.len() >= ROW_GROUP_SIZE {
+                    Self::flush_row_group(writer, &self.columns, buffered).map_err(to_io_error)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<Vec<PathBuf>> {
+        let TableWriter { columns, output, files, .. } = *self;
+        match output {
+            TableOutput::Csv(mut writer) => writer.flush()?,
+            TableOutput::Parquet { mut writer, mut rows } => {
+                Self::flush_row_group(&mut writer, &columns, &mut rows).map_err(to_io_error)?;
+                writer.close().map_err(to_io_error)?;
+            }
+        }
+        Ok(files)
+    }
+}
+
+fn parquet_schema(columns: &[Column]) -> parquet::errors::Result<Type> {
+    let fields = columns.iter()
+        .map(|column| {
+            let builder = match column.kind {
+                ColumnKind::Int => Type::primitive_type_builder(&column.name, PhysicalType::INT64),
+                ColumnKind::Float => Type::primitive_type_builder(&column.name, PhysicalType::DOUBLE),
+                ColumnKind::Bool => Type::primitive_type_builder(&column.name, PhysicalType::BOOLEAN),
+                ColumnKind::Text => Type::primitive_type_builder(&column.name, PhysicalType::BYTE_ARRAY)
+                    .with_converted_type(ConvertedType::UTF8),
+            };
+            builder.with_repetition(Repetition::OPTIONAL).build().map(Arc::new)
+        })
+        .collect::<parquet::errors::Result<Vec<_>>>()?;
+
+    Type::group_type_builder("payload").with_fields(fields).build()
+}
+
+/// Parquet columns are typed, so a value that does not fit its column, say an override of an
+/// integer field with a string, is an error rather than a silent zero.
+fn check_types(columns: &[Column], row: &[Option<Value>]) -> Result<(), String> {
+    for (column, value) in columns.iter().zip(row) {
+        let value = match value {
+            Some(value) if !value.is_null() => value,
+            _ => continue,
+        };
+        let expected = match column.kind {
+            ColumnKind::Int if value.as_i64().is_none() => "an integer",
+            ColumnKind::Float if value.as_f64().is_none() => "a number",
+            ColumnKind::Bool if !value.is_boolean() => "a boolean",
+            _ => continue,
+        };
+        return Err(format!("column {} expects {}, got {}", column.name, expected, value));
+    }
+    Ok(())
+}
+
+fn to_io_error(e: parquet::errors::ParquetError) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use serde_json::json;
+    use structopt::StructOpt;
+
+    use crate::object_definitions::parse_definitions;
+
+    use super::*;
+
+    fn reference_map() -> BTreeMap<String, ObjectDefinition> {
+        parse_definitions(&json!({
+            "carried": {
+                "type": "object",
+                "properties": { "version": { "type": "number" }, "at": { "type": "string", "format": "date-time" } }
+            },
+            "message": {
+                "type": "object",
+                "properties": {
+                    "id": { "type": "integer" },
+                    "carried": { "$ref": "carried" },
+                    "tags": { "type": "array", "items": { "type": "string" } },
+                    "flag": { "type": ["boolean", "null"] }
+                }
+            }
+        }))
+    }
+
+    fn names(columns: &[Column]) -> Vec<&str> {
+        columns.iter().map(|c| c.name.as_str()).collect()
+    }
+
+    fn payload() -> Value {
+        json!({ "id": 7, "carried": { "version": 1.5, "at": "2020-01-01T00:00:00Z" }, "tags": ["a", "b"], "flag": null })
+    }
+
+    #[test]
+    fn columns_follow_the_definition() {
+        let m = reference_map();
+        let flattener = Flattener::new(&m["message"], &m, ArrayMode::Json, 3);
+        assert_eq!(names(&flattener.columns()), vec!["id", "carried.version", "carried.at", "tags", "flag"]);
+        assert_eq!(flattener.columns()[1].kind, ColumnKind::Float);
+        assert_eq!(flattener.columns()[4].kind, ColumnKind::Bool);
+
+        let row = &flattener.rows(&payload())[0];
+        assert_eq!(row.iter().map(cell_text).collect::<Vec<_>>(),
+                   vec!["7", "1.5", "2020-01-01T00:00:00Z", "[\"a\",\"b\"]", ""]);
+    }
+
+    #[test]
+    fn array_modes() {
+        let m = reference_map();
+
+        let index = Flattener::new(&m["message"], &m, ArrayMode::Index, 3);
+        assert_eq!(names(&index.columns())[3..6], ["tags.0", "tags.1", "tags.2"]);
+        let row = &index.rows(&payload())[0];
+        assert_eq!(row[3..6].to_vec(), vec![Some(json!("a")), Some(json!("b")), None]);
+
+        let explode = Flattener::new(&m["message"], &m, ArrayMode::Explode, 3);
+        let rows = explode.rows(&payload());
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1][3], Some(json!("b")));
+        assert_eq!(rows[1][0], Some(json!(7)));
+
+        let empty = json!({ "id": 1, "tags": [] });
+        assert_eq!(explode.rows(&empty).len(), 1);
+    }
+
+    #[test]
+    fn writes_csv_and_parquet() {
+        let m = reference_map();
+        let dir = std::env::temp_dir().join(format!("chimpsky-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut options = OutputOptions::from_iter(&["randomize", "--array-mode", "explode"]);
+        options.output = Some(dir.join("payloads.csv"));
+        let mut writer: Box<dyn PayloadSink> = Box::new(TableWriter::new(OutputFormat::Csv, &options, &m["message"], &m).unwrap());
+        writer.write(&payload()).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("payloads.csv")).unwrap(),
+                   "id,carried.version,carried.at,tags,flag\n7,1.5,2020-01-01T00:00:00Z,a,\n7,1.5,2020-01-01T00:00:00Z,b,\n");
+
+        options.output = Some(dir.join("payloads.parquet"));
+        let mut writer: Box<dyn PayloadSink> = Box::new(TableWriter::new(OutputFormat::Parquet, &options, &m["message"], &m).unwrap());
+        writer.write(&payload()).unwrap();
+        writer.write(&json!({ "id": 8 })).unwrap();
+        assert_eq!(writer.write(&json!({ "id": "abc" })).unwrap_err().to_string(), "column id expects an integer, got \"abc\"");
+        writer.finish().unwrap();
+
+        let reader = SerializedFileReader::new(File::open(dir.join("payloads.parquet")).unwrap()).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 3);
+        assert_eq!(reader.metadata().file_metadata().schema_descr().num_columns(), 5);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
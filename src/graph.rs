@@ -0,0 +1,305 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+use crate::object_definitions::ObjectDefinition;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(GraphFormat::Dot),
+            "mermaid" => Ok(GraphFormat::Mermaid),
+            _ => Err(format!("unknown graph format {}, expected dot or mermaid", s)),
+        }
+    }
+}
+
+/// A reference from one definition to another, either through `allOf` or through a field.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    pub via: String,
+}
+
+pub struct DependencyGraph<'a> {
+    reference_map: &'a BTreeMap<String, ObjectDefinition>,
+    edges: BTreeSet<Edge>,
+}
+
+impl<'a> DependencyGraph<'a> {
+    pub fn new(reference_map: &'a BTreeMap<String, ObjectDefinition>) -> Self {
+        let mut edges = BTreeSet::new();
+
+        for (key, definition) in reference_map {
+            for r in definition.references.iter().flatten() {
+                edges.insert(Edge { from: key.to_owned(), to: r.to_owned(), via: "allOf".to_owned() });
+            }
+
+            for field in definition.field_definitions.iter().flatten() {
+                for r in field.kind.iter().flat_map(|k| k.references()) {
+                    edges.insert(Edge { from: key.to_owned(), to: r.to_owned(), via: field.name.to_owned() });
+                }
+            }
+        }
+
+        DependencyGraph { reference_map, edges }
+    }
+
+    /// References pointing at `key`, from definitions that embed it directly.
+    pub fn referrers(&self, key: &str) -> Vec<&Edge> {
+        self.edges.iter().filter(|e| e.to == key).collect()
+    }
+
+    /// Every definition that embeds `key`, directly or through other definitions.
+    pub fn dependents(&self, key: &str) -> BTreeSet<&str> {
+        let mut found = BTreeSet::new();
+        let mut pending = vec![key];
+
+        while let Some(current) = pending.pop() {
+            for edge in self.edges.iter().filter(|e| e.to == current) {
+                if found.insert(edge.from.as_str()) {
+                    pending.push(&edge.from);
+                }
+            }
+        }
+
+        found
+    }
+
+    /// Definitions that cannot be reached by following references from any of the roots.
+    pub fn unreachable(&self, roots: &BTreeSet<String>) -> Vec<&str> {
+        let mut seen = BTreeSet::new();
+        let mut pending = roots.iter().map(|r| r.as_str()).collect::<Vec<_>>();
+
+        while let Some(current) = pending.pop() {
+            if seen.insert(current) {
+                pending.extend(self.successors(current));
+            }
+        }
+
+        self.reference_map.keys()
+            .map(|k| k.as_str())
+            .filter(|k| !seen.contains(k))
+            .collect()
+    }
+
+    pub fn dangling(&self) -> Vec<&Edge> {
+        self.edges.iter()
+            .filter(|e| !self.reference_map.contains_key(&e.to))
+            .collect()
+    }
+
+    /// Groups of definitions that refer to each other in a loop, found as the strongly connected
+    /// components of the graph. A definition referring to itself forms a group of one.
+    pub fn cycles(&self) -> Vec<Vec<&str>> {
+        let mut tarjan = Tarjan::default();
+        for key in self.reference_map.keys() {
+            if !tarjan.index.contains_key(key.as_str()) {
+                tarjan.visit(self, key);
+            }
+        }
+
+        tarjan.components.into_iter()
+            .filter(|c| c.len() > 1 || self.successors(c[0]).any(|s| s == c[0]))
+            .map(|mut c| {
+                c.sort();
+                c
+            })
+            .collect()
+    }
+
+    pub fn render(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.to_dot(),
+            GraphFormat::Mermaid => self.to_mermaid(),
+        }
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph chimpsky {\n    rankdir=LR;\n    node [shape=box];\n");
+
+        for (key, definition) in self.reference_map {
+            out.push_str(&format!("    \"{}\" [label=\"{}\"];\n", escape(key), escape(&definition.name)));
+        }
+
+        for edge in self.dangling() {
+            out.push_str(&format!("    \"{}\" [label=\"{}\", style=dashed, color=red];\n", escape(&edge.to), escape(&edge.to)));
+        }
+
+        for edge in &self.edges {
+            out.push_str(&format!("    \"{}\" -> \"{}\" [label=\"{}\"];\n", escape(&edge.from), escape(&edge.to), escape(&edge.via)));
+        }
+
+        out.push_str("}\n");
+        out
+    }
+
+    pub fn to_mermaid(&self) -> String {
+        let mut ids: BTreeMap<&str, String> = BTreeMap::new();
+        let mut out = String::from("graph LR\n");
+
+        for (key, definition) in self.reference_map {
+            let id = format!("n{}", ids.len());
+            out.push_str(&format!("    {}[\"{}\"]\n", id, mermaid_label(&definition.name)));
+            ids.insert(key, id);
+        }
+
+        for edge in self.dangling() {
+            if !ids.contains_key(edge.to.as_str()) {
+                let id = format!("n{}", ids.len());
+                out.push_str(&format!("    {}[\"{}\"]:::dangling\n", id, mermaid_label(&edge.to)));
+                ids.insert(&edge.to, id);
+            }
+        }
+
+        for edge in &self.edges {
+            out.push_str(&format!("    {} -->|{}| {}\n", ids[edge.from.as_str()], mermaid_label(&edge.via), ids[edge.to.as_str()]));
+        }
+
+        out.push_str("    classDef dangling stroke:#f00,stroke-dasharray:5 5\n");
+        out
+    }
+
+    fn successors<'b>(&'b self, key: &'b str) -> impl Iterator<Item=&'b str> + 'b {
+        self.edges.iter()
+            .filter(move |e| e.from == key)
+            .map(|e| e.to.as_str())
+    }
+}
+
+#[derive(Default)]
+struct Tarjan<'a> {
+    index: BTreeMap<&'a str, usize>,
+    low_link: BTreeMap<&'a str, usize>,
+    stack: Vec<&'a str>,
+    on_stack: BTreeSet<&'a str>,
+    components: Vec<Vec<&'a str>>,
+}
+
+impl<'a> Tarjan<'a> {
+    fn visit(&mut self, graph: &'a DependencyGraph, key: &'a str) {
+        let index = self.index.len();
+        self.index.insert(key, index);
+        self.low_link.insert(key, index);
+        self.stack.push(key);
+        self.on_stack.insert(key);
+
+        for next in graph.successors(key).filter(|s| graph.reference_map.contains_key(*s)) {
+            if !self.index.contains_key(next) {
+                self.visit(graph, next);
+                let low = self.low_link[key].min(self.low_link[next]);
+                self.low_link.insert(key, low);
+            } else if self.on_stack.contains(next) {
+                let low = self.low_link[key].min(self.index[next]);
+                self.low_link.insert(key, low);
+            }
+        }
+
+        if self.low_link[key] == self.index[key] {
+            let mut component = vec![];
+            while let Some(member) = self.stack.pop() {
+                self.on_stack.remove(member);
+                component.push(member);
+                if member == key {
+                    break;
+                }
+            }
+            self.components.push(component);
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn mermaid_label(s: &str) -> String {
+    s.replace('"', "#quot;").replace('|', "#124;")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn reference_map() -> BTreeMap<String, ObjectDefinition> {
+        let mut m = BTreeMap::new();
+        m.insert("base".to_owned(), ObjectDefinition::new("base", &json!({
+            "type": "object",
+            "properties": { "carried": { "$ref": "carried" } }
+        })));
+        m.insert("carried".to_owned(), ObjectDefinition::new("carried", &json!({
+            "type": "object",
+            "properties": { "n": { "type": "integer" } }
+        })));
+        m.insert("message".to_owned(), ObjectDefinition::new("message", &json!({
+            "type": "object",
+            "allOf": [ { "$ref": "base" }, { "properties": { "gone": { "$ref": "missing" } } } ]
+        })));
+        m.insert("node".to_owned(), ObjectDefinition::new("node", &json!({
+            "type": "object",
+            "properties": {
+                "children": { "type": "array", "items": { "$ref": "node" } },
+                "leaf": { "$ref": "leaf" }
+            }
+        })));
+        m.insert("leaf".to_owned(), ObjectDefinition::new("leaf", &json!({
+            "type": "object",
+            "properties": { "up": { "$ref": "node" } }
+        })));
+        m
+    }
+
+    #[test]
+    fn dependents_are_transitive() {
+        let m = reference_map();
+        let graph = DependencyGraph::new(&m);
+        assert_eq!(graph.dependents("carried").into_iter().collect::<Vec<_>>(), vec!["base", "message"]);
+        assert!(graph.dependents("message").is_empty());
+    }
+
+    #[test]
+    fn unreachable_and_dangling() {
+        let m = reference_map();
+        let graph = DependencyGraph::new(&m);
+        let roots = vec!["message".to_owned()].into_iter().collect();
+        assert_eq!(graph.unreachable(&roots), vec!["leaf", "node"]);
+
+        let dangling = graph.dangling();
+        assert_eq!(dangling.len(), 1);
+        assert_eq!(dangling[0].from, "message");
+        assert_eq!(dangling[0].via, "gone");
+    }
+
+    #[test]
+    fn cycles_are_found() {
+        let m = reference_map();
+        let graph = DependencyGraph::new(&m);
+        assert_eq!(graph.cycles(), vec![vec!["leaf", "node"]]);
+    }
+
+    #[test]
+    fn renders_dot_and_mermaid() {
+        let m = reference_map();
+        let graph = DependencyGraph::new(&m);
+
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph chimpsky {"));
+        assert!(dot.contains("\"message\" -> \"base\" [label=\"allOf\"];"));
+        assert!(dot.contains("\"missing\" [label=\"missing\", style=dashed, color=red];"));
+
+        let mermaid = graph.to_mermaid();
+        assert!(mermaid.starts_with("graph LR\n"));
+        assert!(mermaid.contains("n0 -->|carried| n1"));
+        assert!(mermaid.contains("[\"missing\"]:::dangling"));
+    }
+}
@@ -0,0 +1,351 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::BufRead;
+
+use chrono::DateTime;
+use serde_json::{json, Map, Value};
+use uuid::Uuid;
+
+/// A string field becomes an enum when it has at most `enum_max` distinct values and every
+/// value was seen this many times on average, so a handful of samples does not turn every
+/// string into an enum.
+const ENUM_MIN_OCCURRENCES: u64 = 3;
+
+pub struct InferOptions {
+    /// Name of the root object, and of the schema file written for it.
+    pub name: String,
+    /// Fraction of the samples of an object a field must appear in to be required.
+    pub required_ratio: f64,
+    /// Largest number of distinct values a string field can have to become an enum.
+    pub enum_max: usize,
+}
+
+/// Everything seen at one position of the samples, merged across samples.
+#[derive(Debug, Default)]
+struct Shape {
+    nulls: u64,
+    booleans: u64,
+    integers: u64,
+    numbers: u64,
+    strings: u64,
+    uuids: u64,
+    date_times: u64,
+    /// Distinct strings with their counts, dropped once there are too many for an enum.
+    values: Option<BTreeMap<String, u64>>,
+    objects: u64,
+    fields: Vec<(String, Shape)>,
+    arrays: u64,
+    items: Option<Box<Shape>>,
+}
+
+impl Shape {
+    fn new() -> Self {
+        Shape { values: Some(BTreeMap::new()), ..Shape::default() }
+    }
+
+    fn add(&mut self, value: &Value, enum_max: usize) {
+        match value {
+            Value::Null => self.nulls += 1,
+            Value::Bool(_) => self.booleans += 1,
+            Value::Number(n) if n.is_i64() || n.is_u64() => self.integers += 1,
+            Value::Number(_) => self.numbers += 1,
+            Value::String(s) => {
+                self.strings += 1;
+                if Uuid::parse_str(s).is_ok() {
+                    self.uuids += 1;
+                }
+                if DateTime::parse_from_rfc3339(s).is_ok() {
+                    self.date_times += 1;
+                }
+                if let Some(values) = &mut self.values {
+                    *values.entry(s.to_owned()).or_default() += 1;
+                    if values.len() > enum_max {
+                        self.values = None;
+                    }
+                }
+            }
+            Value::Array(items) => {
+                self.arrays += 1;
+                let shape = self.items.get_or_insert_with(|| Box::new(Shape::new()));
+                for item in items {
+                    shape.add(item, enum_max);
+                }
+            }
+            Value::Object(object) => {
+                self.objects += 1;
+                for (name, value) in object {
+                    let index = match self.fields.iter().position(|(n, _)| n == name) {
+                        Some(index) => index,
+                        None => {
+                            self.fields.push((name.to_owned(), Shape::new()));
+                            self.fields.len() - 1
+                        }
+                    };
+                    self.fields[index].1.add(value, enum_max);
+                }
+            }
+        }
+    }
+
+    fn kinds(&self) -> usize {
+        [self.nulls, self.booleans, self.integers + self.numbers, self.strings, self.objects, self.arrays]
+            .iter()
+            .filter(|count| **count > 0)
+            .count()
+    }
+}
+
+/// Infers a schema from sample payloads. Objects are written as definitions of one schema file,
+/// nested objects as definitions of their own referenced by the fields holding them.
+pub struct Inferrer {
+    options: InferOptions,
+    root: Shape,
+    samples: u64,
+}
+
+impl Inferrer {
+    pub fn new(options: InferOptions) -> Self {
+        Inferrer { options, root: Shape::new(), samples: 0 }
+    }
+
+    pub fn add(&mut self, sample: &Value) {
+        self.samples += 1;
+        self.root.add(sample, self.options.enum_max);
+    }
+
+    /// Adds every non-empty line of an NDJSON stream.
+    pub fn add_ndjson(&mut self, reader: impl BufRead) -> Result<(), String> {
+        for (number, line) in reader.lines().enumerate() {
+            let line = line.map_err(|e| format!("cannot read samples: {}", e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let sample = serde_json::from_str(&line)
+                .map_err(|e| format!("line {} is not a JSON document: {}", number + 1, e))?;
+            self.add(&sample);
+        }
+        Ok(())
+    }
+
+    pub fn file_name(&self) -> String {
+        format!("{}.schema.json", self.options.name)
+    }
+
+    pub fn schema(&self) -> Result<Value, String> {
+        if self.samples == 0 {
+            return Err("no samples to infer a schema from".to_owned());
+        }
+        if self.root.objects == 0 || self.root.kinds() > 1 {
+            return Err("samples must all be JSON objects".to_owned());
+        }
+
+        let mut definitions = Map::new();
+        let mut taken = BTreeSet::new();
+        taken.insert(self.options.name.to_owned());
+        self.definition(&self.options.name, &self.root, &mut definitions, &mut taken);
+
+        Ok(json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "definitions": definitions,
+            "allOf": [ { "$ref": format!("#/definitions/{}", self.options.name) } ]
+        }))
+    }
+
+    fn definition(&self, name: &str, shape: &Shape, definitions: &mut Map<String, Value>, taken: &mut BTreeSet<String>) {
+        // reserved first so definitions of nested objects come after their parent
+        definitions.insert(name.to_owned(), Value::Null);
+
+        let mut properties = Map::new();
+        let mut required = vec![];
+        for (field, field_shape) in &shape.fields {
+            let present = field_shape.nulls + field_shape.booleans + field_shape.integers + field_shape.numbers
+                + field_shape.strings + field_shape.objects + field_shape.arrays;
+            if present as f64 >= self.options.required_ratio * shape.objects as f64 {
+                required.push(field.to_owned());
+            }
+            properties.insert(field.to_owned(), self.property(&format!("{}_{}", name, field), field_shape, true, definitions, taken));
+        }
+
+        let mut definition = json!({ "type": "object", "properties": properties });
+        if !required.is_empty() {
+            definition["required"] = json!(required);
+        }
+        definitions.insert(name.to_owned(), definition);
+    }
+
+    /// Enums are only inferred for fields, chimpsky ignores `enum` on array items when generating.
+    fn property(&self,
+                name: &str,
+                shape: &Shape,
+                enums: bool,
+                definitions: &mut Map<String, Value>,
+                taken: &mut BTreeSet<String>) -> Value {
+        if shape.kinds() == 0 {
+            return json!({});
+        }
+
+        // objects only keep their shape when nothing else appears in their place, since a
+        // reference cannot be combined with other types
+        if shape.kinds() == 1 && shape.objects > 0 {
+            let name = definition_name(name);
+            let mut definition_name = name.to_owned();
+            let mut n = 2;
+            while !taken.insert(definition_name.clone()) {
+                definition_name = format!("{}_{}", name, n);
+                n += 1;
+            }
+            self.definition(&definition_name, shape, definitions, taken);
+            return json!({ "$ref": format!("#/definitions/{}", definition_name) });
+        }
+
+        let mut types = vec![];
+        if shape.strings > 0 {
+            types.push("string");
+        }
+        if shape.numbers > 0 {
+            types.push("number");
+        } else if shape.integers > 0 {
+            types.push("integer");
+        }
+        if shape.booleans > 0 {
+            types.push("boolean");
+        }
+        if shape.objects > 0 {
+            types.push("object");
+        }
+        if shape.arrays > 0 {
+            types.push("array");
+        }
+        if shape.nulls > 0 {
+            types.push("null");
+        }
+
+        let mut property = Map::new();
+        property.insert("type".to_owned(), if types.len() == 1 { json!(types[0]) } else { json!(types) });
+
+        if shape.strings > 0 && shape.uuids == shape.strings {
+            property.insert("format".to_owned(), json!("uuid"));
+        } else if shape.strings > 0 && shape.date_times == shape.strings {
+            property.insert("format".to_owned(), json!("date-time"));
+        } else if let Some(values) = shape.values.as_ref().filter(|v| enums && !v.is_empty() && shape.kinds() == 1 + (shape.nulls > 0) as usize) {
+            if shape.strings >= ENUM_MIN_OCCURRENCES * values.len() as u64 {
+                let mut values = values.keys().map(|v| json!(v)).collect::<Vec<_>>();
+                if shape.nulls > 0 {
+                    values.push(Value::Null);
+                }
+                property.insert("enum".to_owned(), json!(values));
+            }
+        }
+
+        if let Some(items) = &shape.items {
+            property.insert("items".to_owned(), self.property(&format!("{}_item", name), items, false, definitions, taken));
+        }
+
+        Value::Object(property)
+    }
+}
+
+/// Field names become part of definition names, which `$ref` pointers then carry, so anything
+/// but letters, digits, `-` and `_` is replaced to keep the pointers free of `/` and `~`.
+fn definition_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schema_parser::{Schema, SchemaSet};
+    use crate::verifier::Verifier;
+
+    use super::*;
+
+    fn samples() -> Vec<Value> {
+        (0..6).map(|i| json!({
+            "id": Uuid::new_v4().to_string(),
+            "at": "2020-01-01T00:00:01+00:00",
+            "state": if i % 2 == 0 { "on" } else { "off" },
+            "name": format!("name {}", i),
+            "amount": if i == 3 { json!(1.5) } else { json!(i) },
+            "note": if i % 3 == 0 { json!(null) } else { json!("x") },
+            "carried": { "version": i, "tags": ["a", "b"] },
+            "rare": if i == 0 { json!(true) } else { json!(null) }
+        }))
+        .map(|mut v| {
+            if v["rare"].is_null() {
+                v.as_object_mut().unwrap().remove("rare");
+            }
+            v
+        })
+        .collect()
+    }
+
+    fn inferred() -> Value {
+        let mut inferrer = Inferrer::new(InferOptions { name: "message".to_owned(), required_ratio: 1.0, enum_max: 5 });
+        samples().iter().for_each(|s| inferrer.add(s));
+        inferrer.schema().unwrap()
+    }
+
+    #[test]
+    fn types_formats_and_enums() {
+        let schema = inferred();
+        let properties = &schema["definitions"]["message"]["properties"];
+        assert_eq!(properties["id"], json!({ "type": "string", "format": "uuid" }));
+        assert_eq!(properties["at"], json!({ "type": "string", "format": "date-time" }));
+        assert_eq!(properties["state"], json!({ "type": "string", "enum": ["off", "on"] }));
+        assert_eq!(properties["name"], json!({ "type": "string" }));
+        assert_eq!(properties["amount"], json!({ "type": "number" }));
+        assert_eq!(properties["note"], json!({ "type": ["string", "null"], "enum": ["x", null] }));
+        assert_eq!(properties["carried"], json!({ "$ref": "#/definitions/message_carried" }));
+        assert_eq!(schema["definitions"]["message_carried"]["properties"]["tags"],
+                   json!({ "type": "array", "items": { "type": "string" } }));
+    }
+
+    #[test]
+    fn required_from_presence() {
+        let schema = inferred();
+        let required = schema["definitions"]["message"]["required"].as_array().unwrap();
+        assert!(required.contains(&json!("id")));
+        assert!(!required.contains(&json!("rare")));
+    }
+
+    #[test]
+    fn inferred_schemas_load_back_and_accept_the_samples() {
+        let mut set = SchemaSet::default();
+        set.add(Schema::new(&inferred(), Some("message.schema.json".to_owned()))).unwrap();
+        let key = "message.schema.json#/definitions/message";
+        assert!(set.reference_map.contains_key(key));
+
+        let verifier = Verifier::new(&set.documents, key).unwrap();
+        for sample in samples() {
+            assert_eq!(verifier.verify(&sample), vec![]);
+        }
+
+        let payload = set.reference_map[key].generate_json(Some(&set.reference_map)).unwrap();
+        assert_eq!(verifier.verify(&payload), vec![]);
+    }
+
+    #[test]
+    fn definition_names_stay_valid_pointers() {
+        let mut inferrer = Inferrer::new(InferOptions { name: "message".to_owned(), required_ratio: 1.0, enum_max: 5 });
+        inferrer.add(&json!({ "c/d": { "a": 1 }, "c~d": { "b": true }, "c_d": { "e": 3 } }));
+        let schema = inferrer.schema().unwrap();
+        let properties = &schema["definitions"]["message"]["properties"];
+        assert_eq!(properties["c/d"], json!({ "$ref": "#/definitions/message_c_d" }));
+        assert_eq!(properties["c~d"], json!({ "$ref": "#/definitions/message_c_d_2" }));
+        assert_eq!(properties["c_d"], json!({ "$ref": "#/definitions/message_c_d_3" }));
+
+        let mut set = SchemaSet::default();
+        set.add(Schema::new(&schema, Some("message.schema.json".to_owned()))).unwrap();
+        let key = "message.schema.json#/definitions/message";
+        let payload = set.reference_map[key].generate_json(Some(&set.reference_map)).unwrap();
+        assert_eq!(Verifier::new(&set.documents, key).unwrap().verify(&payload), vec![]);
+    }
+
+    #[test]
+    fn samples_must_be_objects() {
+        let mut inferrer = Inferrer::new(InferOptions { name: "message".to_owned(), required_ratio: 1.0, enum_max: 5 });
+        inferrer.add_ndjson("{\"a\": 1}\n\n[1]\n".as_bytes()).unwrap();
+        assert!(inferrer.schema().is_err());
+        assert!(inferrer.add_ndjson("{".as_bytes()).unwrap_err().starts_with("line 1"));
+    }
+}
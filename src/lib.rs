@@ -0,0 +1,363 @@
+//! Random JSON payloads from JSON schema files.
+//!
+//! ```no_run
+//! use chimpsky::Chimpsky;
+//!
+//! # fn main() -> Result<(), chimpsky::Error> {
+//! let payloads: Vec<_> = Chimpsky::load_dir("schema")?.generator("bigmessage.schema")?.seed(42).take(10).collect();
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde_json::{json, Value};
+
+pub use crate::coverage::Coverage;
+pub use crate::dataset::{Dataset, ForeignKey};
+pub use crate::datetimes::{DateTimes, TimeDistribution};
+pub use crate::mixture::{envelope, Mixture};
+pub use crate::object_definitions::ObjectDefinition;
+pub use crate::overrides::Overrides;
+pub use crate::realistic::Locale;
+pub use crate::schema_parser::{Schema, SchemaSet};
+
+use crate::field_definitions::{REALISTIC_FORMATS, SUPPORTED_FORMATS};
+use crate::graph::DependencyGraph;
+
+mod schema_parser;
+mod object_definitions;
+mod field_definitions;
+mod field_kinds;
+#[cfg(feature = "quickcheck")]
+pub mod arbitrary;
+mod avro;
+mod bundle;
+mod codegen;
+mod coverage;
+mod dataset;
+mod datetimes;
+mod diff;
+mod docs;
+mod encoders;
+mod export;
+mod flatten;
+mod graph;
+mod infer;
+mod lint;
+mod mixture;
+mod output;
+mod overrides;
+mod proto;
+mod random_values;
+mod realistic;
+mod report;
+mod rust_codegen;
+#[cfg(any(feature = "proptest", feature = "quickcheck"))]
+mod shrink;
+#[cfg(feature = "proptest")]
+pub mod strategy;
+mod typescript_codegen;
+mod verifier;
+
+/// The modules the `chimpsky` command line is built from. They are not part of the library's
+/// API and change without notice.
+#[doc(hidden)]
+pub mod internals {
+    pub mod avro { pub use crate::avro::*; }
+    pub mod bundle { pub use crate::bundle::*; }
+    pub mod codegen { pub use crate::codegen::*; }
+    pub mod datetimes { pub use crate::datetimes::*; }
+    pub mod diff { pub use crate::diff::*; }
+    pub mod docs { pub use crate::docs::*; }
+    pub mod export { pub use crate::export::*; }
+    pub mod flatten { pub use crate::flatten::*; }
+    pub mod graph { pub use crate::graph::*; }
+    pub mod infer { pub use crate::infer::*; }
+    pub mod lint { pub use crate::lint::*; }
+    pub mod output { pub use crate::output::*; }
+    pub mod report { pub use crate::report::*; }
+    pub mod verifier { pub use crate::verifier::*; }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The schema directory could not be read.
+    LoadDir { path: PathBuf, source: io::Error },
+    /// No definition has this reference key or name.
+    UnknownObject(String),
+    /// The object refers to a definition that does not exist or to itself, or uses a type or
+    /// format payloads cannot be generated for.
+    Ungenerable(String),
+    /// Foreign keys of a dataset refer to objects it does not generate, or to each other in a cycle.
+    InvalidDataset(String),
+    /// Objects of a mixed stream have no weights to pick them by.
+    InvalidWeights(String),
+    /// A schema cannot be added to a schema set.
+    InvalidSchema(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::LoadDir { path, source } => write!(f, "cannot read schema directory {}: {}", path.display(), source),
+            Error::UnknownObject(object) => write!(f, "no object named {}", object),
+            Error::Ungenerable(key) => write!(f, "cannot generate payloads for {}, run lint for details", key),
+            Error::InvalidDataset(message) | Error::InvalidWeights(message) | Error::InvalidSchema(message) =>
+                write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::LoadDir { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// A loaded schema set, the starting point for generating payloads.
+pub struct Chimpsky {
+    schema_set: SchemaSet,
+}
+
+impl Chimpsky {
+    pub fn load_dir(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        SchemaSet::load_dir(path)
+            .map(Chimpsky::from)
+            .map_err(|source| Error::LoadDir { path: path.to_owned(), source })
+    }
+
+    pub fn schema_set(&self) -> &SchemaSet {
+        &self.schema_set
+    }
+
+    pub fn reference_map(&self) -> &BTreeMap<String, ObjectDefinition> {
+        &self.schema_set.reference_map
+    }
+
+    /// Reference key of an object given either its key or its definition name.
+    pub fn object_key<'a>(&'a self, object: &'a str) -> Result<&'a str, Error> {
+        self.schema_set.object_key(object).ok_or_else(|| Error::UnknownObject(object.to_owned()))
+    }
+
+    pub fn generator(&self, object: &str) -> Result<Generator<'_>, Error> {
+        let key = self.object_key(object)?;
+        if !can_generate(key, self.reference_map()) {
+            return Err(Error::Ungenerable(key.to_owned()));
+        }
+        Ok(self.generator_of(key))
+    }
+
+    /// A generator already in realistic mode, which also accepts objects with fields in the
+    /// `email`, `uri` and `ipv4` formats that only realistic mode generates.
+    pub fn realistic_generator(&self, object: &str, locale: Locale) -> Result<Generator<'_>, Error> {
+        let key = self.object_key(object)?;
+        if !can_generate_realistic(key, self.reference_map()) {
+            return Err(Error::Ungenerable(key.to_owned()));
+        }
+        Ok(self.generator_of(key).realistic(locale))
+    }
+
+    fn generator_of(&self, key: &str) -> Generator<'_> {
+        let (key, definition) = self.reference_map().get_key_value(key).unwrap();
+        Generator {
+            key,
+            definition,
+            reference_map: self.reference_map(),
+            rng: None,
+            coverage: None,
+            overrides: Some(Overrides::default()),
+            realistic: None,
+            datetimes: None,
+        }
+    }
+
+    /// A proptest strategy for payloads of an object, failing the same way `generator` does.
+    #[cfg(feature = "proptest")]
+    pub fn strategy(&self, object: &str) -> Result<strategy::SchemaStrategy<'_>, Error> {
+        let generator = self.generator(object)?;
+        Ok(strategy::SchemaStrategy::new(generator.definition, generator.reference_map))
+    }
+}
+
+impl From<SchemaSet> for Chimpsky {
+    fn from(schema_set: SchemaSet) -> Self {
+        Chimpsky { schema_set }
+    }
+}
+
+/// Endless payloads for one object. Unseeded generators draw from the thread's random number
+/// generator, seeded ones produce the same payloads on every run.
+pub struct Generator<'a> {
+    key: &'a str,
+    definition: &'a ObjectDefinition,
+    reference_map: &'a BTreeMap<String, ObjectDefinition>,
+    rng: Option<StdRng>,
+    coverage: Option<Coverage>,
+    overrides: Option<Overrides>,
+    realistic: Option<Locale>,
+    datetimes: Option<DateTimes>,
+}
+
+impl<'a> Generator<'a> {
+    pub fn key(&self) -> &'a str {
+        self.key
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.rng = Some(StdRng::seed_from_u64(seed));
+        self
+    }
+
+    /// Steers generation toward the branches of the schema produced the least so far, see `Coverage`.
+    pub fn cover(mut self) -> Self {
+        self.coverage = Some(Coverage::new(self.key, self.reference_map));
+        self
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    pub(crate) fn overrides_foreign_keys(&self) -> Vec<dataset::ForeignKey> {
+        self.overrides.as_ref().map(|overrides| overrides.foreign_keys()).unwrap_or_default()
+    }
+
+    /// Generates the fields `overrides` configures with its generators instead of their schema.
+    pub fn overrides(mut self, overrides: Overrides) -> Self {
+        self.overrides = Some(overrides);
+        self
+    }
+
+    /// Generates plausible values from the word lists of `locale` for fields whose names
+    /// chimpsky recognises, like `email`, `city` or `price`.
+    pub fn realistic(mut self, locale: Locale) -> Self {
+        self.realistic = Some(locale);
+        self
+    }
+
+    /// Generates date-times, dates and times in the range and distribution `datetimes` describes
+    /// instead of the last year.
+    pub fn datetimes(mut self, datetimes: DateTimes) -> Self {
+        self.datetimes = Some(datetimes);
+        self
+    }
+
+    /// Objects without properties generate as empty objects.
+    pub fn generate(&mut self) -> Value {
+        let (definition, reference_map, locale) = (self.definition, self.reference_map, self.realistic);
+        let (rng, coverage, overrides, datetimes) = (&mut self.rng, &mut self.coverage, &mut self.overrides, &mut self.datetimes);
+        random_values::with_rng(rng, || {
+            coverage::with_coverage(coverage, || {
+                overrides::with_overrides(overrides, || {
+                    datetimes::with_datetimes(datetimes, || {
+                        realistic::with_locale(locale, || definition.generate_json(Some(reference_map)))
+                    })
+                })
+            })
+        })
+        .unwrap_or_else(|| json!({}))
+    }
+}
+
+impl Iterator for Generator<'_> {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        Some(self.generate())
+    }
+}
+
+/// Whether `generate_json` can produce payloads of an object without hitting an unresolved reference, an
+/// unknown type, a format it cannot generate or a definition that refers to itself, which it
+/// would nest without end.
+pub(crate) fn can_generate(key: &str, reference_map: &BTreeMap<String, ObjectDefinition>) -> bool {
+    generable(key, reference_map, false)
+}
+
+/// Like `can_generate`, also accepting the formats only realistic mode generates.
+pub(crate) fn can_generate_realistic(key: &str, reference_map: &BTreeMap<String, ObjectDefinition>) -> bool {
+    generable(key, reference_map, true)
+}
+
+fn generable(key: &str, reference_map: &BTreeMap<String, ObjectDefinition>, realistic: bool) -> bool {
+    let keys = match codegen::reachable_keys(&[key], reference_map) {
+        Ok(keys) => keys,
+        Err(_) => return false,
+    };
+    let graph = DependencyGraph::new(reference_map);
+    if graph.cycles().iter().flatten().any(|k| keys.iter().any(|key| key == k)) {
+        return false;
+    }
+
+    keys.iter()
+        .flat_map(|k| reference_map[k].field_definitions.iter().flatten())
+        .all(|f| {
+            f.kind.as_ref().is_none_or(|k| k.unknown_types().is_empty())
+                && f.format.as_ref().is_none_or(|format| SUPPORTED_FORMATS.contains(&format.as_str())
+                    || (realistic && REALISTIC_FORMATS.contains(&format.as_str())))
+                && f.sequence.as_ref().is_none_or(|_| overrides::sequence_of(f).is_ok())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chimpsky() -> Chimpsky {
+        let mut set = SchemaSet::default();
+        set.add(Schema::new(&json!({
+            "definitions": {
+                "message": {
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "string", "format": "uuid" },
+                        "at": { "type": "string", "format": "date-time" },
+                        "state": { "enum": ["on", "off"] },
+                        "code": { "type": "string", "pattern": "^[A-Z]{3}$" },
+                        "tags": { "type": "array", "items": { "type": ["string", "integer"] } }
+                    }
+                },
+                "broken": { "type": "object", "properties": { "size": { "type": "decimal" } } },
+                "contact": { "type": "object", "properties": { "email": { "type": "string", "format": "email" } } },
+                "node": { "type": "object", "properties": { "next": { "$ref": "#/definitions/node" } } }
+            },
+            "allOf": [ { "$ref": "#/definitions/message" } ]
+        }), Some("message.json".to_owned()))).unwrap();
+        Chimpsky::from(set)
+    }
+
+    #[test]
+    fn seeded_generators_repeat() {
+        let chimpsky = chimpsky();
+        let first: Vec<_> = chimpsky.generator("message").unwrap().seed(42).take(5).collect();
+        let second: Vec<_> = chimpsky.generator("message").unwrap().seed(42).take(5).collect();
+        let other: Vec<_> = chimpsky.generator("message").unwrap().seed(7).take(5).collect();
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+        assert_ne!(first[0], first[1]);
+    }
+
+    #[test]
+    fn objects_by_key_or_name() {
+        let chimpsky = chimpsky();
+        let generator = chimpsky.generator("message.json#/definitions/message").unwrap();
+        assert_eq!(generator.key(), chimpsky.generator("message").unwrap().key());
+
+        assert_eq!(chimpsky.generator("missing").err().unwrap().to_string(), "no object named missing");
+        assert!(matches!(chimpsky.generator("broken"), Err(Error::Ungenerable(_))));
+        assert!(matches!(chimpsky.generator("node"), Err(Error::Ungenerable(_))));
+        assert!(matches!(chimpsky.generator("contact"), Err(Error::Ungenerable(_))));
+        assert!(chimpsky.realistic_generator("contact", Locale::En).unwrap().generate()["email"].as_str().unwrap().contains('@'));
+        assert!(matches!(Chimpsky::load_dir("/nonexistent"), Err(Error::LoadDir { .. })));
+    }
+}
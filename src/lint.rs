@@ -0,0 +1,282 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::dataset::ForeignKey;
+use crate::field_definitions::{REALISTIC_FORMATS, SUPPORTED_FORMATS};
+use crate::field_kinds::FieldKind;
+use crate::graph::DependencyGraph;
+use crate::overrides;
+use crate::schema_parser::SchemaSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Finding {
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub object: String,
+    pub field: Option<String>,
+    pub message: String,
+}
+
+/// Checks every definition in the schema set without generating anything, so problems surface
+/// before `randomize` happens to walk into them.
+pub fn lint(schema_set: &SchemaSet) -> Vec<Finding> {
+    let mut findings = vec![];
+    let reference_map = &schema_set.reference_map;
+    let graph = DependencyGraph::new(reference_map);
+
+    for edge in graph.dangling() {
+        findings.push(Finding {
+            rule: "dangling-ref",
+            severity: Severity::Error,
+            object: edge.from.to_owned(),
+            field: field_of(&edge.via),
+            message: format!("reference {} does not resolve to a definition", edge.to),
+        });
+    }
+
+    for key in graph.unreachable(&schema_set.roots) {
+        findings.push(Finding {
+            rule: "unused-definition",
+            severity: Severity::Warning,
+            object: key.to_owned(),
+            field: None,
+            message: "definition is not referenced from any root object".to_owned(),
+        });
+    }
+
+    for cycle in graph.cycles() {
+        for key in &cycle {
+            let message = if cycle.len() == 1 {
+                "definition refers to itself, payloads would nest without end".to_owned()
+            } else {
+                format!("definition refers to itself through {}, payloads would nest without end", others(&cycle, key))
+            };
+            findings.push(Finding {
+                rule: "recursive-ref",
+                severity: Severity::Error,
+                object: key.to_string(),
+                field: None,
+                message,
+            });
+        }
+    }
+
+    let mut by_name: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (key, definition) in reference_map {
+        by_name.entry(&definition.name).or_default().push(key);
+    }
+
+    for (name, keys) in by_name.into_iter().filter(|(_, keys)| keys.len() > 1) {
+        for key in &keys {
+            findings.push(Finding {
+                rule: "duplicate-name",
+                severity: Severity::Warning,
+                object: key.to_string(),
+                field: None,
+                message: format!("definition name {} is also used by {}", name, others(&keys, key)),
+            });
+        }
+    }
+
+    for (key, definition) in reference_map {
+        if !definition.kind.is_empty() && !FieldKind::is_known_type(&definition.kind) {
+            findings.push(Finding {
+                rule: "unknown-type",
+                severity: Severity::Error,
+                object: key.to_owned(),
+                field: None,
+                message: format!("unknown type {}", definition.kind),
+            });
+        }
+
+        for keyword in &definition.unsupported {
+            findings.push(unsupported_keyword(key, None, keyword));
+        }
+
+        for field in definition.field_definitions.iter().flatten() {
+            for unknown in field.kind.iter().flat_map(|k| k.unknown_types()) {
+                findings.push(Finding {
+                    rule: "unknown-type",
+                    severity: Severity::Error,
+                    object: key.to_owned(),
+                    field: Some(field.name.to_owned()),
+                    message: format!("unknown type {}", unknown),
+                });
+            }
+
+            if let Some(format) = field.format.as_ref().filter(|f| !SUPPORTED_FORMATS.contains(&f.as_str())) {
+                let message = if REALISTIC_FORMATS.contains(&format.as_str()) {
+                    format!("format {} is only generated with --realistic", format)
+                } else {
+                    format!("format {} cannot be generated", format)
+                };
+                findings.push(Finding {
+                    rule: "unsupported-format",
+                    severity: Severity::Error,
+                    object: key.to_owned(),
+                    field: Some(field.name.to_owned()),
+                    message,
+                });
+            }
+
+            if let Some(Err(e)) = field.sequence.as_ref().map(|_| overrides::sequence_of(field)) {
+                findings.push(Finding {
+                    rule: "invalid-sequence",
+                    severity: Severity::Error,
+                    object: key.to_owned(),
+                    field: Some(field.name.to_owned()),
+                    message: e,
+                });
+            }
+
+            let foreign_key = ForeignKey::of(field).and_then(|foreign_key| match foreign_key {
+                Some(foreign_key) if schema_set.object_key(&foreign_key.object).is_none() =>
+                    Err(format!("x-chimpsky-ref refers to {}, which is not an object", foreign_key.object)),
+                _ => Ok(()),
+            });
+            if let Err(e) = foreign_key {
+                findings.push(Finding {
+                    rule: "invalid-ref",
+                    severity: Severity::Error,
+                    object: key.to_owned(),
+                    field: Some(field.name.to_owned()),
+                    message: e,
+                });
+            }
+
+            for keyword in &field.unsupported {
+                findings.push(unsupported_keyword(key, Some(&field.name), keyword));
+            }
+        }
+    }
+
+    findings.sort_by(|a, b| (b.severity, &a.object, &a.field, a.rule).cmp(&(a.severity, &b.object, &b.field, b.rule)));
+    findings
+}
+
+pub fn render_text(findings: &[Finding]) -> String {
+    findings.iter()
+        .map(|f| {
+            let location = match &f.field {
+                Some(field) => format!("{} field {}", f.object, field),
+                None => f.object.to_owned(),
+            };
+            format!("{}[{}] {}: {}\n", f.severity.as_str(), f.rule, location, f.message)
+        })
+        .collect()
+}
+
+pub fn render_json(findings: &[Finding]) -> String {
+    serde_json::to_string_pretty(findings).unwrap()
+}
+
+fn unsupported_keyword(key: &str, field: Option<&str>, keyword: &str) -> Finding {
+    Finding {
+        rule: "unsupported-keyword",
+        severity: Severity::Warning,
+        object: key.to_owned(),
+        field: field.map(|f| f.to_owned()),
+        message: format!("keyword {} is ignored when generating payloads", keyword),
+    }
+}
+
+fn field_of(via: &str) -> Option<String> {
+    if via == "allOf" {
+        None
+    } else {
+        Some(via.to_owned())
+    }
+}
+
+fn others(keys: &[&str], key: &str) -> String {
+    keys.iter()
+        .filter(|k| **k != key)
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::schema_parser::Schema;
+
+    use super::*;
+
+    fn schema_set() -> SchemaSet {
+        let mut set = SchemaSet::default();
+        set.add(Schema::new(&json!({
+            "definitions": {
+                "message": {
+                    "type": "object",
+                    "properties": {
+                        "amount": { "type": "decimal" },
+                        "email": { "type": "string", "format": "email" },
+                        "flag": { "type": "boolean", "x-chimpsky-sequence": true },
+                        "owner": { "type": "string", "x-chimpsky-ref": "nobody" },
+                        "size": { "type": "integer", "multipleOf": 2 },
+                        "gone": { "$ref": "missing.json#/definitions/missing" }
+                    }
+                },
+                "orphan": { "type": "object", "properties": {} },
+                "node": { "type": "object", "properties": { "next": { "$ref": "#/definitions/node" } } }
+            },
+            "allOf": [ { "$ref": "#/definitions/message" } ]
+        }), Some("message.json".to_owned()))).unwrap();
+        set.add(Schema::new(&json!({
+            "definitions": { "message": { "type": "object", "properties": {} } },
+            "allOf": [ { "$ref": "#/definitions/message" } ]
+        }), Some("other.json".to_owned()))).unwrap();
+        set
+    }
+
+    fn rules(findings: &[Finding]) -> Vec<&str> {
+        findings.iter().map(|f| f.rule).collect()
+    }
+
+    #[test]
+    fn finds_every_rule() {
+        let findings = lint(&schema_set());
+        let mut found = rules(&findings);
+        found.sort();
+        found.dedup();
+        assert_eq!(found, vec!["dangling-ref", "duplicate-name", "invalid-ref", "invalid-sequence", "recursive-ref", "unknown-type", "unsupported-format",
+                               "unsupported-keyword", "unused-definition"]);
+    }
+
+    #[test]
+    fn errors_come_first() {
+        let findings = lint(&schema_set());
+        let first_warning = findings.iter().position(|f| f.severity == Severity::Warning).unwrap();
+        assert!(findings[first_warning..].iter().all(|f| f.severity == Severity::Warning));
+        assert_eq!(findings.iter().filter(|f| f.severity == Severity::Error).count(), 6);
+    }
+
+    #[test]
+    fn text_rendering() {
+        let findings = lint(&schema_set());
+        let text = render_text(&findings);
+        assert!(text.contains("error[dangling-ref] message.json#/definitions/message field gone: reference missing.json#/definitions/missing does not resolve to a definition"));
+        assert!(text.contains("warning[unused-definition] message.json#/definitions/orphan"));
+        assert!(text.contains("error[recursive-ref] message.json#/definitions/node: definition refers to itself"));
+        assert!(text.contains("error[unsupported-format] message.json#/definitions/message field email: format email is only generated with --realistic"));
+    }
+}
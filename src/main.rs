@@ -1,37 +1,281 @@
-use std::collections::HashMap;
-use std::fs;
-use std::fs::DirEntry;
-use std::io::Result;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::process;
 
+use serde_json::Value;
 use structopt::StructOpt;
 
-use crate::object_definitions::ObjectDefinition;
-use crate::schema_parser::Schema;
-
-mod schema_parser;
-mod object_definitions;
-mod field_definitions;
-mod field_kinds;
-mod random_values;
+use chimpsky::{Chimpsky, Dataset, DateTimes, Locale, Mixture, ObjectDefinition, Overrides, SchemaSet, TimeDistribution};
+use chimpsky::internals::{codegen, diff, docs, export, lint, report, verifier};
+use chimpsky::internals::avro::AvroWriter;
+use chimpsky::internals::bundle::Bundler;
+use chimpsky::internals::codegen::{CodegenLanguage, CodegenOptions};
+use chimpsky::internals::datetimes::{DEFAULT_FROM, DEFAULT_TO};
+use chimpsky::internals::docs::DocsFormat;
+use chimpsky::internals::export::ExportTarget;
+use chimpsky::internals::graph::{DependencyGraph, GraphFormat};
+use chimpsky::internals::infer::{InferOptions, Inferrer};
+use chimpsky::internals::lint::Severity;
+use chimpsky::internals::flatten::TableWriter;
+use chimpsky::internals::output::{OutputFormat, OutputOptions, PayloadSink, PayloadWriter};
+use chimpsky::internals::report::ReportFormat;
+use chimpsky::internals::verifier::{ObjectCheck, Verifier};
 
 #[derive(Debug, StructOpt)]
 enum Command {
     /// Print object definitions found and exit
-    Report,
+    Report {
+        /// Only report the object with this key or name
+        #[structopt(short, long)]
+        object: Option<String>,
+
+        /// Output format, text or json
+        #[structopt(short, long, default_value = "text")]
+        format: ReportFormat,
+    },
 
     /// Generate random JSON payloads based on supplied object name
     Randomize {
         /// Object name to emit random JSON payloads for
-        #[structopt(short, long)]
-        object_name: String,
+        #[structopt(short, long, required_unless_one = &["object", "all"], conflicts_with_all = &["object", "all"])]
+        object_name: Option<String>,
+
+        /// Objects to interleave payloads of, with their weights, like a=70,b=25,c=5
+        #[structopt(long, use_delimiter = true, parse(try_from_str = parse_object_number))]
+        object: Vec<(String, u64)>,
+
+        /// Interleave payloads of every object payloads can be generated for, with equal weights
+        #[structopt(long, conflicts_with = "object")]
+        all: bool,
+
+        /// Wrap each payload into {"object": <object key>, "payload": <payload>}
+        #[structopt(long)]
+        envelope: bool,
 
         /// Number of random payloads to emit
         #[structopt(short, long, default_value = "100")]
         emit_count: u64,
 
-        /// Prettify emitted JSON
+        /// Prettify emitted JSON, same as --output-format pretty
         #[structopt(short, long)]
         prettify: bool,
+
+        /// Validate each payload against its source schema before printing it
+        #[structopt(long)]
+        verify: bool,
+
+        /// Seed for the random number generator, runs with the same seed emit the same payloads
+        #[structopt(long)]
+        seed: Option<u64>,
+
+        /// TOML or JSON config with generators overriding the schema for chosen fields
+        #[structopt(long, parse(from_os_str))]
+        config: Option<PathBuf>,
+
+        /// Steer generation toward schema branches not produced yet, stop once every branch was
+        /// produced or --emit-count payloads were emitted, and report branch coverage on stderr
+        #[structopt(long)]
+        cover: bool,
+
+        /// Generate plausible names, emails, cities, prices and the like for fields whose names
+        /// are recognised
+        #[structopt(long)]
+        realistic: bool,
+
+        /// Locale of realistic values, en, de, fr or es
+        #[structopt(long, default_value = "en")]
+        locale: Locale,
+
+        /// Earliest date-time generated, an RFC 3339 date-time, a date, now, or relative to now
+        /// like -7d, in s, m, h, d or w
+        #[structopt(long, default_value = DEFAULT_FROM, allow_hyphen_values = true)]
+        datetime_from: String,
+
+        /// Latest date-time generated, in the same forms as --datetime-from
+        #[structopt(long, default_value = DEFAULT_TO, allow_hyphen_values = true)]
+        datetime_to: String,
+
+        /// Where date-times fall in their range: uniform, recent (biased toward --datetime-to)
+        /// or now (the moment of generation)
+        #[structopt(long, default_value = "uniform")]
+        datetime_distribution: TimeDistribution,
+
+        /// Write date-times and times in a variety of UTC offsets instead of UTC alone
+        #[structopt(long)]
+        datetime_offsets: bool,
+
+        /// Add milliseconds to date-times and times
+        #[structopt(long)]
+        fractional_seconds: bool,
+
+        #[structopt(flatten)]
+        output: OutputOptions,
+    },
+
+    /// Generate payloads of several objects together, with foreign keys declared through
+    /// x-chimpsky-ref or a config referring to generated payloads
+    Dataset {
+        /// Objects with the number of payloads to generate of each, like customer=10 order=50
+        #[structopt(required = true, parse(try_from_str = parse_object_number))]
+        objects: Vec<(String, u64)>,
+
+        /// Seed for the random number generators, runs with the same seed emit the same payloads
+        #[structopt(long)]
+        seed: Option<u64>,
+
+        /// TOML or JSON config with generators overriding the schema for chosen fields, applied to
+        /// every object
+        #[structopt(long, parse(from_os_str))]
+        config: Option<PathBuf>,
+
+        // --output is the directory to write one file per object into
+        #[structopt(flatten)]
+        output: OutputOptions,
+    },
+
+    /// Print the dependency graph between object definitions
+    Graph {
+        /// Output format, dot or mermaid
+        #[structopt(short, long, default_value = "dot")]
+        format: GraphFormat,
+
+        /// List the objects that embed this object, directly or transitively
+        #[structopt(long)]
+        dependents_of: Option<String>,
+
+        /// List objects that are not reachable from any root object
+        #[structopt(long)]
+        unreachable: bool,
+
+        /// List references that do not resolve to an object
+        #[structopt(long)]
+        dangling: bool,
+
+        /// List groups of objects that refer to each other in a loop
+        #[structopt(long)]
+        cycles: bool,
+
+        /// Root objects for reachability, defaults to the objects each schema file stands for
+        #[structopt(long)]
+        root: Vec<String>,
+    },
+
+    /// Check references, names, types and keywords of every object without generating payloads
+    Lint {
+        /// Output format, text or json
+        #[structopt(short, long, default_value = "text")]
+        format: ReportFormat,
+
+        /// Exit with a failure status on warnings as well as errors
+        #[structopt(long)]
+        strict: bool,
+    },
+
+    /// Compare the schema directory with a previous version of it and report breaking changes
+    Diff {
+        /// Schema directory holding the previous version
+        base: String,
+
+        /// Output format, text or json
+        #[structopt(short, long, default_value = "text")]
+        format: ReportFormat,
+    },
+
+    /// Convert object definitions into the schema language of another serialization system
+    Export {
+        /// Schema language to export to, avro or proto
+        #[structopt(short, long)]
+        to: ExportTarget,
+
+        /// Only export this object, defaults to the objects each schema file stands for
+        #[structopt(short, long)]
+        object: Option<String>,
+
+        /// File to write the exported schema to instead of stdout
+        #[structopt(long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    /// Generate source code with types mirroring object definitions
+    Codegen {
+        /// Language to generate, rust or typescript
+        language: CodegenLanguage,
+
+        /// Only generate types for this object and the objects it refers to, defaults to the
+        /// objects each schema file stands for
+        #[structopt(short, long)]
+        object: Option<String>,
+
+        /// File to write the generated code to instead of stdout, or an existing directory to write
+        /// one file per generated module into
+        #[structopt(long, parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Also generate tests that round-trip generated payloads through the generated types
+        #[structopt(long)]
+        tests: bool,
+
+        /// Number of payloads embedded in each round-trip test
+        #[structopt(long, default_value = "10")]
+        test_payloads: u64,
+    },
+
+    /// Write one schema for an object with every schema it refers to inlined
+    Bundle {
+        /// Object to bundle
+        #[structopt(short, long)]
+        object: String,
+
+        /// Replace references with the schemas they point to, fails for recursive schemas
+        #[structopt(long)]
+        dereference: bool,
+
+        /// File to write the bundled schema to instead of stdout
+        #[structopt(long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    /// Infer a schema from NDJSON sample payloads and write it into the schema directory
+    Infer {
+        /// NDJSON files with one sample payload per line, read from stdin when none are given
+        #[structopt(parse(from_os_str))]
+        samples: Vec<PathBuf>,
+
+        /// Name of the inferred object and its schema file
+        #[structopt(short, long, default_value = "inferred")]
+        name: String,
+
+        /// Fraction of samples a field must appear in to be required
+        #[structopt(long, default_value = "1.0")]
+        required_ratio: f64,
+
+        /// Largest number of distinct values of a string field that makes it an enum
+        #[structopt(long, default_value = "10")]
+        enum_max: usize,
+
+        /// Print the inferred schema instead of writing it into the schema directory
+        #[structopt(long)]
+        print: bool,
+    },
+
+    /// Write documentation pages for every object into a directory
+    Docs {
+        /// Page format, markdown or html
+        #[structopt(short, long, default_value = "markdown")]
+        format: DocsFormat,
+
+        /// Directory to write the pages into, created when missing
+        #[structopt(long, parse(from_os_str))]
+        output: PathBuf,
+    },
+
+    /// Generate payloads for every object and list the schema keywords they violate
+    Selfcheck {
+        /// Number of payloads to generate per object
+        #[structopt(short = "n", long, default_value = "100")]
+        iterations: u64,
     },
 }
 
@@ -41,9 +285,9 @@ struct Options {
     #[structopt(short, long)]
     schema_dir: String,
 
-    /// Action to perform
+    /// Action to perform, required unless --report-and-exit is given
     #[structopt(subcommand)]
-    command: Command,
+    command: Option<Command>,
 
     /// Exit after showing analyzed object definitions
     #[structopt(short, long)]
@@ -51,56 +295,490 @@ struct Options {
 }
 
 
-fn schema_from_entry(entry: Result<DirEntry>) -> Option<Schema> {
-    let entry = entry.ok()?;
-    let path = entry.path();
+fn report_schemas(reference_map: &BTreeMap<String, ObjectDefinition>, object: Option<&str>, format: ReportFormat) {
+    let reports = report::build_reports(reference_map, object);
+    if let Some(object) = object {
+        if reports.is_empty() {
+            eprintln!("no object named {}", object);
+            process::exit(2);
+        }
+    }
 
-    if entry.file_type().ok()?.is_file() && path.extension()? == "json" {
-        Schema::from_file(&path)
-    } else {
-        None
+    match format {
+        ReportFormat::Text => print!("{}", report::render_text(&reports)),
+        ReportFormat::Json => println!("{}", report::render_json(&reports)),
     }
 }
 
-fn report_schemas(reference_map: &HashMap<String, ObjectDefinition>) {
-    reference_map.iter()
-        .for_each(|(key, object_definition)| {
-            println!("{} in file {}", object_definition.name, key);
-        });
+fn exit_on_error<T>(e: impl std::fmt::Display) -> T {
+    eprintln!("{}", e);
+    process::exit(2);
+}
+
+fn load_schemas(schema_root: &str) -> Chimpsky {
+    Chimpsky::load_dir(schema_root).unwrap_or_else(exit_on_error)
 }
 
-fn randomize_payloads(reference_map: &HashMap<String, ObjectDefinition>, command: &Command) {
-    if let Command::Randomize { object_name, emit_count, prettify } = command {
-        let definition = &reference_map[object_name];
-        for _ in 0..*emit_count {
-            let payload = definition.generate_json(Some(&reference_map)).unwrap();
-            let s = if *prettify {
-                serde_json::to_string_pretty(&payload).unwrap()
+fn resolve_object_key<'a>(schema_set: &'a SchemaSet, object: &'a str) -> &'a str {
+    schema_set.object_key(object).unwrap_or_else(|| {
+        eprintln!("no object named {}", object);
+        process::exit(2);
+    })
+}
+
+fn graph_schemas(schema_set: &SchemaSet, command: &Command) {
+    if let Command::Graph { format, dependents_of, unreachable, dangling, cycles, root } = command {
+        let reference_map = &schema_set.reference_map;
+        let graph = DependencyGraph::new(reference_map);
+
+        if dependents_of.is_none() && !unreachable && !dangling && !cycles {
+            print!("{}", graph.render(*format));
+            return;
+        }
+
+        if let Some(object) = dependents_of {
+            let key = resolve_object_key(schema_set, object);
+            println!("objects depending on {}:", key);
+            graph.dependents(key).iter().for_each(|k| println!("    {}", k));
+        }
+
+        if *unreachable {
+            let roots = if root.is_empty() {
+                schema_set.roots.clone()
             } else {
-                serde_json::to_string(&payload).unwrap()
+                root.iter().map(|r| resolve_object_key(schema_set, r).to_owned()).collect()
             };
-            println!("{}", s);
+            println!("unreachable objects:");
+            graph.unreachable(&roots).iter().for_each(|k| println!("    {}", k));
+        }
+
+        if *dangling {
+            println!("dangling references:");
+            graph.dangling().iter().for_each(|e| println!("    {} in {} via {}", e.to, e.from, e.via));
         }
+
+        if *cycles {
+            println!("reference cycles:");
+            graph.cycles().iter().for_each(|c| println!("    {}", c.join(" -> ")));
+        }
+    }
+}
+
+/// The key of the named object, or the keys of the objects each schema file stands for.
+fn object_keys<'a>(schema_set: &'a SchemaSet, object: Option<&'a str>) -> Vec<&'a str> {
+    match object {
+        Some(object) => vec![resolve_object_key(schema_set, object)],
+        None => schema_set.roots.iter().map(|k| k.as_str()).collect(),
     }
 }
 
+fn export_schemas(schema_set: &SchemaSet, to: ExportTarget, object: Option<&str>, output: Option<&Path>) {
+    let reference_map = &schema_set.reference_map;
+    let exported = export::export(to, &object_keys(schema_set, object), reference_map).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+
+    for loss in &exported.losses {
+        eprintln!("lossy: {} field {}: {}", loss.object, loss.field, loss.message);
+    }
+
+    match output {
+        Some(path) => fs::write(path, exported.schema).unwrap_or_else(exit_on_write_error),
+        None => print!("{}", exported.schema),
+    }
+}
+
+fn codegen_schemas(schema_set: &SchemaSet, command: &Command) {
+    if let Command::Codegen { language, object, output, tests, test_payloads } = command {
+        let reference_map = &schema_set.reference_map;
+        let options = CodegenOptions { tests: *tests, test_payloads: *test_payloads };
+        if *tests && *language != CodegenLanguage::Rust {
+            eprintln!("round-trip tests can only be generated for rust");
+            process::exit(2);
+        }
+
+        let files = codegen::generate(*language, &object_keys(schema_set, object.as_deref()), reference_map, &options)
+            .unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                process::exit(2);
+            });
+
+        match output {
+            Some(dir) if dir.is_dir() => {
+                for (name, code) in files {
+                    fs::write(dir.join(name), code).unwrap_or_else(exit_on_write_error);
+                }
+            }
+            Some(path) if files.len() == 1 => fs::write(path, &files[0].1).unwrap_or_else(exit_on_write_error),
+            Some(_) => {
+                eprintln!("{} files were generated, --output must be an existing directory", files.len());
+                process::exit(2);
+            }
+            None if files.len() == 1 => print!("{}", files[0].1),
+            None => files.iter().for_each(|(name, code)| print!("// {}\n{}\n", name, code)),
+        }
+    }
+}
+
+fn bundle_schema(schema_set: &SchemaSet, object: &str, dereference: bool, output: Option<&Path>) {
+    let bundler = Bundler::new(schema_set, resolve_object_key(schema_set, object));
+    let bundled = if dereference { bundler.dereference() } else { bundler.bundle() };
+    let bundled = bundled.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+
+    let text = serde_json::to_string_pretty(&bundled).unwrap() + "\n";
+    match output {
+        Some(path) => fs::write(path, text).unwrap_or_else(exit_on_write_error),
+        None => print!("{}", text),
+    }
+}
+
+fn infer_schema(schema_dir: &str, command: &Command) {
+    if let Command::Infer { samples, name, required_ratio, enum_max, print } = command {
+        let mut inferrer = Inferrer::new(InferOptions {
+            name: name.to_owned(),
+            required_ratio: *required_ratio,
+            enum_max: *enum_max,
+        });
+
+        let added = if samples.is_empty() {
+            inferrer.add_ndjson(io::stdin().lock())
+        } else {
+            samples.iter().try_for_each(|path| {
+                let file = File::open(path).map_err(|e| format!("cannot open {}: {}", path.display(), e))?;
+                inferrer.add_ndjson(BufReader::new(file))
+            })
+        };
+
+        let schema = added.and_then(|_| inferrer.schema()).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(2);
+        });
+
+        let text = serde_json::to_string_pretty(&schema).unwrap() + "\n";
+        if *print {
+            print!("{}", text);
+        } else {
+            fs::create_dir_all(schema_dir).unwrap_or_else(exit_on_write_error);
+            fs::write(Path::new(schema_dir).join(inferrer.file_name()), text).unwrap_or_else(exit_on_write_error);
+        }
+    }
+}
+
+fn write_docs(schema_set: &SchemaSet, format: DocsFormat, output: &Path) {
+    fs::create_dir_all(output).unwrap_or_else(exit_on_write_error);
+    for (name, page) in docs::render(schema_set, format) {
+        fs::write(output.join(name), page).unwrap_or_else(exit_on_write_error);
+    }
+}
+
+fn lint_schemas(schema_set: &SchemaSet, format: ReportFormat, strict: bool) {
+    let findings = lint::lint(schema_set);
+
+    match format {
+        ReportFormat::Text => print!("{}", lint::render_text(&findings)),
+        ReportFormat::Json => println!("{}", lint::render_json(&findings)),
+    }
+
+    let threshold = if strict { Severity::Warning } else { Severity::Error };
+    if findings.iter().any(|f| f.severity >= threshold) {
+        process::exit(1);
+    }
+}
+
+fn diff_schemas(schema_set: &SchemaSet, base: &str, format: ReportFormat) {
+    let base = load_schemas(base);
+    let changes = diff::diff(base.reference_map(), &schema_set.reference_map).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+
+    match format {
+        ReportFormat::Text => print!("{}", diff::render_text(&changes)),
+        ReportFormat::Json => println!("{}", diff::render_json(&changes)),
+    }
+
+    if changes.iter().any(|c| c.is_breaking()) {
+        process::exit(1);
+    }
+}
+
+fn verifier_for(documents: &BTreeMap<String, Value>, key: &str) -> Verifier {
+    Verifier::new(documents, key).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    })
+}
+
+fn payload_sink(format: OutputFormat, output: &OutputOptions, key: &str, reference_map: &BTreeMap<String, ObjectDefinition>) -> Box<dyn PayloadSink> {
+    let definition = &reference_map[key];
+    let writer = if format.is_avro() {
+        AvroWriter::new(format, output, key, reference_map).map(|w| Box::new(w) as Box<dyn PayloadSink>)
+    } else if format.is_tabular() {
+        TableWriter::new(format, output, definition, reference_map).map(|w| Box::new(w) as Box<dyn PayloadSink>)
+    } else {
+        PayloadWriter::new(format, output, definition.name()).map(|w| Box::new(w) as Box<dyn PayloadSink>)
+    };
+    writer.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    })
+}
+
+fn parse_object_number(s: &str) -> Result<(String, u64), String> {
+    let (object, number) = s.rsplit_once('=').ok_or_else(|| format!("{} is not an object=number pair", s))?;
+    let number = number.parse().map_err(|_| format!("{} is not a whole number", number))?;
+    Ok((object.to_owned(), number))
+}
+
+fn generate_dataset(chimpsky: &Chimpsky, command: &Command) {
+    if let Command::Dataset { objects, seed, config, output } = command {
+        let directory = output.output.as_ref().filter(|d| d.is_dir()).unwrap_or_else(|| {
+            eprintln!("datasets are written into an existing --output directory");
+            process::exit(2);
+        });
+
+        let mut parts = vec![];
+        for (i, (object, count)) in objects.iter().enumerate() {
+            let mut generator = chimpsky.generator(object).unwrap_or_else(exit_on_error);
+            if let Some(seed) = seed {
+                generator = generator.seed(seed.wrapping_add(i as u64));
+            }
+            parts.push((generator, *count));
+        }
+        let mut dataset = Dataset::new(chimpsky, parts).unwrap_or_else(exit_on_error);
+        if let Some(config) = config {
+            let overrides = Overrides::load(config).unwrap_or_else(exit_on_error);
+            dataset = dataset.overrides(overrides).unwrap_or_else(exit_on_error);
+        }
+
+        let format = output.output_format.unwrap_or(OutputFormat::Ndjson);
+        let reference_map = chimpsky.reference_map();
+        let mut writers: BTreeMap<&str, Box<dyn PayloadSink>> = BTreeMap::new();
+        for generator in dataset.generators() {
+            let name = reference_map[generator.key()].name();
+            let options = OutputOptions { output: Some(directory.join(format!("{}.{}", name, format.extension()))), ..output.clone() };
+            writers.insert(generator.key(), payload_sink(format, &options, generator.key(), reference_map));
+        }
+
+        for (key, payload) in dataset {
+            writers.get_mut(key).unwrap().write(&payload).unwrap_or_else(exit_on_write_error);
+        }
+        for writer in writers.into_values() {
+            for file in writer.finish().unwrap_or_else(exit_on_write_error) {
+                eprintln!("wrote {}", file.display());
+            }
+        }
+    }
+}
+
+fn randomize_payloads(chimpsky: &Chimpsky, command: &Command) {
+    if let Command::Randomize { object_name, object, all, envelope, emit_count, prettify, verify, seed, config, cover,
+        realistic, locale, datetime_from, datetime_to, datetime_distribution, datetime_offsets, fractional_seconds,
+        output } = command {
+        let generator_of = |object: &str| if *realistic {
+            chimpsky.realistic_generator(object, *locale)
+        } else {
+            chimpsky.generator(object)
+        };
+        let objects: Vec<(&str, u64)> = match object_name {
+            Some(object_name) => vec![(object_name, 1)],
+            None if *all => chimpsky.reference_map().keys()
+                .filter(|key| match generator_of(key) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        eprintln!("skipping {}", e);
+                        false
+                    }
+                })
+                .map(|key| (key.as_str(), 1))
+                .collect(),
+            None => object.iter().map(|(object, weight)| (object.as_str(), *weight)).collect(),
+        };
+
+        let mut datetimes = DateTimes::parse(datetime_from, datetime_to)
+            .unwrap_or_else(exit_on_error)
+            .distribution(*datetime_distribution);
+        if *datetime_offsets {
+            datetimes = datetimes.offsets();
+        }
+        if *fractional_seconds {
+            datetimes = datetimes.fractional_seconds();
+        }
+
+        let mut parts = vec![];
+        for (i, (object, weight)) in objects.into_iter().enumerate() {
+            let mut generator = generator_of(object).unwrap_or_else(exit_on_error);
+            if let Some(seed) = seed {
+                generator = generator.seed(seed.wrapping_add(i as u64));
+            }
+            if *cover {
+                generator = generator.cover();
+            }
+            parts.push((generator.datetimes(datetimes.clone()), weight));
+        }
+        let mixed = parts.len() > 1;
+        let mut mixture = Mixture::new(parts).unwrap_or_else(exit_on_error);
+        if let Some(seed) = seed {
+            mixture = mixture.seed(*seed);
+        }
+        if let Some(config) = config {
+            mixture = mixture.overrides(Overrides::load(config).unwrap_or_else(exit_on_error));
+        }
+
+        let reference_map = chimpsky.reference_map();
+        let documents = &chimpsky.schema_set().documents;
+        let verifiers = mixture.generators()
+            .filter(|_| *verify)
+            .map(|g| (g.key(), verifier_for(documents, g.key())))
+            .collect::<BTreeMap<_, _>>();
+
+        let format = output.output_format
+            .unwrap_or(if *prettify { OutputFormat::Pretty } else { OutputFormat::Ndjson });
+        let mut writer = match mixture.generators().next() {
+            Some(generator) if !mixed && !*envelope => payload_sink(format, output, generator.key(), reference_map),
+            _ if format.is_tabular() || format.is_avro() => {
+                eprintln!("{:?} output holds payloads of a single object, without --envelope", format);
+                process::exit(2);
+            }
+            first => {
+                let object = if mixed { "mixed" } else { reference_map[first.unwrap().key()].name() };
+                let writer = PayloadWriter::new(format, output, object).unwrap_or_else(exit_on_error);
+                Box::new(writer) as Box<dyn PayloadSink>
+            }
+        };
+
+        for emitted in 0..*emit_count {
+            if emitted > 0 && mixture.generators().all(|g| g.coverage().is_some_and(|c| c.is_complete())) {
+                break;
+            }
+
+            let (key, payload) = mixture.generate();
+            if let Some(verifier) = verifiers.get(key) {
+                let violations = verifier.verify(&payload);
+                if !violations.is_empty() {
+                    eprintln!("generated payload does not conform to {}: {}", key, payload);
+                    violations.iter().for_each(|v| eprintln!("    {}: {}", v.instance_path, v.message));
+                    process::exit(1);
+                }
+            }
+
+            let payload = if *envelope { chimpsky::envelope(key, payload) } else { payload };
+            writer.write(&payload).unwrap_or_else(exit_on_write_error);
+        }
+
+        writer.finish().unwrap_or_else(exit_on_write_error);
+
+        if mixed {
+            eprint!("{}", mixture.render_counts());
+        }
+        let coverages = mixture.generators().filter_map(|g| g.coverage()).collect::<Vec<_>>();
+        for coverage in &coverages {
+            eprint!("{}", coverage.render_text());
+        }
+        if coverages.iter().any(|c| !c.is_complete()) {
+            process::exit(1);
+        }
+    }
+}
+
+fn exit_on_write_error<T>(e: std::io::Error) -> T {
+    if e.kind() != std::io::ErrorKind::BrokenPipe {
+        eprintln!("cannot write payloads: {}", e);
+    }
+    process::exit(1);
+}
+
+fn selfcheck(schema_set: &SchemaSet, iterations: u64) {
+    let mut failing_keywords: BTreeMap<String, u64> = BTreeMap::new();
+    let mut skipped = 0;
+
+    for check in verifier::selfcheck(schema_set, iterations) {
+        match check {
+            ObjectCheck::Checked { key, invalid, violations } => {
+                println!("{}: {}/{} payloads invalid", key, invalid, iterations);
+                for ((keyword, path), count) in violations {
+                    println!("    {} at {} ({} times)", keyword, path, count);
+                    *failing_keywords.entry(keyword).or_default() += count;
+                }
+            }
+            ObjectCheck::Skipped { key, reason } => {
+                println!("{}: skipped, {}", key, reason);
+                skipped += 1;
+            }
+        }
+    }
+
+    if failing_keywords.is_empty() {
+        println!("all generated payloads conform to their schema");
+    } else {
+        println!("keywords producing invalid output:");
+        for (keyword, count) in &failing_keywords {
+            println!("    {} ({} violations)", keyword, count);
+        }
+    }
+    if skipped > 0 {
+        println!("{} objects could not be checked", skipped);
+    }
+    if !failing_keywords.is_empty() {
+        process::exit(1);
+    }
+}
 
 fn main() {
     let options: Options = Options::from_args();
+    if options.command.is_none() && !options.report_and_exit {
+        structopt::clap::Error::with_description(
+            "a subcommand is required unless --report-and-exit is given",
+            structopt::clap::ErrorKind::MissingSubcommand,
+        ).exit();
+    }
 
-    let schema_root = &options.schema_dir;
-    let dir = fs::read_dir(schema_root).unwrap();
+    // the schema directory is where inferred schemas are written, it need not exist yet
+    if let Some(command @ Command::Infer { .. }) = &options.command {
+        infer_schema(&options.schema_dir, command);
+        return;
+    }
 
-    let mut reference_map: HashMap<String, ObjectDefinition> = HashMap::new();
+    let chimpsky = load_schemas(&options.schema_dir);
+    let schema_set = chimpsky.schema_set();
+    let reference_map = &schema_set.reference_map;
 
-    for entry in dir {
-        schema_from_entry(entry).map(|schema| reference_map.extend(schema.export_definitions().unwrap()));
-    }
+    let command = match &options.command {
+        Some(command) if !options.report_and_exit => command,
+        _ => {
+            report_schemas(reference_map, None, ReportFormat::Text);
+            return;
+        }
+    };
 
-    match &options.command {
-        Command::Report =>
-            report_schemas(&reference_map),
+    match command {
+        Command::Report { object, format } =>
+            report_schemas(reference_map, object.as_deref(), *format),
         Command::Randomize { .. } =>
-            randomize_payloads(&reference_map, &options.command),
+            randomize_payloads(&chimpsky, command),
+        Command::Dataset { .. } =>
+            generate_dataset(&chimpsky, command),
+        Command::Graph { .. } =>
+            graph_schemas(schema_set, command),
+        Command::Lint { format, strict } =>
+            lint_schemas(schema_set, *format, *strict),
+        Command::Diff { base, format } =>
+            diff_schemas(schema_set, base, *format),
+        Command::Export { to, object, output } =>
+            export_schemas(schema_set, *to, object.as_deref(), output.as_deref()),
+        Command::Codegen { .. } =>
+            codegen_schemas(schema_set, command),
+        Command::Bundle { object, dereference, output } =>
+            bundle_schema(schema_set, object, *dereference, output.as_deref()),
+        Command::Infer { .. } =>
+            unreachable!("schemas are inferred before the schema directory is loaded"),
+        Command::Docs { format, output } =>
+            write_docs(schema_set, *format, output),
+        Command::Selfcheck { iterations } =>
+            selfcheck(schema_set, *iterations),
     }
 }
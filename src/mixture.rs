@@ -0,0 +1,171 @@
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde_json::{json, Value};
+
+use crate::overrides::Overrides;
+use crate::random_values;
+use crate::{Error, Generator};
+
+/// Mixed into the seed of the mixture, so that it does not draw the same numbers as a generator
+/// given the same seed.
+const SEED_SALT: u64 = 0x6d69_7874_7572_6573;
+
+/// Payloads of several objects interleaved at random, each object picked in proportion to its
+/// weight. Which object comes next is drawn separately from the payloads, so seeding the
+/// generators and the mixture repeats a stream exactly.
+pub struct Mixture<'a> {
+    generators: Vec<Generator<'a>>,
+    weights: WeightedIndex<u64>,
+    counts: Vec<u64>,
+    rng: Option<StdRng>,
+    overrides: Option<Overrides>,
+}
+
+impl<'a> Mixture<'a> {
+    /// Generators with their weights, which need not add up to anything in particular.
+    pub fn new(parts: Vec<(Generator<'a>, u64)>) -> Result<Self, Error> {
+        let (generators, weights): (Vec<_>, Vec<_>) = parts.into_iter().unzip();
+        let weights = WeightedIndex::new(&weights)
+            .map_err(|e| Error::InvalidWeights(format!("cannot mix objects with these weights: {}", e)))?;
+        let counts = vec![0; generators.len()];
+        Ok(Mixture { generators, weights, counts, rng: None, overrides: Some(Overrides::default()) })
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.rng = Some(StdRng::seed_from_u64(seed ^ SEED_SALT));
+        self
+    }
+
+    /// Generates every object with `overrides` in place of the overrides of its generator, so
+    /// that sequences and unique values carry on across objects.
+    pub fn overrides(mut self, overrides: Overrides) -> Self {
+        self.overrides = Some(overrides);
+        self
+    }
+
+    pub fn generators(&self) -> impl Iterator<Item = &Generator<'a>> {
+        self.generators.iter()
+    }
+
+    /// Payloads generated so far by object key, in the order the generators were given.
+    pub fn counts(&self) -> impl Iterator<Item = (&'a str, u64)> + '_ {
+        self.generators.iter().map(|g| g.key()).zip(self.counts.iter().copied())
+    }
+
+    pub fn render_counts(&self) -> String {
+        let total: u64 = self.counts.iter().sum();
+        let mut out = format!("payloads: {}\n", total);
+        for (key, count) in self.counts() {
+            out += &format!("    {}: {}\n", key, count);
+        }
+        out
+    }
+
+    pub fn generate(&mut self) -> (&'a str, Value) {
+        let weights = &self.weights;
+        let picked = random_values::with_rng(&mut self.rng, || weights.sample(&mut random_values::rng()));
+        self.counts[picked] += 1;
+
+        let generator = &mut self.generators[picked];
+        std::mem::swap(&mut generator.overrides, &mut self.overrides);
+        let payload = generator.generate();
+        std::mem::swap(&mut generator.overrides, &mut self.overrides);
+        (generator.key(), payload)
+    }
+}
+
+impl<'a> Iterator for Mixture<'a> {
+    /// Reference key of the object and the payload.
+    type Item = (&'a str, Value);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.generate())
+    }
+}
+
+/// A payload wrapped together with the key of its object, for streams carrying several objects.
+pub fn envelope(key: &str, payload: Value) -> Value {
+    json!({ "object": key, "payload": payload })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schema_parser::{Schema, SchemaSet};
+    use crate::Chimpsky;
+
+    use super::*;
+
+    fn chimpsky() -> Chimpsky {
+        let mut set = SchemaSet::default();
+        set.add(Schema::new(&json!({
+            "definitions": {
+                "click": { "type": "object", "properties": { "x": { "type": "integer" } }, "required": ["x"] },
+                "view": { "type": "object", "properties": { "page": { "type": "string" } }, "required": ["page"] },
+                "purchase": { "type": "object", "properties": { "total": { "type": "number" } }, "required": ["total"] }
+            }
+        }), Some("events.json".to_owned()))).unwrap();
+        Chimpsky::from(set)
+    }
+
+    fn mixture(chimpsky: &Chimpsky, weights: [u64; 3]) -> Result<Mixture<'_>, Error> {
+        let parts = ["click", "view", "purchase"].iter()
+            .zip(weights)
+            .enumerate()
+            .map(|(i, (object, weight))| (chimpsky.generator(object).unwrap().seed(i as u64), weight))
+            .collect();
+        Mixture::new(parts)
+    }
+
+    #[test]
+    fn objects_are_mixed_by_weight() {
+        let chimpsky = chimpsky();
+        let mut mixture = mixture(&chimpsky, [70, 30, 0]).unwrap().seed(5);
+        let records = mixture.by_ref().take(1000).collect::<Vec<_>>();
+
+        let counts = mixture.counts().collect::<Vec<_>>();
+        assert_eq!(counts.iter().map(|(_, c)| c).sum::<u64>(), 1000);
+        assert!((620..780).contains(&counts[0].1), "{:?}", counts);
+        assert_eq!(counts[2], ("events.json#/definitions/purchase", 0));
+        assert!(records.iter().all(|(key, payload)| payload.get(if key.ends_with("click") { "x" } else { "page" }).is_some()));
+        assert!(mixture.render_counts().starts_with("payloads: 1000\n    events.json#/definitions/click: "));
+    }
+
+    #[test]
+    fn seeded_streams_repeat() {
+        let chimpsky = chimpsky();
+        let records = || mixture(&chimpsky, [1, 1, 1]).unwrap().seed(9).take(20).collect::<Vec<_>>();
+        assert_eq!(records(), records());
+    }
+
+    #[test]
+    fn sequences_carry_on_across_objects() {
+        let mut set = SchemaSet::default();
+        set.add(Schema::new(&json!({
+            "definitions": {
+                "event": { "type": "object", "properties": { "seq": { "type": "integer", "x-chimpsky-sequence": true } }, "required": ["seq"] },
+                "click": { "allOf": [ { "$ref": "#/definitions/event" } ] },
+                "view": { "allOf": [ { "$ref": "#/definitions/event" } ] }
+            }
+        }), Some("events.json".to_owned()))).unwrap();
+        let chimpsky = Chimpsky::from(set);
+        let parts = vec![(chimpsky.generator("click").unwrap(), 1), (chimpsky.generator("view").unwrap(), 1)];
+
+        let records = Mixture::new(parts).unwrap().seed(3).take(10).collect::<Vec<_>>();
+        assert!(records.iter().any(|(key, _)| key.ends_with("view")) && records.iter().any(|(key, _)| key.ends_with("click")));
+        assert_eq!(records.iter().map(|(_, payload)| payload["seq"].as_u64().unwrap()).collect::<Vec<_>>(), (1..=10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn weights_must_pick_something() {
+        let chimpsky = chimpsky();
+        assert!(matches!(mixture(&chimpsky, [0, 0, 0]), Err(Error::InvalidWeights(_))));
+        assert!(matches!(Mixture::new(vec![]), Err(Error::InvalidWeights(_))));
+    }
+
+    #[test]
+    fn envelopes_record_the_object() {
+        assert_eq!(envelope("events.json#/definitions/click", json!({ "x": 1 })),
+                   json!({ "object": "events.json#/definitions/click", "payload": { "x": 1 } }));
+    }
+}
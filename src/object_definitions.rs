@@ -1,20 +1,25 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use serde_json::{Map, Value};
 
-use crate::field_definitions::{FieldDefinition, parse_field_definitions};
+use crate::coverage;
+use crate::field_definitions::{ANNOTATION_KEYWORDS, FieldDefinition, parse_field_definitions};
+use crate::schema_parser::qualify_reference;
+
+const SUPPORTED_KEYWORDS: &[&str] = &["type", "required", "properties", "allOf"];
 
 #[derive(Debug)]
 pub struct ObjectDefinition {
-    pub name: String,
-    pub kind: String,
-    pub required: Option<Vec<String>>,
-    pub field_definitions: Option<Vec<FieldDefinition>>,
-    pub references: Option<Vec<String>>,
+    pub(crate) name: String,
+    pub(crate) kind: String,
+    pub(crate) required: Option<Vec<String>>,
+    pub(crate) field_definitions: Option<Vec<FieldDefinition>>,
+    pub(crate) references: Option<Vec<String>>,
+    pub(crate) unsupported: Vec<String>,
 }
 
 impl ObjectDefinition {
-    pub fn new(name: &str, v: &Value) -> Self {
+    pub(crate) fn new(name: &str, v: &Value) -> Self {
         let name = name.to_owned();
         let mut od = ObjectDefinition {
             name,
@@ -22,6 +27,7 @@ impl ObjectDefinition {
             required: None,
             field_definitions: None,
             references: None,
+            unsupported: vec![],
         };
 
         assert!(v.is_object());
@@ -51,47 +57,65 @@ impl ObjectDefinition {
 
                 od.references = Some(refs);
             }
+
+            if !SUPPORTED_KEYWORDS.contains(&k.as_str()) && !ANNOTATION_KEYWORDS.contains(&k.as_str()) {
+                od.unsupported.push(k.to_owned());
+            }
         }
         od
     }
 
-    pub fn generate_json(&self, reference_map: Option<&HashMap<String, ObjectDefinition>>) -> Option<Value> {
+    /// Name of the definition, the schema file stem for single definition files.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Fields pulled in through `allOf` references come first, followed by the object's own
+    /// properties in the order the schema declares them.
+    pub fn generate_json(&self, reference_map: Option<&BTreeMap<String, ObjectDefinition>>) -> Option<Value> {
+        let omitted = coverage::omitted_fields(self, reference_map);
+        self.generate_fields(reference_map, &omitted).map(Value::Object)
+    }
+
+    /// Fields named in `omitted` are left out wherever they are declared, so that whether an
+    /// optional field is present is decided for the object including it.
+    fn generate_fields(&self, reference_map: Option<&BTreeMap<String, ObjectDefinition>>, omitted: &[String]) -> Option<Map<String, Value>> {
         self.field_definitions.as_ref().map(|field_definitions| {
-            let mut v = field_definitions.iter()
-                .map(|field| field.generate_json_elements(reference_map))
-                .collect::<Map<String, Value>>();
+            let mut v = self.populate_references_without(reference_map, omitted).unwrap_or_default();
 
-            if let Some(Value::Object(m)) = self.populate_references(reference_map) {
-                v.extend(m);
+            for (name, value) in field_definitions.iter()
+                .filter(|field| !omitted.contains(&field.name))
+                .map(|field| field.generate_json_elements(reference_map)) {
+                v.entry(name).or_insert(value);
             }
 
-            v.into()
+            v
         })
     }
 
-    pub fn populate_references(&self, reference_map: Option<&HashMap<String, ObjectDefinition>>) -> Option<Value> {
+    fn populate_references_without(&self, reference_map: Option<&BTreeMap<String, ObjectDefinition>>, omitted: &[String]) -> Option<Map<String, Value>> {
         reference_map.map(|reference_map| {
             let mut m = Map::new();
-            self.references.as_ref().map(|references| {
+            if let Some(references) = self.references.as_ref() {
                 for r in references {
                     let definition = &reference_map[r];
-                    if let Some(Value::Object(o)) = definition.generate_json(Some(reference_map)) {
+                    if let Some(o) = definition.generate_fields(Some(reference_map), omitted) {
                         m.extend(o);
                     }
                 }
-            });
-            m.into()
+            }
+            m
         })
     }
 
     fn parse_all_of(v: &Value) -> (Vec<String>, Vec<FieldDefinition>) {
-        assert!(v.is_array(), format!("allOf {} is not an array", v));
+        assert!(v.is_array(), "allOf {} is not an array", v);
 
         let mut references = vec![];
         let mut field_definitions = vec![];
 
         for m in v.as_array().unwrap().iter() {
-            assert!(m.is_object(), format!("value in allOf {} is not an object", m));
+            assert!(m.is_object(), "value in allOf {} is not an object", m);
 
             for (k, v) in m.as_object().unwrap() {
                 if k == "$ref" {
@@ -108,8 +132,58 @@ impl ObjectDefinition {
         (references, field_definitions)
     }
 
+    pub(crate) fn qualify_references(&mut self, filename: &str) {
+        if let Some(references) = self.references.as_mut() {
+            references.iter_mut().for_each(|r| *r = qualify_reference(filename, r));
+        }
+
+        if let Some(fields) = self.field_definitions.as_mut() {
+            fields.iter_mut()
+                .filter_map(|f| f.kind.as_mut())
+                .for_each(|k| k.qualify_references(filename));
+        }
+    }
+
+    pub(crate) fn own_fields(&mut self, key: &str) {
+        self.field_definitions.iter_mut().flatten().for_each(|f| f.owner = key.to_owned());
+    }
+
+    /// Fields of this object in the order `generate_json` produces them, each with whether it
+    /// may be left out. A field is required when the object or the definition declaring it says so.
+    pub(crate) fn all_fields<'a>(&'a self, reference_map: &'a BTreeMap<String, ObjectDefinition>) -> Result<Vec<(&'a FieldDefinition, bool)>, String> {
+        self.fields_required_by(self, reference_map)
+    }
+
+    fn fields_required_by<'a>(&'a self,
+                              including: &ObjectDefinition,
+                              reference_map: &'a BTreeMap<String, ObjectDefinition>) -> Result<Vec<(&'a FieldDefinition, bool)>, String> {
+        let mut fields: Vec<(&FieldDefinition, bool)> = vec![];
+
+        for r in self.references.iter().flatten() {
+            let referenced = reference_map.get(r).ok_or_else(|| format!("cannot resolve {}", r))?;
+            for (field, optional) in referenced.fields_required_by(including, reference_map)? {
+                if !fields.iter().any(|(f, _)| f.name == field.name) {
+                    fields.push((field, optional));
+                }
+            }
+        }
+
+        for field in self.field_definitions.iter().flatten() {
+            if !fields.iter().any(|(f, _)| f.name == field.name) {
+                let optional = !self.is_required(&field.name) && !including.is_required(&field.name);
+                fields.push((field, optional));
+            }
+        }
+
+        Ok(fields)
+    }
+
+    pub(crate) fn is_required(&self, field: &str) -> bool {
+        self.required.as_ref().is_some_and(|required| required.iter().any(|r| r == field))
+    }
+
     fn parse_required(v: &Value) -> Vec<String> {
-        assert!(v.is_array(), format!("required {} is not an array", v));
+        assert!(v.is_array(), "required {} is not an array", v);
         v.as_array()
             .unwrap()
             .iter()
@@ -118,12 +192,12 @@ impl ObjectDefinition {
     }
 
     fn parse_kind(v: &Value) -> String {
-        assert!(v.is_string(), format!("kind {} is not a string", v));
+        assert!(v.is_string(), "kind {} is not a string", v);
         v.as_str().unwrap().to_owned()
     }
 }
 
-pub fn parse_definitions(v: &Value) -> HashMap<String, ObjectDefinition> {
+pub fn parse_definitions(v: &Value) -> BTreeMap<String, ObjectDefinition> {
     assert!(v.is_object());
     let v = v.as_object().unwrap();
     v.iter()
@@ -236,4 +310,24 @@ mod tests {
         assert!(v["is_working"].is_boolean());
         assert!(v["weight"].is_number());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn generate_json_preserves_declared_order() {
+        let v: Value = serde_json::from_str(r#" {
+        "base": {
+          "type": "object",
+          "properties": { "zulu": { "type": "integer" }, "alpha": { "type": "integer" } }
+        },
+        "derived": {
+          "type": "object",
+          "allOf": [
+            { "$ref": "base" },
+            { "properties": { "yankee": { "type": "string" }, "bravo": { "type": "boolean" } } }
+          ]
+        } } "#).unwrap();
+        let definitions = parse_definitions(&v);
+        let v = definitions["derived"].generate_json(Some(&definitions)).unwrap();
+        let keys = v.as_object().unwrap().keys().collect::<Vec<_>>();
+        assert_eq!(keys, vec!["zulu", "alpha", "yankee", "bravo"]);
+    }
+}
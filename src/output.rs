@@ -0,0 +1,526 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde_json::Value;
+use structopt::StructOpt;
+
+use crate::encoders;
+use crate::flatten::ArrayMode;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Ndjson,
+    Array,
+    Pretty,
+    MessagePack,
+    Cbor,
+    Bson,
+    Csv,
+    Parquet,
+    Avro,
+    AvroContainer,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            "array" => Ok(OutputFormat::Array),
+            "pretty" => Ok(OutputFormat::Pretty),
+            "msgpack" => Ok(OutputFormat::MessagePack),
+            "cbor" => Ok(OutputFormat::Cbor),
+            "bson" => Ok(OutputFormat::Bson),
+            "csv" => Ok(OutputFormat::Csv),
+            "parquet" => Ok(OutputFormat::Parquet),
+            "avro" => Ok(OutputFormat::Avro),
+            "avro-ocf" => Ok(OutputFormat::AvroContainer),
+            _ => Err(format!("unknown output format {}, expected ndjson, array, pretty, msgpack, cbor, bson, csv, parquet, avro or avro-ocf", s)),
+        }
+    }
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Array | OutputFormat::Pretty => "json",
+            OutputFormat::MessagePack => "msgpack",
+            OutputFormat::Cbor => "cbor",
+            OutputFormat::Bson => "bson",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Avro | OutputFormat::AvroContainer => "avro",
+        }
+    }
+
+    /// Tabular formats flatten payloads into rows and are written by `flatten::TableWriter`.
+    pub fn is_tabular(self) -> bool {
+        matches!(self, OutputFormat::Csv | OutputFormat::Parquet)
+    }
+
+    /// Avro formats encode payloads against a schema derived from the object and are written by
+    /// `avro::AvroWriter`.
+    pub fn is_avro(self) -> bool {
+        matches!(self, OutputFormat::Avro | OutputFormat::AvroContainer)
+    }
+
+    pub fn is_binary(self) -> bool {
+        matches!(self, OutputFormat::MessagePack | OutputFormat::Cbor | OutputFormat::Bson)
+    }
+
+    /// Bytes written once at the start of every output file or stream.
+    pub fn prelude(self) -> &'static [u8] {
+        match self {
+            OutputFormat::Array => b"[\n",
+            _ => b"",
+        }
+    }
+
+    /// Bytes written between two payloads of the same output file or stream.
+    pub fn separator(self) -> &'static [u8] {
+        match self {
+            OutputFormat::Array => b",\n",
+            _ => b"",
+        }
+    }
+
+    /// Bytes written once at the end of every output file or stream.
+    pub fn epilogue(self, empty: bool) -> &'static [u8] {
+        match self {
+            OutputFormat::Array if empty => b"]\n",
+            OutputFormat::Array => b"\n]\n",
+            _ => b"",
+        }
+    }
+
+    pub fn encode(self, payload: &Value) -> Result<Vec<u8>, String> {
+        match self {
+            OutputFormat::Ndjson => {
+                let mut bytes = serde_json::to_vec(payload).unwrap();
+                bytes.push(b'\n');
+                Ok(bytes)
+            }
+            OutputFormat::Array => Ok(serde_json::to_vec(payload).unwrap()),
+            OutputFormat::Pretty => {
+                let mut bytes = serde_json::to_vec_pretty(payload).unwrap();
+                bytes.push(b'\n');
+                Ok(bytes)
+            }
+            OutputFormat::MessagePack => encoders::to_messagepack(payload),
+            OutputFormat::Cbor => encoders::to_cbor(payload),
+            OutputFormat::Bson => encoders::to_bson(payload),
+            OutputFormat::Csv | OutputFormat::Parquet => Err(format!("{:?} payloads are written as table rows", self)),
+            OutputFormat::Avro | OutputFormat::AvroContainer => Err(format!("{:?} payloads need an avro schema", self)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Framing {
+    None,
+    LengthPrefixed,
+}
+
+impl FromStr for Framing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Framing::None),
+            "length-prefixed" => Ok(Framing::LengthPrefixed),
+            _ => Err(format!("unknown framing {}, expected none or length-prefixed", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct OutputOptions {
+    /// Layout of emitted payloads, ndjson, array, pretty, msgpack, cbor, bson, csv, parquet, avro
+    /// for raw avro datums or avro-ocf for an avro object container file
+    #[structopt(long)]
+    pub output_format: Option<OutputFormat>,
+
+    /// Framing of binary payloads and avro datums, none or length-prefixed with a big endian u32
+    #[structopt(long, default_value = "none")]
+    pub framing: Framing,
+
+    /// File to write payloads to, or an existing directory to write one file per payload into
+    #[structopt(long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+
+    /// Name of files written into an output directory, {object}, {index} or {index:N} for an index
+    /// padded to N digits, and {ext} are replaced
+    #[structopt(long, default_value = "{object}-{index:6}.{ext}")]
+    pub file_template: String,
+
+    /// Start a new output file after this many payloads
+    #[structopt(long)]
+    pub rotate_count: Option<u64>,
+
+    /// Start a new output file before it grows beyond this many bytes
+    #[structopt(long)]
+    pub rotate_size: Option<u64>,
+
+    /// How csv and parquet output lays out arrays, explode, json or index
+    #[structopt(long, default_value = "json")]
+    pub array_mode: ArrayMode,
+
+    /// Number of columns per array when arrays are laid out by index
+    #[structopt(long, default_value = "10")]
+    pub array_columns: usize,
+}
+
+/// Destination for generated payloads.
+pub trait PayloadSink {
+    fn write(&mut self, payload: &Value) -> io::Result<()>;
+
+    /// Completes the output and returns the files created, if any.
+    fn finish(self: Box<Self>) -> io::Result<Vec<PathBuf>>;
+}
+
+enum Target {
+    Stdout,
+    Files { template: String, rotate_count: Option<u64>, rotate_size: Option<u64> },
+}
+
+/// Writes encoded payloads to stdout or to files, starting a new file whenever the rotation
+/// limits are reached. Every file is a complete document in the chosen format.
+pub struct PayloadWriter {
+    format: OutputFormat,
+    framing: Framing,
+    target: Target,
+    object: String,
+    current: Option<Box<dyn Write>>,
+    current_count: u64,
+    current_size: u64,
+    files: Vec<PathBuf>,
+}
+
+impl PayloadWriter {
+    pub fn new(format: OutputFormat, options: &OutputOptions, object: &str) -> Result<Self, String> {
+        if format.is_tabular() {
+            return Err(format!("{:?} output is written as table rows", format));
+        }
+
+        if format.is_avro() {
+            return Err(format!("{:?} output is written by the avro writer", format));
+        }
+
+        if options.framing != Framing::None && !format.is_binary() {
+            return Err("framing only applies to msgpack, cbor, bson and avro output".to_owned());
+        }
+
+        let target = match &options.output {
+            None if options.rotate_count.is_some() || options.rotate_size.is_some() =>
+                return Err("rotation needs an --output file or directory".to_owned()),
+            None => Target::Stdout,
+            Some(path) if path.is_dir() => Target::Files {
+                template: escape_braces(&path.to_string_lossy()) + std::path::MAIN_SEPARATOR_STR + &options.file_template,
+                rotate_count: options.rotate_count.or_else(|| options.rotate_size.map_or(Some(1), |_| None)),
+                rotate_size: options.rotate_size,
+            },
+            Some(path) if options.rotate_count.is_some() || options.rotate_size.is_some() => Target::Files {
+                template: rotated_template(path),
+                rotate_count: options.rotate_count,
+                rotate_size: options.rotate_size,
+            },
+            Some(path) => Target::Files {
+                template: escape_braces(&path.to_string_lossy()),
+                rotate_count: None,
+                rotate_size: None,
+            },
+        };
+
+        Ok(PayloadWriter {
+            format,
+            framing: options.framing,
+            target,
+            object: object.to_owned(),
+            current: None,
+            current_count: 0,
+            current_size: 0,
+            files: vec![],
+        })
+    }
+
+    fn needs_rotation(&self, next: u64) -> bool {
+        match &self.target {
+            Target::Files { rotate_count, rotate_size, .. } if self.current.is_some() && self.current_count > 0 => {
+                rotate_count.is_some_and(|c| self.current_count >= c) ||
+                    rotate_size.is_some_and(|s| self.current_size + next > s)
+            }
+            _ => false,
+        }
+    }
+
+    fn open_next(&mut self) -> io::Result<()> {
+        let mut out: Box<dyn Write> = match &self.target {
+            Target::Stdout => Box::new(BufWriter::new(io::stdout())),
+            Target::Files { template, .. } => {
+                let path = PathBuf::from(render_template(template, &self.object, self.files.len() as u64, self.format.extension()));
+                if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                    fs::create_dir_all(parent)?;
+                }
+                let file = File::create(&path)?;
+                self.files.push(path);
+                Box::new(BufWriter::new(file))
+            }
+        };
+
+        out.write_all(self.format.prelude())?;
+        self.current_size = self.format.prelude().len() as u64;
+        self.current_count = 0;
+        self.current = Some(out);
+        Ok(())
+    }
+
+    fn close_current(&mut self) -> io::Result<()> {
+        if let Some(mut out) = self.current.take() {
+            out.write_all(self.format.epilogue(self.current_count == 0))?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl PayloadSink for PayloadWriter {
+    fn write(&mut self, payload: &Value) -> io::Result<()> {
+        let bytes = self.format.encode(payload).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let bytes = match self.framing {
+            Framing::None => bytes,
+            Framing::LengthPrefixed => encoders::length_prefixed(bytes),
+        };
+
+        if self.needs_rotation(bytes.len() as u64) {
+            self.close_current()?;
+        }
+
+        if self.current.is_none() {
+            self.open_next()?;
+        }
+
+        let separator = if self.current_count > 0 { self.format.separator() } else { b"" };
+        let out = self.current.as_mut().unwrap();
+        out.write_all(separator)?;
+        out.write_all(&bytes)?;
+
+        self.current_count += 1;
+        self.current_size += (separator.len() + bytes.len()) as u64;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<Vec<PathBuf>> {
+        let single_document = match &self.target {
+            Target::Stdout => true,
+            Target::Files { rotate_count, rotate_size, .. } => rotate_count.is_none() && rotate_size.is_none(),
+        };
+
+        if self.current.is_none() && self.files.is_empty() && single_document {
+            self.open_next()?;
+        }
+        self.close_current()?;
+        Ok(std::mem::take(&mut self.files))
+    }
+}
+
+/// `out/payloads.json` becomes `out/payloads-{index:4}.json`, with braces in the original path
+/// escaped so they are not mistaken for placeholders.
+fn rotated_template(path: &Path) -> String {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}-{{index:4}}.{}", escape_braces(&stem), escape_braces(&ext.to_string_lossy())),
+        None => format!("{}-{{index:4}}", escape_braces(&stem)),
+    };
+
+    match path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        Some(parent) => escape_braces(&parent.to_string_lossy()) + std::path::MAIN_SEPARATOR_STR + &name,
+        None => name,
+    }
+}
+
+fn escape_braces(s: &str) -> String {
+    s.replace('{', "{{").replace('}', "}}")
+}
+
+pub fn render_template(template: &str, object: &str, index: u64, extension: &str) -> String {
+    let mut out = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let placeholder = chars.by_ref().take_while(|c| *c != '}').collect::<String>();
+                let mut parts = placeholder.splitn(2, ':');
+                match (parts.next().unwrap(), parts.next()) {
+                    ("object", _) => out.push_str(object),
+                    ("ext", _) => out.push_str(extension),
+                    ("index", None) => out.push_str(&index.to_string()),
+                    ("index", Some(width)) => {
+                        let width = width.parse::<usize>().unwrap_or(0);
+                        out.push_str(&format!("{:0width$}", index, width = width));
+                    }
+                    _ => {
+                        out.push('{');
+                        out.push_str(&placeholder);
+                        out.push('}');
+                    }
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn options(output: Option<PathBuf>) -> OutputOptions {
+        OutputOptions {
+            output_format: None,
+            framing: Framing::None,
+            output,
+            file_template: "{object}-{index:6}.{ext}".to_owned(),
+            rotate_count: None,
+            rotate_size: None,
+            array_mode: ArrayMode::Json,
+            array_columns: 10,
+        }
+    }
+
+    fn boxed(writer: PayloadWriter) -> Box<dyn PayloadSink> {
+        Box::new(writer)
+    }
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("chimpsky-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn templates() {
+        assert_eq!(render_template("{object}-{index:4}.{ext}", "msg", 7, "json"), "msg-0007.json");
+        assert_eq!(render_template("{{literal}}-{index}-{other}", "msg", 12, "json"), "{literal}-12-{other}");
+        assert_eq!(rotated_template(Path::new("out/payloads.ndjson")), "out/payloads-{index:4}.ndjson");
+    }
+
+    #[test]
+    fn array_file_is_one_json_document() {
+        let dir = temp_dir();
+        let path = dir.join("payloads.json");
+        let mut writer = boxed(PayloadWriter::new(OutputFormat::Array, &options(Some(path.clone())), "msg").unwrap());
+        for n in 0..3 {
+            writer.write(&json!({ "n": n })).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), vec![path.clone()]);
+
+        let v: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(v, json!([{ "n": 0 }, { "n": 1 }, { "n": 2 }]));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn empty_array_is_still_valid() {
+        let dir = temp_dir();
+        let path = dir.join("payloads.json");
+        let writer = boxed(PayloadWriter::new(OutputFormat::Array, &options(Some(path.clone())), "msg").unwrap());
+        writer.finish().unwrap();
+
+        let v: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(v, json!([]));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn directory_gets_a_file_per_payload() {
+        let dir = temp_dir();
+        let mut writer = boxed(PayloadWriter::new(OutputFormat::Pretty, &options(Some(dir.clone())), "msg").unwrap());
+        writer.write(&json!({ "n": 0 })).unwrap();
+        writer.write(&json!({ "n": 1 })).unwrap();
+
+        let files = writer.finish().unwrap();
+        assert_eq!(files, vec![dir.join("msg-000000.json"), dir.join("msg-000001.json")]);
+        let v: Value = serde_json::from_str(&fs::read_to_string(&files[1]).unwrap()).unwrap();
+        assert_eq!(v, json!({ "n": 1 }));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rotation_by_count_and_size() {
+        let dir = temp_dir();
+        let mut by_count = options(Some(dir.join("count.ndjson")));
+        by_count.rotate_count = Some(2);
+        let mut writer = boxed(PayloadWriter::new(OutputFormat::Ndjson, &by_count, "msg").unwrap());
+        for n in 0..5 {
+            writer.write(&json!({ "n": n })).unwrap();
+        }
+        let files = writer.finish().unwrap();
+        assert_eq!(files.len(), 3);
+        assert_eq!(fs::read_to_string(&files[2]).unwrap(), "{\"n\":4}\n");
+
+        let mut by_size = options(Some(dir.join("size.ndjson")));
+        by_size.rotate_size = Some(16);
+        let mut writer = boxed(PayloadWriter::new(OutputFormat::Ndjson, &by_size, "msg").unwrap());
+        for n in 0..5 {
+            writer.write(&json!({ "n": n })).unwrap();
+        }
+        let files = writer.finish().unwrap();
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[0], dir.join("size-0000.ndjson"));
+        assert_eq!(fs::read_to_string(&files[0]).unwrap(), "{\"n\":0}\n{\"n\":1}\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn length_prefixed_binary_stream() {
+        let dir = temp_dir();
+        let mut framed = options(Some(dir.join("payloads.cbor")));
+        framed.framing = Framing::LengthPrefixed;
+        let mut writer = boxed(PayloadWriter::new(OutputFormat::Cbor, &framed, "msg").unwrap());
+        writer.write(&json!({ "n": 1 })).unwrap();
+        writer.write(&json!({ "n": 2.5 })).unwrap();
+        let files = writer.finish().unwrap();
+
+        let bytes = fs::read(&files[0]).unwrap();
+        let mut rest = bytes.as_slice();
+        let mut decoded = vec![];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            decoded.push(ciborium::de::from_reader::<Value, _>(&rest[4..4 + len]).unwrap());
+            rest = &rest[4 + len..];
+        }
+        assert_eq!(decoded, vec![json!({ "n": 1 }), json!({ "n": 2.5 })]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn framing_needs_a_binary_format() {
+        let mut framed = options(None);
+        framed.framing = Framing::LengthPrefixed;
+        assert!(PayloadWriter::new(OutputFormat::Ndjson, &framed, "msg").is_err());
+    }
+
+    #[test]
+    fn rotation_needs_an_output() {
+        let mut stdout = options(None);
+        stdout.rotate_count = Some(10);
+        assert!(PayloadWriter::new(OutputFormat::Ndjson, &stdout, "msg").is_err());
+    }
+}
@@ -0,0 +1,596 @@
+use std::cell::RefCell;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, Duration, FixedOffset, SecondsFormat, TimeZone, Utc};
+use rand::distributions::{Distribution, Uniform, WeightedIndex};
+use rand::Rng;
+use serde_json::{json, Map, Value};
+
+use crate::dataset::{self, ForeignKey};
+use crate::datetimes;
+use crate::field_definitions::FieldDefinition;
+use crate::field_kinds::FieldKind;
+use crate::random_values;
+
+thread_local! {
+    static ACTIVE: RefCell<Option<Overrides>> = const { RefCell::new(None) };
+    static PATH: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+    /// Sequences of payloads generated without overrides installed, kept for the whole run.
+    static UNMANAGED: RefCell<Overrides> = RefCell::new(Overrides::default());
+}
+
+const KINDS: &[&str] = &["value", "choice", "range", "pattern", "sequence", "file", "ref"];
+
+/// How the values of one field are generated instead of following its schema.
+#[derive(Debug)]
+pub enum FieldGenerator {
+    Fixed(Value),
+    Choice { values: Vec<Value>, weights: Option<WeightedIndex<f64>> },
+    IntRange(i64, i64),
+    FloatRange(f64, f64),
+    DateTimeRange(DateTime<FixedOffset>, DateTime<FixedOffset>),
+    Pattern(String),
+    /// Numbers counting up from `next`, written into `format` in place of `{}` when given.
+    Sequence { next: i64, step: i64, format: Option<String> },
+    /// Date-times moving forward from `next` by `step` plus up to `jitter` each time.
+    Timestamps { next: DateTime<Utc>, step: Duration, jitter: Duration },
+    /// UUIDs or strings never generated before by this generator.
+    Unique { uuids: bool, seen: HashSet<String> },
+    /// Values of a field of another object generated in the same dataset.
+    Reference(ForeignKey),
+}
+
+impl FieldGenerator {
+    /// `None` when the value has to come from the schema after all, as for references to objects
+    /// not generated yet.
+    fn generate(&mut self) -> Option<Value> {
+        let value = match self {
+            FieldGenerator::Fixed(value) => value.clone(),
+            FieldGenerator::Choice { values, weights: None } => random_values::element_from_collection(values).clone(),
+            FieldGenerator::Choice { values, weights: Some(weights) } => values[weights.sample(&mut random_values::rng())].clone(),
+            FieldGenerator::IntRange(min, max) => json!(random_values::rng().gen_range(*min, *max + 1)),
+            FieldGenerator::FloatRange(min, max) => json!(Uniform::new_inclusive(*min, *max).sample(&mut random_values::rng())),
+            FieldGenerator::DateTimeRange(min, max) => {
+                let seconds = random_values::rng().gen_range(min.timestamp(), max.timestamp() + 1);
+                json!(min.timezone().timestamp_opt(seconds, 0).unwrap().to_rfc3339())
+            }
+            FieldGenerator::Pattern(pattern) => json!(random_values::string_matching_pattern(pattern)),
+            FieldGenerator::Sequence { next, step, format } => {
+                let n = *next;
+                *next += *step;
+                match format {
+                    Some(format) => json!(format.replace("{}", &n.to_string())),
+                    None => json!(n),
+                }
+            }
+            FieldGenerator::Timestamps { next, step, jitter } => {
+                let at = *next;
+                let jitter = random_values::rng().gen_range(0, jitter.num_milliseconds() + 1);
+                *next = at + *step + Duration::milliseconds(jitter);
+                json!(at.to_rfc3339_opts(SecondsFormat::AutoSi, false))
+            }
+            FieldGenerator::Unique { uuids, seen } => loop {
+                let candidate = if *uuids { random_values::uuid4() } else { random_values::string() };
+                if seen.insert(candidate.clone()) {
+                    break json!(candidate);
+                }
+            },
+            FieldGenerator::Reference(foreign_key) => return dataset::draw(foreign_key),
+        };
+        Some(value)
+    }
+}
+
+/// Generators for fields chosen by JSON pointer, like `/a_carried_object/version`, or by field
+/// name anywhere in the payload. Pointers take precedence over names, and both over the schema.
+/// Items of arrays are addressed with `-`, as in `/items/-/id`, and `~` and `/` in field names
+/// are escaped as `~0` and `~1`, as in `/a~1b` for the field `a/b`. Also keeps the state of fields
+/// with an `x-chimpsky-sequence` keyword, so their sequences carry on from payload to payload.
+#[derive(Debug, Default)]
+pub struct Overrides {
+    pointers: Vec<(String, FieldGenerator)>,
+    names: Vec<(String, FieldGenerator)>,
+    /// By the key of the definition declaring the field and its name.
+    sequences: BTreeMap<(String, String), FieldGenerator>,
+}
+
+impl Overrides {
+    /// Reads a `.toml` or `.json` config with a `fields` table. Dictionary files are resolved
+    /// relative to the config file.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        let config: Value = match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => toml::from_str(&text).map_err(|e| format!("{} is not valid TOML: {}", path.display(), e))?,
+            _ => serde_json::from_str(&text).map_err(|e| format!("{} is not valid JSON: {}", path.display(), e))?,
+        };
+        Self::from_config(&config, path.parent().unwrap_or_else(|| Path::new("")))
+    }
+
+    pub fn from_config(config: &Value, base_dir: &Path) -> Result<Self, String> {
+        let config = config.as_object().ok_or("config must be a table")?;
+        if let Some(section) = config.keys().find(|k| *k != "fields") {
+            return Err(format!("unknown config section {}, expected fields", section));
+        }
+
+        let mut overrides = Overrides::default();
+        let fields = match config.get("fields") {
+            None => return Ok(overrides),
+            Some(fields) => fields.as_object().ok_or("fields must be a table")?,
+        };
+
+        for (field, spec) in fields {
+            let generator = parse_generator(spec, base_dir).map_err(|e| format!("field {}: {}", field, e))?;
+            if field.starts_with('/') {
+                overrides.pointers.push((field.to_owned(), generator));
+            } else {
+                overrides.names.push((field.to_owned(), generator));
+            }
+        }
+        Ok(overrides)
+    }
+
+    fn generate(&mut self, pointer: &str, name: &str) -> Option<Value> {
+        let by_pointer = self.pointers.iter_mut().find(|(p, _)| p == pointer);
+        let (_, generator) = match by_pointer {
+            Some(found) => found,
+            None => self.names.iter_mut().find(|(n, _)| n == name)?,
+        };
+        generator.generate()
+    }
+
+    /// Foreign keys configured with `ref`.
+    pub fn foreign_keys(&self) -> Vec<ForeignKey> {
+        self.pointers.iter()
+            .chain(&self.names)
+            .filter_map(|(_, generator)| match generator {
+                FieldGenerator::Reference(foreign_key) => Some(foreign_key.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// A field included by several objects has one sequence, counting across them.
+    fn generate_sequence(&mut self, field: &FieldDefinition) -> Option<Value> {
+        let id = (field.owner.to_owned(), field.name.to_owned());
+        let generator = match self.sequences.entry(id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(sequence_of(field).ok()?),
+        };
+        generator.generate()
+    }
+}
+
+fn parse_generator(spec: &Value, base_dir: &Path) -> Result<FieldGenerator, String> {
+    let spec = spec.as_object().ok_or("expected a table")?;
+    let kinds = spec.keys().filter(|k| KINDS.contains(&k.as_str())).collect::<Vec<_>>();
+    if kinds.len() != 1 {
+        return Err(format!("expected exactly one of {}", KINDS.join(", ")));
+    }
+    if let Some(unknown) = spec.keys().find(|k| !KINDS.contains(&k.as_str()) && !["weights", "start", "step", "jitter", "format", "field"].contains(&k.as_str())) {
+        return Err(format!("unknown key {}", unknown));
+    }
+
+    let kind = kinds[0].as_str();
+    let value = &spec[kind];
+    match kind {
+        "value" => Ok(FieldGenerator::Fixed(value.clone())),
+        "choice" => {
+            let values = value.as_array().filter(|v| !v.is_empty()).ok_or("choice must be a non-empty array")?.clone();
+            let weights = match spec.get("weights") {
+                None => None,
+                Some(weights) => Some(parse_weights(weights, values.len())?),
+            };
+            Ok(FieldGenerator::Choice { values, weights })
+        }
+        "range" => parse_range(value),
+        "pattern" => {
+            let pattern = value.as_str().ok_or("pattern must be a string")?;
+            regex::Regex::new(pattern).map_err(|e| format!("invalid pattern: {}", e))?;
+            Ok(FieldGenerator::Pattern(pattern.to_owned()))
+        }
+        "sequence" => parse_sequence(value, spec),
+        "ref" => {
+            let object = value.as_str().ok_or("ref must be an object name")?;
+            let field = match spec.get("field") {
+                None => None,
+                Some(field) => Some(field.as_str().ok_or("field must be a JSON pointer")?),
+            };
+            Ok(FieldGenerator::Reference(ForeignKey::new(object, field)))
+        }
+        "file" => {
+            let file = base_dir.join(value.as_str().ok_or("file must be a path")?);
+            let text = fs::read_to_string(&file).map_err(|e| format!("cannot read {}: {}", file.display(), e))?;
+            let values = text.lines()
+                .map(|line| line.trim())
+                .filter(|line| !line.is_empty())
+                .map(|line| json!(line))
+                .collect::<Vec<_>>();
+            if values.is_empty() {
+                return Err(format!("{} has no values", file.display()));
+            }
+            Ok(FieldGenerator::Choice { values, weights: None })
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn parse_weights(weights: &Value, count: usize) -> Result<WeightedIndex<f64>, String> {
+    let weights = weights.as_array()
+        .and_then(|w| w.iter().map(|w| w.as_f64()).collect::<Option<Vec<_>>>())
+        .ok_or("weights must be an array of numbers")?;
+    if weights.len() != count {
+        return Err(format!("{} weights for {} choices", weights.len(), count));
+    }
+    WeightedIndex::new(weights).map_err(|e| format!("invalid weights: {}", e))
+}
+
+/// `[min, max]` with both bounds included, as integers, numbers or date-times as
+/// `datetimes::parse_instant` reads them. Like `--datetime-from`, a relative minimum is relative
+/// to the maximum.
+fn parse_range(range: &Value) -> Result<FieldGenerator, String> {
+    let (min, max) = match range.as_array().map(|r| r.as_slice()) {
+        Some([min, max]) => (min, max),
+        _ => return Err("range must be [min, max]".to_owned()),
+    };
+
+    let generator = if let (Some(min), Some(max)) = (min.as_i64(), max.as_i64()) {
+        FieldGenerator::IntRange(min, max)
+    } else if let (Some(min), Some(max)) = (min.as_f64(), max.as_f64()) {
+        FieldGenerator::FloatRange(min, max)
+    } else if let (Some(min), Some(max)) = (min.as_str(), max.as_str()) {
+        let max = parse_bound(max, datetimes::now())?;
+        let anchor = if min.starts_with(['-', '+']) { max.with_timezone(&Utc) } else { datetimes::now() };
+        FieldGenerator::DateTimeRange(parse_bound(min, anchor)?, max)
+    } else {
+        return Err("range bounds must both be numbers or date-times".to_owned());
+    };
+
+    let empty = match &generator {
+        FieldGenerator::IntRange(min, max) => min > max,
+        FieldGenerator::FloatRange(min, max) => min > max,
+        FieldGenerator::DateTimeRange(min, max) => min > max,
+        _ => false,
+    };
+    if empty {
+        return Err("range minimum must be below its maximum".to_owned());
+    }
+    Ok(generator)
+}
+
+/// RFC 3339 bounds keep their offset, which generated values are written in.
+fn parse_bound(s: &str, anchor: DateTime<Utc>) -> Result<DateTime<FixedOffset>, String> {
+    match DateTime::parse_from_rfc3339(s) {
+        Ok(instant) => Ok(instant),
+        Err(_) => datetimes::parse_instant(s, anchor).map(|instant| instant.fixed_offset()),
+    }
+}
+
+fn parse_sequence(sequence: &Value, spec: &Map<String, Value>) -> Result<FieldGenerator, String> {
+    let kind = match sequence {
+        Value::Bool(true) => "counter",
+        Value::String(kind) => kind.as_str(),
+        _ => "",
+    };
+    if let Some(unknown) = spec.keys().find(|k| !["sequence", "start", "step", "jitter", "format"].contains(&k.as_str())) {
+        return Err(format!("unknown key {} for a sequence", unknown));
+    }
+
+    match kind {
+        "counter" => {
+            let number = |key: &str, default: i64| match spec.get(key) {
+                None => Ok(default),
+                Some(v) => v.as_i64().ok_or(format!("{} must be an integer", key)),
+            };
+            let format = match spec.get("format") {
+                None => None,
+                Some(format) => Some(format.as_str().ok_or("format must be a string")?.to_owned()),
+            };
+            Ok(FieldGenerator::Sequence { next: number("start", 1)?, step: number("step", 1)?, format })
+        }
+        "timestamp" => {
+            let text = |key: &str, default: &'static str| match spec.get(key) {
+                None => Ok(default),
+                Some(v) => v.as_str().ok_or(format!("{} must be a string", key)),
+            };
+            // to the millisecond, like the steps
+            let next = datetimes::parse_instant(text("start", "now")?, datetimes::now())?.timestamp_millis();
+            let next = Utc.timestamp_millis_opt(next).unwrap();
+            let step = datetimes::parse_duration(text("step", "1s")?)?;
+            let jitter = datetimes::parse_duration(text("jitter", "0s")?)?;
+            if step.is_zero() && jitter.is_zero() {
+                return Err("timestamps need a step or jitter to move forward".to_owned());
+            }
+            Ok(FieldGenerator::Timestamps { next, step, jitter })
+        }
+        "uuid" | "string" if spec.len() == 1 => Ok(FieldGenerator::Unique { uuids: kind == "uuid", seen: HashSet::new() }),
+        "uuid" | "string" => Err(format!("{} sequences take no options", kind)),
+        _ => Err("sequence must be true, counter, timestamp, uuid or string".to_owned()),
+    }
+}
+
+/// Generator of a field with an `x-chimpsky-sequence` keyword. The keyword is `true`, one of the
+/// sequence kinds, or a table of sequence options like a config entry has. Without a kind, it is
+/// a counter for numbers and strings with a `format` option, timestamps for date-times and unique
+/// values for UUIDs and other strings. Fields whose values a `pattern`, `enum` or `const`
+/// constrains cannot have one, sequences would not keep to it.
+pub fn sequence_of(field: &FieldDefinition) -> Result<FieldGenerator, String> {
+    let keyword = field.sequence.as_ref().ok_or("no x-chimpsky-sequence")?;
+    for (constrained, constraint) in [(field.pattern.is_some(), "pattern"), (field.enum_values.is_some(), "enum"), (field.const_value.is_some(), "const")] {
+        if constrained {
+            return Err(format!("x-chimpsky-sequence cannot keep to the {} of the field", constraint));
+        }
+    }
+    let mut spec = match keyword {
+        Value::Object(options) => options.clone(),
+        kind => {
+            let mut spec = Map::new();
+            spec.insert("sequence".to_owned(), kind.clone());
+            spec
+        }
+    };
+
+    if spec.get("sequence").is_none_or(|kind| kind == &json!(true)) {
+        let kind = match (field.format.as_deref(), &field.kind) {
+            (Some("uuid"), _) => "uuid",
+            (Some("date-time"), _) => "timestamp",
+            (None, Some(FieldKind::Int)) | (None, Some(FieldKind::Float)) => "counter",
+            (None, Some(FieldKind::Str)) if spec.contains_key("format") => "counter",
+            (None, Some(FieldKind::Str)) => "string",
+            _ => return Err("x-chimpsky-sequence needs a number, string, uuid or date-time field".to_owned()),
+        };
+        spec.insert("sequence".to_owned(), json!(kind));
+    }
+    parse_sequence(&spec["sequence"], &spec)
+}
+
+/// Generates with `overrides` installed when it holds any, keeping sequences going across calls.
+pub fn with_overrides<T>(overrides: &mut Option<Overrides>, f: impl FnOnce() -> T) -> T {
+    ACTIVE.with(|active| std::mem::swap(&mut *active.borrow_mut(), overrides));
+    let result = f();
+    ACTIVE.with(|active| std::mem::swap(&mut *active.borrow_mut(), overrides));
+    result
+}
+
+fn is_active() -> bool {
+    ACTIVE.with(|active| active.borrow().is_some())
+}
+
+/// Runs `f` one level deeper in the payload, `segment` being a field name or `-` for array items.
+pub fn within<T>(segment: &str, f: impl FnOnce() -> T) -> T {
+    if !is_active() {
+        return f();
+    }
+
+    PATH.with(|path| path.borrow_mut().push(segment.to_owned()));
+    let result = f();
+    PATH.with(|path| path.borrow_mut().pop());
+    result
+}
+
+/// Value configured for the field at the current position, see `within`, or the next value of
+/// its `x-chimpsky-sequence`. Without overrides installed, sequences carry on for the whole run.
+pub fn generate(field: &FieldDefinition) -> Option<Value> {
+    if !is_active() {
+        field.sequence.as_ref()?;
+        return UNMANAGED.with(|unmanaged| unmanaged.borrow_mut().generate_sequence(field));
+    }
+
+    let pointer = PATH.with(|path| path.borrow().iter().map(|s| format!("/{}", s.replace('~', "~0").replace('/', "~1"))).collect::<String>());
+    ACTIVE.with(|active| {
+        let mut active = active.borrow_mut();
+        let overrides = active.as_mut()?;
+        overrides.generate(&pointer, &field.name).or_else(|| field.sequence.as_ref().and_then(|_| overrides.generate_sequence(field)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schema_parser::{Schema, SchemaSet};
+    use crate::Chimpsky;
+
+    use super::*;
+
+    const KEY: &str = "message.json#/definitions/message";
+
+    fn chimpsky() -> Chimpsky {
+        let mut set = SchemaSet::default();
+        set.add(Schema::new(&json!({
+            "definitions": {
+                "message": {
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "string", "format": "uuid" },
+                        "action": { "type": "string" },
+                        "amount": { "type": "integer" },
+                        "ratio": { "type": "number" },
+                        "at": { "type": "string", "format": "date-time" },
+                        "code": { "type": "string" },
+                        "a/b~c": { "type": "string" },
+                        "carried": { "$ref": "#/definitions/carried" },
+                        "items": { "type": "array", "items": { "$ref": "#/definitions/carried" } }
+                    }
+                },
+                "carried": { "type": "object", "properties": { "id": { "type": "integer" } } }
+            },
+            "allOf": [ { "$ref": "#/definitions/message" } ]
+        }), Some("message.json".to_owned()))).unwrap();
+        Chimpsky::from(set)
+    }
+
+    fn overrides(fields: Value) -> Overrides {
+        Overrides::from_config(&json!({ "fields": fields }), Path::new("")).unwrap()
+    }
+
+    #[test]
+    fn overrides_take_precedence() {
+        let chimpsky = chimpsky();
+        let mut generator = chimpsky.generator(KEY).unwrap().overrides(overrides(json!({
+            "action": { "choice": ["create", "delete"], "weights": [1, 0] },
+            "amount": { "range": [5, 7] },
+            "ratio": { "range": [0.5, 1.5] },
+            "at": { "range": ["2020-01-01T00:00:00+02:00", "2020-01-08T00:00:00+02:00"] },
+            "code": { "pattern": "^[A-Z]{3}$" },
+            "id": { "sequence": true, "start": 10, "step": 5, "format": "user-{}" },
+            "/carried/id": { "value": 42 },
+            "/items/-/id": { "value": 7 }
+        })));
+
+        for (i, payload) in (0..20).map(|_| generator.generate()).enumerate() {
+            assert_eq!(payload["action"], "create");
+            assert!((5..=7).contains(&payload["amount"].as_i64().unwrap()));
+            assert!((0.5..=1.5).contains(&payload["ratio"].as_f64().unwrap()));
+            let at = DateTime::parse_from_rfc3339(payload["at"].as_str().unwrap()).unwrap();
+            assert!(at.to_rfc3339().starts_with("2020-01-0") && at.offset().local_minus_utc() == 7200);
+            assert_eq!(payload["code"].as_str().unwrap().len(), 3);
+            assert_eq!(payload["id"], format!("user-{}", 10 + 5 * i));
+            assert_eq!(payload["carried"]["id"], 42);
+            assert!(payload["items"].as_array().unwrap().iter().all(|item| item["id"] == 7));
+        }
+    }
+
+    #[test]
+    fn date_ranges_can_be_relative() {
+        let chimpsky = chimpsky();
+        let mut generator = chimpsky.generator(KEY).unwrap().overrides(overrides(json!({
+            "at": { "range": ["-7d", "now"] }
+        })));
+
+        let now = datetimes::now();
+        for _ in 0..20 {
+            let at = DateTime::parse_from_rfc3339(generator.generate()["at"].as_str().unwrap()).unwrap();
+            assert!(at >= now - Duration::days(7) - Duration::seconds(1) && at <= now);
+        }
+
+        let overrides = Overrides::from_config(&json!({ "fields": { "at": { "range": ["now", "-1d"] } } }), Path::new(""));
+        assert_eq!(overrides.unwrap_err(), "field at: range minimum must be below its maximum");
+    }
+
+    #[test]
+    fn names_apply_anywhere_pointers_win() {
+        let chimpsky = chimpsky();
+        let mut generator = chimpsky.generator(KEY).unwrap().overrides(overrides(json!({
+            "id": { "value": 1 },
+            "/id": { "value": "root" },
+            "/a~1b~0c": { "value": "escaped" },
+            "ratio": { "range": [1.5, 1.5] }
+        })));
+
+        let payload = generator.generate();
+        assert_eq!(payload["id"], "root");
+        assert_eq!(payload["carried"]["id"], 1);
+        assert_eq!(payload["a/b~c"], "escaped");
+        assert_eq!(payload["ratio"], 1.5);
+    }
+
+    #[test]
+    fn dictionary_files() {
+        let dir = std::env::temp_dir().join(format!("chimpsky-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("verbs.txt"), "create\n\nupdate\n").unwrap();
+        fs::write(dir.join("config.toml"), "[fields.action]\nfile = \"verbs.txt\"\n").unwrap();
+
+        let overrides = Overrides::load(&dir.join("config.toml")).unwrap();
+        let chimpsky = chimpsky();
+        let mut generator = chimpsky.generator(KEY).unwrap().overrides(overrides);
+        for _ in 0..10 {
+            let action = generator.generate()["action"].clone();
+            assert!(action == "create" || action == "update");
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_configs() {
+        let error = |config: Value| Overrides::from_config(&config, Path::new("")).unwrap_err();
+        assert_eq!(error(json!({ "other": {} })), "unknown config section other, expected fields");
+        assert!(error(json!({ "fields": { "a": { "value": 1, "range": [1, 2] } } })).starts_with("field a: expected exactly one of"));
+        assert_eq!(error(json!({ "fields": { "a": { "choice": [1, 2], "weights": [1] } } })), "field a: 1 weights for 2 choices");
+        assert_eq!(error(json!({ "fields": { "a": { "range": [2, 1] } } })), "field a: range minimum must be below its maximum");
+        assert_eq!(error(json!({ "fields": { "a": { "value": 1, "wieghts": [1] } } })), "field a: unknown key wieghts");
+        assert_eq!(error(json!({ "fields": { "a": { "sequence": "uuid", "start": 1 } } })), "field a: uuid sequences take no options");
+        assert_eq!(error(json!({ "fields": { "a": { "sequence": "timestamp", "step": "0s" } } })),
+                   "field a: timestamps need a step or jitter to move forward");
+    }
+
+    #[test]
+    fn sequences_carry_on_across_payloads() {
+        let mut set = SchemaSet::default();
+        set.add(Schema::new(&json!({
+            "definitions": {
+                "event": {
+                    "type": "object",
+                    "properties": {
+                        "unique_number": { "type": "integer", "x-chimpsky-sequence": { "start": 100 } },
+                        "id": { "type": "string", "format": "uuid", "x-chimpsky-sequence": true },
+                        "at": { "type": "string", "format": "date-time",
+                                "x-chimpsky-sequence": { "start": "2024-01-01", "step": "1m", "jitter": "30s" } },
+                        "token": { "type": "string" },
+                        "name": { "type": "string", "x-chimpsky-sequence": { "format": "user-{}" } }
+                    },
+                    "required": ["unique_number", "id", "at", "token", "name"]
+                }
+            },
+            "allOf": [ { "$ref": "#/definitions/event" } ]
+        }), Some("event.json".to_owned()))).unwrap();
+        let chimpsky = Chimpsky::from(set);
+        let key = "event.json#/definitions/event";
+
+        let payloads = chimpsky.generator(key).unwrap()
+            .overrides(overrides(json!({ "token": { "sequence": "string" } })))
+            .take(200)
+            .collect::<Vec<_>>();
+        let field = |name: &str| payloads.iter().map(|p| p[name].clone()).collect::<Vec<_>>();
+
+        assert_eq!(field("unique_number"), (100..300).map(|n| json!(n)).collect::<Vec<_>>());
+        assert_eq!(field("name")[2], "user-3");
+        assert_eq!(field("id").into_iter().collect::<HashSet<_>>().len(), 200);
+        assert_eq!(field("token").into_iter().collect::<HashSet<_>>().len(), 200);
+
+        let at = field("at").iter().map(|at| DateTime::parse_from_rfc3339(at.as_str().unwrap()).unwrap()).collect::<Vec<_>>();
+        assert_eq!(at[0].to_rfc3339(), "2024-01-01T00:00:00+00:00");
+        for pair in at.windows(2) {
+            let gap = pair[1] - pair[0];
+            assert!(gap >= Duration::minutes(1) && gap <= Duration::seconds(90));
+        }
+    }
+
+    #[test]
+    fn sequences_are_kept_by_object_and_field() {
+        let chimpsky = || {
+            let mut set = SchemaSet::default();
+            set.add(Schema::new(&json!({
+                "type": "object",
+                "properties": { "n": { "type": "integer", "x-chimpsky-sequence": { "start": 100 } } },
+                "required": ["n"]
+            }), Some("counted.json".to_owned()))).unwrap();
+            Chimpsky::from(set)
+        };
+        let key = "counted.json#/definitions/counted";
+        let n = |chimpsky: &Chimpsky| chimpsky.reference_map()[key].generate_json(Some(chimpsky.reference_map())).unwrap()["n"].clone();
+
+        let (first, second) = (chimpsky(), chimpsky());
+        assert_eq!([n(&first), n(&second), n(&first)], [json!(100), json!(101), json!(102)]);
+
+        let mut overrides = Some(Overrides::default());
+        let numbers = with_overrides(&mut overrides, || [n(&first), n(&second)]);
+        assert_eq!(numbers, [json!(100), json!(101)]);
+        assert_eq!(with_overrides(&mut overrides, || n(&chimpsky())), json!(102));
+    }
+
+    #[test]
+    fn sequences_need_a_fitting_field() {
+        let field = |schema: Value| FieldDefinition::new("f", &schema);
+        assert!(sequence_of(&field(json!({ "type": "boolean", "x-chimpsky-sequence": true }))).is_err());
+        assert_eq!(sequence_of(&field(json!({ "type": "string", "pattern": "^[A-Z]{3}$", "x-chimpsky-sequence": true }))).err(),
+                   Some("x-chimpsky-sequence cannot keep to the pattern of the field".to_owned()));
+        assert!(sequence_of(&field(json!({ "type": "string", "enum": ["a", "b"], "x-chimpsky-sequence": "string" }))).is_err());
+        assert!(sequence_of(&field(json!({ "type": "integer", "const": 1, "x-chimpsky-sequence": true }))).is_err());
+        assert!(matches!(sequence_of(&field(json!({ "type": "number", "x-chimpsky-sequence": true }))),
+                         Ok(FieldGenerator::Sequence { next: 1, step: 1, format: None })));
+        assert!(matches!(sequence_of(&field(json!({ "type": "string", "x-chimpsky-sequence": "timestamp" }))),
+                         Ok(FieldGenerator::Timestamps { .. })));
+    }
+}
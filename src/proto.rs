@@ -0,0 +1,487 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde_json::Value;
+
+use crate::codegen;
+use crate::export::Loss;
+use crate::field_definitions::FieldDefinition;
+use crate::field_kinds::FieldKind;
+use crate::object_definitions::ObjectDefinition;
+
+const TIMESTAMP: &str = "google.protobuf.Timestamp";
+const STRUCT: &str = "google.protobuf.Struct";
+const VALUE: &str = "google.protobuf.Value";
+const LIST_VALUE: &str = "google.protobuf.ListValue";
+const NULL_VALUE: &str = "google.protobuf.NullValue";
+
+/// Field numbers derived from names start above the numbers protobuf reserves for itself, so
+/// numbers picked by hand with `x-proto-number` below them never clash with derived ones.
+const FIRST_DERIVED_NUMBER: u32 = 20000;
+const MAX_NUMBER: u32 = 536_870_911;
+const RESERVED_NUMBERS: std::ops::RangeInclusive<u32> = 19000..=19999;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Label {
+    None,
+    Optional,
+    Repeated,
+}
+
+#[derive(Debug)]
+enum ProtoField {
+    Single { label: Label, ty: String, name: String, json_name: Option<String>, number: u32 },
+    OneOf { name: String, members: Vec<(String, String, u32)> },
+}
+
+#[derive(Debug)]
+struct Message {
+    key: String,
+    name: String,
+    fields: Vec<ProtoField>,
+}
+
+/// Protocol Buffers view of object definitions. Every definition reachable from the exported
+/// objects becomes a proto3 message. Fields keep the numbers given with `x-proto-number`, the
+/// others are numbered from a hash of their name, so adding, removing or reordering properties
+/// leaves the numbers of the other fields alone.
+pub struct ProtoSchema {
+    messages: Vec<Message>,
+    pub losses: Vec<Loss>,
+}
+
+impl ProtoSchema {
+    pub fn new(keys: &[&str], reference_map: &BTreeMap<String, ObjectDefinition>) -> Result<Self, String> {
+        let mut schema = ProtoSchema { messages: vec![], losses: vec![] };
+        let keys = codegen::reachable_keys(keys, reference_map)?;
+        let names = codegen::type_names(&keys, reference_map);
+
+        for key in &keys {
+            let mut fields = vec![];
+            let mut taken = BTreeSet::new();
+            let definitions = reference_map[key].all_fields(reference_map)?;
+            for (field, optional) in &definitions {
+                fields.push(schema.field(key, field, *optional, &names, &mut taken));
+            }
+            let definitions = definitions.iter().map(|(field, _)| *field).collect::<Vec<_>>();
+            schema.number(key, &definitions, &mut fields)?;
+            schema.messages.push(Message { key: key.to_owned(), name: names[key].to_owned(), fields });
+        }
+
+        Ok(schema)
+    }
+
+    pub fn render(&self) -> String {
+        let mut types = self.messages.iter()
+            .flat_map(|m| m.fields.iter())
+            .flat_map(|f| match f {
+                ProtoField::Single { ty, .. } => vec![ty.as_str()],
+                ProtoField::OneOf { members, .. } => members.iter().map(|(ty, _, _)| ty.as_str()).collect(),
+            });
+
+        let mut imports = BTreeSet::new();
+        for ty in &mut types {
+            if ty == TIMESTAMP {
+                imports.insert("google/protobuf/timestamp.proto");
+            } else if ty.starts_with("google.protobuf.") {
+                imports.insert("google/protobuf/struct.proto");
+            }
+        }
+
+        let mut out = String::from("syntax = \"proto3\";\n\n");
+        for import in &imports {
+            out += &format!("import \"{}\";\n", import);
+        }
+        if !imports.is_empty() {
+            out += "\n";
+        }
+
+        for (i, message) in self.messages.iter().enumerate() {
+            if i > 0 {
+                out += "\n";
+            }
+            out += &format!("// {}\nmessage {} {{\n", message.key, message.name);
+            for field in &message.fields {
+                match field {
+                    ProtoField::Single { label, ty, name, json_name, number } => {
+                        let label = match label {
+                            Label::None => "",
+                            Label::Optional => "optional ",
+                            Label::Repeated => "repeated ",
+                        };
+                        let options = json_name.as_ref()
+                            .map(|j| format!(" [json_name = \"{}\"]", j))
+                            .unwrap_or_default();
+                        out += &format!("  {}{} {} = {}{};\n", label, ty, name, number, options);
+                    }
+                    ProtoField::OneOf { name, members } => {
+                        out += &format!("  oneof {} {{\n", name);
+                        for (ty, member, number) in members {
+                            out += &format!("    {} {} = {};\n", ty, member, number);
+                        }
+                        out += "  }\n";
+                    }
+                }
+            }
+            out += "}\n";
+        }
+
+        out
+    }
+
+    fn field(&mut self,
+             key: &str,
+             field: &FieldDefinition,
+             optional: bool,
+             names: &BTreeMap<String, String>,
+             taken: &mut BTreeSet<String>) -> ProtoField {
+        let name = self.unique_name(key, &field.name, codegen::snake_case(&field.name), taken);
+        let json_name = Some(field.name.to_owned()).filter(|n| *n != name);
+        // fields are numbered once every field of the message is known, see `number`
+        let label = if optional { Label::Optional } else { Label::None };
+
+        if field.format.as_deref() == Some("date-time") {
+            return ProtoField::Single { label, ty: TIMESTAMP.to_owned(), name, json_name, number: 0 };
+        }
+        if field.format.is_some() || field.pattern.is_some() {
+            return ProtoField::Single { label, ty: "string".to_owned(), name, json_name, number: 0 };
+        }
+
+        let kind = match &field.kind {
+            Some(kind) => kind,
+            None => return ProtoField::Single { label, ty: VALUE.to_owned(), name, json_name, number: 0 },
+        };
+
+        let (kinds, nullable) = match kind {
+            FieldKind::OneOf(kinds) => {
+                let non_null = kinds.iter().filter(|k| !matches!(k, FieldKind::Null)).collect::<Vec<_>>();
+                let nullable = non_null.len() < kinds.len();
+                if non_null.is_empty() {
+                    (vec![kind], false)
+                } else {
+                    (non_null, nullable)
+                }
+            }
+            kind => (vec![kind], false),
+        };
+
+        if kinds.len() == 1 {
+            if let FieldKind::ListOf(items) = kinds[0] {
+                if nullable {
+                    self.lose(key, &field.name, "null and an empty array are both written as no elements");
+                }
+                let ty = self.repeated_type(key, &field.name, items, names);
+                return ProtoField::Single { label: Label::Repeated, ty, name, json_name, number: 0 };
+            }
+
+            let label = if nullable { Label::Optional } else { label };
+            let ty = self.singular_type(key, &field.name, kinds[0], names);
+            return ProtoField::Single { label, ty, name, json_name, number: 0 };
+        }
+
+        self.lose(key, &field.name, "a field of several types becomes a oneof, proto JSON writes each member under its own name");
+        let members = kinds.into_iter()
+            .map(|kind| {
+                let ty = match kind {
+                    FieldKind::ListOf(_) => {
+                        self.lose(key, &field.name, "arrays cannot be oneof members and become google.protobuf.ListValue");
+                        LIST_VALUE.to_owned()
+                    }
+                    kind => self.singular_type(key, &field.name, kind, names),
+                };
+                let member = self.unique_name(key, &field.name, format!("{}_{}", name, member_suffix(kind, &ty)), taken);
+                (ty, member, 0)
+            })
+            .collect();
+
+        ProtoField::OneOf { name, members }
+    }
+
+    fn singular_type(&mut self, key: &str, field: &str, kind: &FieldKind, names: &BTreeMap<String, String>) -> String {
+        match kind {
+            FieldKind::Str => "string".to_owned(),
+            FieldKind::Int => "int64".to_owned(),
+            FieldKind::Float => "double".to_owned(),
+            FieldKind::Bool => "bool".to_owned(),
+            FieldKind::Null => NULL_VALUE.to_owned(),
+            FieldKind::Object => {
+                self.lose(key, field, "a free-form object becomes google.protobuf.Struct, which holds every number as a double");
+                STRUCT.to_owned()
+            }
+            FieldKind::Reference(r) => names[r].to_owned(),
+            FieldKind::Unknown(t) => {
+                self.lose(key, field, &format!("unknown type {} is exported as string", t));
+                "string".to_owned()
+            }
+            FieldKind::OneOf(_) | FieldKind::ListOf(_) => {
+                self.lose(key, field, &format!("{} becomes google.protobuf.Value", kind));
+                VALUE.to_owned()
+            }
+        }
+    }
+
+    fn repeated_type(&mut self, key: &str, field: &str, items: &[FieldKind], names: &BTreeMap<String, String>) -> String {
+        let non_null = items.iter().filter(|k| !matches!(k, FieldKind::Null)).collect::<Vec<_>>();
+        if non_null.len() < items.len() {
+            self.lose(key, field, "null array items cannot be repeated and are dropped");
+        }
+
+        match non_null.as_slice() {
+            [] => VALUE.to_owned(),
+            [FieldKind::ListOf(_)] => {
+                self.lose(key, field, "nested arrays become repeated google.protobuf.ListValue");
+                LIST_VALUE.to_owned()
+            }
+            [kind] => self.singular_type(key, field, kind, names),
+            _ => {
+                self.lose(key, field, "array items of several types become google.protobuf.Value");
+                VALUE.to_owned()
+            }
+        }
+    }
+
+    /// Numbers the fields of a message. Numbers given with `x-proto-number`, a number or a list
+    /// of numbers for the members of a oneof, are taken first. The other fields, and oneof
+    /// members, get a number from a hash of their name, moved to the next free number when taken.
+    fn number(&mut self, key: &str, definitions: &[&FieldDefinition], fields: &mut [ProtoField]) -> Result<(), String> {
+        let mut taken = BTreeMap::new();
+        let mut explicit = vec![];
+        for (definition, field) in definitions.iter().zip(fields.iter_mut()) {
+            let numbers = match pinned_numbers(definition).map_err(|e| format!("{} field {}: {}", key, definition.name, e))? {
+                Some(numbers) => numbers,
+                None => {
+                    explicit.push(false);
+                    continue;
+                }
+            };
+            let mut slots = field_numbers(field);
+            if slots.len() != numbers.len() {
+                return Err(format!("{} field {}: x-proto-number needs {} numbers, one for each oneof member", key, definition.name, slots.len()));
+            }
+            for (slot, number) in slots.iter_mut().zip(numbers) {
+                if let Some(other) = taken.insert(number, definition.name.to_owned()) {
+                    return Err(format!("{} field {}: field number {} is also given to {}", key, definition.name, number, other));
+                }
+                **slot = number;
+            }
+            explicit.push(true);
+        }
+
+        for ((definition, field), explicit) in definitions.iter().zip(fields.iter_mut()).zip(explicit) {
+            if explicit {
+                continue;
+            }
+            let names = match field {
+                ProtoField::Single { .. } => vec![definition.name.to_owned()],
+                ProtoField::OneOf { members, .. } => members.iter().map(|(_, member, _)| member.to_owned()).collect(),
+            };
+            for (slot, name) in field_numbers(field).into_iter().zip(names) {
+                let derived = derived_number(&name);
+                let mut number = derived;
+                while taken.contains_key(&number) {
+                    number = if number == MAX_NUMBER { FIRST_DERIVED_NUMBER } else { number + 1 };
+                }
+                if number != derived {
+                    self.lose(key, &definition.name, &format!("field number {} of {} is taken, {} is used instead, pin it with x-proto-number", derived, name, number));
+                }
+                taken.insert(number, definition.name.to_owned());
+                *slot = number;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fields and oneof members of a message share one namespace, so a name already taken, say
+    /// by `category_of` when `category-of` is converted, gets a number appended.
+    fn unique_name(&mut self, key: &str, field: &str, name: String, taken: &mut BTreeSet<String>) -> String {
+        let mut unique = name.to_owned();
+        let mut n = 2;
+        while !taken.insert(unique.clone()) {
+            unique = format!("{}_{}", name, n);
+            n += 1;
+        }
+        if unique != name {
+            self.lose(key, field, &format!("{} is already the name of another field, written as {}", name, unique));
+        }
+        unique
+    }
+
+    fn lose(&mut self, object: &str, field: &str, message: &str) {
+        self.losses.push(Loss { object: object.to_owned(), field: field.to_owned(), message: message.to_owned() });
+    }
+}
+
+fn field_numbers(field: &mut ProtoField) -> Vec<&mut u32> {
+    match field {
+        ProtoField::Single { number, .. } => vec![number],
+        ProtoField::OneOf { members, .. } => members.iter_mut().map(|(_, _, number)| number).collect(),
+    }
+}
+
+fn pinned_numbers(field: &FieldDefinition) -> Result<Option<Vec<u32>>, String> {
+    let values = match &field.proto_number {
+        None => return Ok(None),
+        Some(Value::Array(values)) => values.clone(),
+        Some(value) => vec![value.clone()],
+    };
+    values.iter()
+        .map(|value| match value.as_u64() {
+            Some(n) if n >= 1 && n <= MAX_NUMBER as u64 && !RESERVED_NUMBERS.contains(&(n as u32)) => Ok(n as u32),
+            _ => Err(format!("x-proto-number {} is not a usable field number", value)),
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(Some)
+}
+
+/// FNV-1a, spelled out so numbers do not change with the standard library's hasher.
+fn derived_number(name: &str) -> u32 {
+    let hash = name.bytes().fold(0x811c_9dc5_u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193));
+    FIRST_DERIVED_NUMBER + hash % (MAX_NUMBER - FIRST_DERIVED_NUMBER + 1)
+}
+
+fn member_suffix(kind: &FieldKind, ty: &str) -> String {
+    match kind {
+        FieldKind::Str => "string".to_owned(),
+        FieldKind::Int => "integer".to_owned(),
+        FieldKind::Float => "number".to_owned(),
+        FieldKind::Bool => "boolean".to_owned(),
+        FieldKind::Object => "object".to_owned(),
+        FieldKind::ListOf(_) => "list".to_owned(),
+        _ => codegen::snake_case(ty.rsplit('.').next().unwrap()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::object_definitions::parse_definitions;
+
+    use super::*;
+
+    fn message(extra: Value) -> Value {
+        let mut properties = json!({
+            "at": { "type": "string", "format": "date-time", "x-proto-number": 2 },
+            "category-of": { "type": ["string", "null"] },
+            "value": { "type": ["string", "integer"], "x-proto-number": [4, 5] },
+            "tags": { "type": "array", "items": { "type": "string" } },
+            "data": { "type": "array", "items": { "type": "object" } },
+            "parent": { "$ref": "message" }
+        });
+        properties.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        json!({
+            "type": "object",
+            "allOf": [ { "$ref": "base" } ],
+            "properties": properties,
+            "required": ["at", "value", "tags", "data"]
+        })
+    }
+
+    fn reference_map_with(extra: Value) -> BTreeMap<String, ObjectDefinition> {
+        parse_definitions(&json!({
+            "base": {
+                "type": "object",
+                "properties": { "version": { "type": "number", "x-proto-number": 1 } },
+                "required": ["version"]
+            },
+            "message": message(extra)
+        }))
+    }
+
+    fn reference_map() -> BTreeMap<String, ObjectDefinition> {
+        reference_map_with(json!({}))
+    }
+
+    #[test]
+    fn messages_and_fields() {
+        let m = reference_map();
+        let proto = ProtoSchema::new(&["message"], &m).unwrap();
+        let text = proto.render();
+
+        assert!(text.starts_with("syntax = \"proto3\";\n\nimport \"google/protobuf/struct.proto\";\nimport \"google/protobuf/timestamp.proto\";\n"));
+        assert!(text.contains("message Message {\n  double version = 1;\n  google.protobuf.Timestamp at = 2;\n"));
+        assert!(text.contains(&format!("  optional string category_of = {} [json_name = \"category-of\"];\n", derived_number("category-of"))));
+        assert!(text.contains("  oneof value {\n    string value_string = 4;\n    int64 value_integer = 5;\n  }\n"));
+        assert!(text.contains(&format!("  repeated string tags = {};\n", derived_number("tags"))));
+        assert!(text.contains(&format!("  repeated google.protobuf.Struct data = {};\n", derived_number("data"))));
+        assert!(text.contains(&format!("  optional Message parent = {};\n", derived_number("parent"))));
+        assert!(text.contains("message Base {\n  double version = 1;\n}\n"));
+    }
+
+    #[test]
+    fn adding_a_property_keeps_numbers() {
+        let numbers = |m: &BTreeMap<String, ObjectDefinition>| {
+            let text = ProtoSchema::new(&["message"], m).unwrap().render();
+            text.lines().filter(|l| l.ends_with(';') && l.contains(" = ")).map(|l| l.trim().to_owned()).collect::<Vec<_>>()
+        };
+        let before = numbers(&reference_map());
+        let after = numbers(&reference_map_with(json!({ "added": { "type": "string" } })));
+
+        assert_eq!(after.len(), before.len() + 1);
+        assert!(before.iter().all(|line| after.contains(line)));
+        assert!(after.contains(&format!("optional string added = {};", derived_number("added"))));
+    }
+
+    #[test]
+    fn pinned_numbers_are_checked() {
+        let error = |extra: Value| ProtoSchema::new(&["message"], &reference_map_with(extra)).err().unwrap();
+        assert_eq!(error(json!({ "other": { "type": "string", "x-proto-number": 2 } })),
+                   "message field other: field number 2 is also given to at");
+        assert_eq!(error(json!({ "other": { "type": "string", "x-proto-number": 19500 } })),
+                   "message field other: x-proto-number 19500 is not a usable field number");
+        assert_eq!(error(json!({ "other": { "type": ["string", "integer"], "x-proto-number": 7 } })),
+                   "message field other: x-proto-number needs 2 numbers, one for each oneof member");
+    }
+
+    #[test]
+    fn lossy_constructs_are_reported() {
+        let m = reference_map();
+        let proto = ProtoSchema::new(&["message"], &m).unwrap();
+        let fields = proto.losses.iter().map(|l| l.field.as_str()).collect::<Vec<_>>();
+        assert_eq!(fields, vec!["value", "data"]);
+    }
+
+    #[test]
+    fn clashing_names_are_renamed() {
+        let m = parse_definitions(&json!({
+            "message": {
+                "type": "object",
+                "properties": {
+                    "category_of": { "type": "string" },
+                    "category-of": { "type": "string" },
+                    "value_string": { "type": "string" },
+                    "value": { "type": ["string", "integer"] }
+                },
+                "required": ["category_of", "category-of", "value_string", "value"]
+            }
+        }));
+        let proto = ProtoSchema::new(&["message"], &m).unwrap();
+        let text = proto.render();
+
+        assert!(text.contains(&format!("  string category_of_2 = {} [json_name = \"category-of\"];\n", derived_number("category-of"))));
+        assert!(text.contains(&format!("    string value_string_2 = {};\n", derived_number("value_string_2"))));
+        let messages = proto.losses.iter().map(|l| l.message.as_str()).collect::<Vec<_>>();
+        assert!(messages.contains(&"category_of is already the name of another field, written as category_of_2"));
+        assert!(messages.contains(&"value_string is already the name of another field, written as value_string_2"));
+    }
+
+    #[test]
+    fn taken_numbers_move_on() {
+        assert_eq!(derived_number("tags"), derived_number("tags"));
+        assert!(derived_number("tags") >= FIRST_DERIVED_NUMBER);
+
+        let m = parse_definitions(&json!({
+            "message": {
+                "type": "object",
+                "properties": {
+                    "pinned": { "type": "string", "x-proto-number": derived_number("tags") },
+                    "tags": { "type": "string" }
+                },
+                "required": ["pinned", "tags"]
+            }
+        }));
+        let proto = ProtoSchema::new(&["message"], &m).unwrap();
+        assert!(proto.render().contains(&format!("  string tags = {};\n", derived_number("tags") + 1)));
+        assert_eq!(proto.losses[0].message,
+                   format!("field number {} of tags is taken, {} is used instead, pin it with x-proto-number", derived_number("tags"), derived_number("tags") + 1));
+    }
+}
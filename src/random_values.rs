@@ -1,35 +1,114 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 
-use chrono::{TimeZone, Utc};
-use rand::{Rng, thread_rng};
-use rand::distributions::Alphanumeric;
+use rand::{Error, Rng, RngCore, thread_rng};
+use rand::distributions::{Alphanumeric, Distribution, Uniform};
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
 use serde_json::{json, Map, Value};
-use uuid::Uuid;
+use uuid::{Builder, Variant, Version};
 
+use crate::coverage;
+use crate::datetimes;
+use crate::field_definitions::Bounds;
 use crate::field_kinds::FieldKind;
 use crate::object_definitions::ObjectDefinition;
+use crate::overrides;
+use crate::realistic;
+
+thread_local! {
+    static SEEDED: RefCell<Option<StdRng>> = const { RefCell::new(None) };
+}
+
+/// Source of every random value: the generator installed by `with_rng` when there is one, so
+/// seeded runs repeat themselves, and the thread's own generator otherwise.
+pub struct SourceRng;
+
+impl RngCore for SourceRng {
+    fn next_u32(&mut self) -> u32 {
+        SEEDED.with(|seeded| match seeded.borrow_mut().as_mut() {
+            Some(rng) => rng.next_u32(),
+            None => thread_rng().next_u32(),
+        })
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        SEEDED.with(|seeded| match seeded.borrow_mut().as_mut() {
+            Some(rng) => rng.next_u64(),
+            None => thread_rng().next_u64(),
+        })
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        SEEDED.with(|seeded| match seeded.borrow_mut().as_mut() {
+            Some(rng) => rng.fill_bytes(dest),
+            None => thread_rng().fill_bytes(dest),
+        })
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+pub fn rng() -> SourceRng {
+    SourceRng
+}
+
+/// Draws every random value `f` needs from `seeded` when it holds a generator, leaving it
+/// advanced past them for the next call.
+pub fn with_rng<T>(seeded: &mut Option<StdRng>, f: impl FnOnce() -> T) -> T {
+    SEEDED.with(|current| std::mem::swap(&mut *current.borrow_mut(), seeded));
+    let result = f();
+    SEEDED.with(|current| std::mem::swap(&mut *current.borrow_mut(), seeded));
+    result
+}
 
 pub fn string() -> String {
-    thread_rng().sample_iter(&Alphanumeric).take(20).collect()
+    rng().sample_iter(&Alphanumeric).take(20).collect()
 }
 
 pub fn u64() -> u64 {
-    thread_rng().gen_range(0, 10000)
+    rng().gen_range(0, 10000)
 }
 
 pub fn float() -> f64 {
-    thread_rng().gen_range(0.0, 1000.0)
+    rng().gen_range(0.0, 1000.0)
+}
+
+/// An integer within `bounds`, or the lowest integer above the minimum when there is none.
+pub fn integer_within(bounds: Bounds) -> i64 {
+    let (low, high) = bounds.range(10000.0);
+    let (low, high) = (low.ceil() as i64, high.floor() as i64);
+    if low >= high {
+        return low;
+    }
+    Uniform::new_inclusive(low, high).sample(&mut rng())
+}
+
+pub fn float_within(bounds: Bounds) -> f64 {
+    let (low, high) = bounds.range(1000.0);
+    if low >= high {
+        return low;
+    }
+    Uniform::new_inclusive(low, high).sample(&mut rng())
 }
 
 pub fn boolean() -> bool {
-    thread_rng().gen()
+    rng().gen()
 }
 
 pub fn datetime() -> String {
-    let offset = thread_rng().gen_range(0, 10000000);
-    let point = Utc.timestamp(offset * 60 * 24, 0);
-    point.to_rfc3339()
+    datetimes::date_time()
+}
+
+pub fn date() -> String {
+    datetimes::date()
+}
+
+pub fn time() -> String {
+    datetimes::time()
 }
 
 pub fn string_matching_pattern(pattern: &str) -> String {
@@ -52,58 +131,89 @@ pub fn string_matching_pattern(pattern: &str) -> String {
 
     if need_filter {
         let filter_regex = regex::Regex::new(pattern).unwrap();
-        thread_rng()
+        rng()
             .sample_iter::<String, _>(&gen)
-            .filter(|s| filter_regex.is_match(s))
-            .next()
+            .find(|s| filter_regex.is_match(s))
             .unwrap()
     } else {
-        thread_rng().sample(&gen)
+        rng().sample(&gen)
     }
 }
 
-pub fn element_from_collection<T>(v: &Vec<T>) -> &T {
-    v.choose(&mut thread_rng()).unwrap()
+pub fn element_from_collection<T>(v: &[T]) -> &T {
+    v.choose(&mut rng()).unwrap()
 }
 
-pub fn value_of_kind(k: &FieldKind, reference_map: Option<&HashMap<String, ObjectDefinition>>) -> Value {
+pub fn value_of_kind(k: &FieldKind, reference_map: Option<&BTreeMap<String, ObjectDefinition>>) -> Value {
     match k {
-        FieldKind::Str => json!(string()),
-        FieldKind::Int => json!(u64()),
-        FieldKind::Float => json!(float()),
+        FieldKind::Str => json!(realistic::string().unwrap_or_else(string)),
+        FieldKind::Int => json!(realistic::integer().unwrap_or_else(u64)),
+        FieldKind::Float => json!(realistic::float().unwrap_or_else(float)),
         FieldKind::Bool => json!(boolean()),
         FieldKind::Object => random_object(),
         FieldKind::Null => json!(()),
-        FieldKind::OneOf(kinds) => {
-            let kind = element_from_collection(kinds);
-            value_of_kind(kind, reference_map)
-        }
+        FieldKind::OneOf(kinds) => value_of_kind(branch(k, kinds), reference_map),
         FieldKind::Reference(s) => reference_map
             .unwrap()[s]
             .generate_json(reference_map)
             .unwrap(),
         FieldKind::ListOf(field_kinds) => {
-            field_kinds
-                .iter()
-                .flat_map(|k| values_of_kind(k, thread_rng().gen_range(0, 10), reference_map))
-                .collect()
+            let steered = coverage::steer_kind(k);
+            if steered == Some(0) {
+                return json!([]);
+            }
+
+            overrides::within("-", || {
+                let mut values: Vec<Value> = field_kinds
+                    .iter()
+                    .flat_map(|k| values_of_kind(k, rng().gen_range(0, 10), reference_map))
+                    .collect();
+                if steered == Some(1) && values.is_empty() {
+                    values = values_of_kind(element_from_collection(field_kinds), rng().gen_range(1, 10), reference_map);
+                }
+                values.into()
+            })
         }
+        FieldKind::Unknown(s) => panic!("unknown type {}", s),
+    }
+}
+
+/// Like `value_of_kind`, with numbers kept within `bounds` when the field has any.
+pub fn value_of_kind_within(k: &FieldKind, bounds: Bounds, reference_map: Option<&BTreeMap<String, ObjectDefinition>>) -> Value {
+    match k {
+        _ if !bounds.is_set() => value_of_kind(k, reference_map),
+        FieldKind::Int => json!(integer_within(bounds)),
+        FieldKind::Float => json!(float_within(bounds)),
+        FieldKind::OneOf(kinds) => value_of_kind_within(branch(k, kinds), bounds, reference_map),
+        _ => value_of_kind(k, reference_map),
+    }
+}
+
+fn branch<'a>(k: &FieldKind, kinds: &'a [FieldKind]) -> &'a FieldKind {
+    match coverage::steer_kind(k) {
+        Some(branch) => &kinds[branch],
+        None => element_from_collection(kinds),
     }
 }
 
-pub fn values_of_kind(k: &FieldKind, count: u64, reference_map: Option<&HashMap<String, ObjectDefinition>>) -> Vec<Value> {
+pub fn values_of_kind(k: &FieldKind, count: u64, reference_map: Option<&BTreeMap<String, ObjectDefinition>>) -> Vec<Value> {
     (0..count).map(|_| value_of_kind(k, reference_map)).collect()
 }
 
 pub fn uuid4() -> String {
-    Uuid::new_v4().to_string()
+    Builder::from_bytes(rng().gen())
+        .set_variant(Variant::RFC4122)
+        .set_version(Version::Random)
+        .build()
+        .to_string()
 }
 
 fn random_object() -> Value {
     let domain_of_values = FieldKind::OneOf(vec![FieldKind::Int, FieldKind::Str, FieldKind::Bool]);
-    let m = (0..10)
+    // keys of free-form objects are not field names realistic values could go by
+    let m = realistic::within("", || (0..10)
         .map(|_| (string(), value_of_kind(&domain_of_values, None)))
-        .collect::<Map<String, Value>>();
+        .collect::<Map<String, Value>>());
     Value::Object(m)
 }
 
@@ -117,7 +227,7 @@ fn remove_anchors(pattern: &str) -> String {
 
 #[cfg(test)]
 mod tests {
-    use chrono::DateTime;
+    use chrono::{DateTime, Utc};
 
     use super::*;
 
@@ -0,0 +1,395 @@
+use std::cell::{Cell, RefCell};
+use std::str::FromStr;
+
+use rand::Rng;
+
+use crate::random_values::{element_from_collection, rng};
+
+thread_local! {
+    static LOCALE: Cell<Option<Locale>> = const { Cell::new(None) };
+    static FIELD: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+}
+
+const DOMAINS: &[&str] = &["example.com", "example.org", "example.net"];
+
+const PATHS: &[&str] = &["products", "orders", "account", "help", "blog", "search", "cart", "about"];
+
+const USER_AGENTS: &[&str] = &[
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 14_4) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4 Safari/605.1.15",
+    "Mozilla/5.0 (X11; Linux x86_64; rv:125.0) Gecko/20100101 Firefox/125.0",
+    "Mozilla/5.0 (iPhone; CPU iPhone OS 17_4 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4 Mobile/15E148 Safari/604.1",
+    "Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Mobile Safari/537.36",
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36 Edg/124.0.0.0",
+];
+
+/// Word lists values are drawn from. Phone numbers follow `phone`, with every `#` replaced by a digit.
+struct Words {
+    first_names: &'static [&'static str],
+    last_names: &'static [&'static str],
+    cities: &'static [&'static str],
+    countries: &'static [&'static str],
+    country_codes: &'static [&'static str],
+    currencies: &'static [&'static str],
+    phone: &'static str,
+}
+
+const EN: Words = Words {
+    first_names: &["James", "Mary", "Robert", "Patricia", "John", "Jennifer", "Michael", "Linda", "David", "Elizabeth", "William", "Susan"],
+    last_names: &["Smith", "Johnson", "Williams", "Brown", "Jones", "Garcia", "Miller", "Davis", "Wilson", "Anderson", "Taylor", "Thomas"],
+    cities: &["New York", "Los Angeles", "Chicago", "Houston", "Phoenix", "Philadelphia", "San Antonio", "San Diego", "Dallas", "Seattle", "Boston", "Denver"],
+    countries: &["United States", "Canada", "United Kingdom", "Australia", "Ireland", "New Zealand", "Mexico", "Germany", "France", "Japan"],
+    country_codes: &["US", "CA", "GB", "AU", "IE", "NZ", "MX", "DE", "FR", "JP"],
+    currencies: &["USD", "CAD", "GBP", "AUD", "EUR"],
+    phone: "+1 (###) ###-####",
+};
+
+const DE: Words = Words {
+    first_names: &["Lukas", "Anna", "Leon", "Lena", "Finn", "Marie", "Jonas", "Sophie", "Paul", "Laura", "Felix", "Julia"],
+    last_names: &["Müller", "Schmidt", "Schneider", "Fischer", "Weber", "Meyer", "Wagner", "Becker", "Schulz", "Hoffmann", "Koch", "Richter"],
+    cities: &["Berlin", "Hamburg", "München", "Köln", "Frankfurt am Main", "Stuttgart", "Düsseldorf", "Leipzig", "Dortmund", "Bremen", "Dresden", "Hannover"],
+    countries: &["Deutschland", "Österreich", "Schweiz", "Frankreich", "Italien", "Spanien", "Niederlande", "Polen", "Dänemark", "Belgien"],
+    country_codes: &["DE", "AT", "CH", "FR", "IT", "ES", "NL", "PL", "DK", "BE"],
+    currencies: &["EUR", "CHF", "PLN", "DKK"],
+    phone: "+49 ### #######",
+};
+
+const FR: Words = Words {
+    first_names: &["Gabriel", "Louise", "Raphaël", "Jade", "Louis", "Emma", "Arthur", "Alice", "Jules", "Chloé", "Hugo", "Léa"],
+    last_names: &["Martin", "Bernard", "Thomas", "Petit", "Robert", "Richard", "Durand", "Dubois", "Moreau", "Laurent", "Simon", "Michel"],
+    cities: &["Paris", "Marseille", "Lyon", "Toulouse", "Nice", "Nantes", "Strasbourg", "Montpellier", "Bordeaux", "Lille", "Rennes", "Reims"],
+    countries: &["France", "Belgique", "Suisse", "Canada", "Luxembourg", "Allemagne", "Espagne", "Italie", "Maroc", "Sénégal"],
+    country_codes: &["FR", "BE", "CH", "CA", "LU", "DE", "ES", "IT", "MA", "SN"],
+    currencies: &["EUR", "CHF", "CAD", "MAD"],
+    phone: "+33 # ## ## ## ##",
+};
+
+const ES: Words = Words {
+    first_names: &["Hugo", "Lucía", "Martín", "Sofía", "Pablo", "María", "Daniel", "Paula", "Alejandro", "Julia", "Álvaro", "Carmen"],
+    last_names: &["García", "Rodríguez", "González", "Fernández", "López", "Martínez", "Sánchez", "Pérez", "Gómez", "Martín", "Jiménez", "Ruiz"],
+    cities: &["Madrid", "Barcelona", "Valencia", "Sevilla", "Zaragoza", "Málaga", "Murcia", "Palma", "Bilbao", "Alicante", "Córdoba", "Valladolid"],
+    countries: &["España", "México", "Argentina", "Colombia", "Chile", "Perú", "Portugal", "Francia", "Uruguay", "Ecuador"],
+    country_codes: &["ES", "MX", "AR", "CO", "CL", "PE", "PT", "FR", "UY", "EC"],
+    currencies: &["EUR", "MXN", "ARS", "COP", "CLP"],
+    phone: "+34 ### ### ###",
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Locale {
+    En,
+    De,
+    Fr,
+    Es,
+}
+
+impl Locale {
+    fn words(self) -> &'static Words {
+        match self {
+            Locale::En => &EN,
+            Locale::De => &DE,
+            Locale::Fr => &FR,
+            Locale::Es => &ES,
+        }
+    }
+}
+
+impl FromStr for Locale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "en" => Ok(Locale::En),
+            "de" => Ok(Locale::De),
+            "fr" => Ok(Locale::Fr),
+            "es" => Ok(Locale::Es),
+            _ => Err(format!("unknown locale {}, expected en, de, fr or es", s)),
+        }
+    }
+}
+
+/// What a field holds, going by its name.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Semantic {
+    FirstName,
+    LastName,
+    FullName,
+    UserName,
+    Email,
+    City,
+    Country,
+    CountryCode,
+    Phone,
+    Currency,
+    Price,
+    Url,
+    Ip,
+    UserAgent,
+    Latitude,
+    Longitude,
+}
+
+/// Names that are not about people even though they end in `name`.
+const NOT_PEOPLE: &[&str] = &["file", "host", "domain", "type", "event", "class", "table", "schema", "key", "field", "display", "product"];
+
+fn semantic(field: &str) -> Option<Semantic> {
+    let words = words(field);
+    let has = |w: &str| words.iter().any(|word| word == w);
+    let joined = words.concat();
+
+    let semantic = if has("email") || has("mail") {
+        Semantic::Email
+    } else if joined.contains("useragent") {
+        Semantic::UserAgent
+    } else if has("url") || has("uri") || has("link") || has("website") || has("homepage") {
+        Semantic::Url
+    } else if has("ip") || has("ipv4") {
+        Semantic::Ip
+    } else if has("lat") || has("latitude") {
+        Semantic::Latitude
+    } else if has("lon") || has("lng") || has("longitude") {
+        Semantic::Longitude
+    } else if has("phone") || has("mobile") || has("tel") || has("telephone") {
+        Semantic::Phone
+    } else if has("currency") {
+        Semantic::Currency
+    } else if has("price") || has("cost") || has("amount") || has("total") || has("fee") {
+        Semantic::Price
+    } else if has("country") {
+        if has("code") || has("iso") { Semantic::CountryCode } else { Semantic::Country }
+    } else if has("city") || has("town") {
+        Semantic::City
+    } else if joined.contains("firstname") || has("forename") || (has("given") && has("name")) {
+        Semantic::FirstName
+    } else if joined.contains("lastname") || has("surname") || (has("family") && has("name")) {
+        Semantic::LastName
+    } else if joined.contains("username") || has("login") || has("handle") {
+        Semantic::UserName
+    } else if has("name") && !NOT_PEOPLE.iter().any(|w| has(w)) {
+        Semantic::FullName
+    } else {
+        return None;
+    };
+    Some(semantic)
+}
+
+/// Lowercase words of a field name, split at `_`, `-` and camel case humps.
+fn words(field: &str) -> Vec<String> {
+    let mut words: Vec<String> = vec![];
+    let mut previous_lower = false;
+    for c in field.chars() {
+        if !c.is_alphanumeric() {
+            words.push(String::new());
+            previous_lower = false;
+            continue;
+        }
+        if words.is_empty() || (c.is_uppercase() && previous_lower) {
+            words.push(String::new());
+        }
+        words.last_mut().unwrap().extend(c.to_lowercase());
+        previous_lower = c.is_lowercase() || c.is_ascii_digit();
+    }
+    words.retain(|w| !w.is_empty());
+    words
+}
+
+/// Generates with realistic values for fields whose names are recognised, using the word lists
+/// of `locale`. Values for other fields and without a locale are generated as before.
+pub fn with_locale<T>(locale: Option<Locale>, f: impl FnOnce() -> T) -> T {
+    let previous = LOCALE.with(|current| current.replace(locale));
+    let result = f();
+    LOCALE.with(|current| current.set(previous));
+    result
+}
+
+/// Runs `f` while generating the value of the field `name`.
+pub fn within<T>(name: &str, f: impl FnOnce() -> T) -> T {
+    if LOCALE.with(|locale| locale.get()).is_none() {
+        return f();
+    }
+
+    FIELD.with(|field| field.borrow_mut().push(name.to_owned()));
+    let result = f();
+    FIELD.with(|field| field.borrow_mut().pop());
+    result
+}
+
+fn current() -> Option<(Locale, Semantic)> {
+    let locale = LOCALE.with(|locale| locale.get())?;
+    let field = FIELD.with(|field| field.borrow().last().cloned())?;
+    Some((locale, semantic(&field)?))
+}
+
+pub fn string() -> Option<String> {
+    let (locale, semantic) = current()?;
+    Some(match semantic {
+        Semantic::Price => format!("{:.2}", price()),
+        Semantic::Latitude => format!("{:.6}", coordinate(90.0)),
+        Semantic::Longitude => format!("{:.6}", coordinate(180.0)),
+        semantic => text(locale, semantic),
+    })
+}
+
+pub fn integer() -> Option<u64> {
+    match current()? {
+        (_, Semantic::Price) => Some(rng().gen_range(1, 500)),
+        _ => None,
+    }
+}
+
+pub fn float() -> Option<f64> {
+    match current()? {
+        (_, Semantic::Price) => Some(price()),
+        (_, Semantic::Latitude) => Some(coordinate(90.0)),
+        (_, Semantic::Longitude) => Some(coordinate(180.0)),
+        _ => None,
+    }
+}
+
+/// Values of the formats realistic values exist for, in the selected locale, or `None` outside
+/// realistic mode.
+pub fn format(format: &str) -> Option<String> {
+    let locale = LOCALE.with(|locale| locale.get())?;
+    let semantic = match format {
+        "email" => Semantic::Email,
+        "uri" => Semantic::Url,
+        "ipv4" => Semantic::Ip,
+        _ => return None,
+    };
+    Some(text(locale, semantic))
+}
+
+fn text(locale: Locale, semantic: Semantic) -> String {
+    let words = locale.words();
+    let pick = |list: &[&'static str]| *element_from_collection(list);
+    match semantic {
+        Semantic::FirstName => pick(words.first_names).to_owned(),
+        Semantic::LastName => pick(words.last_names).to_owned(),
+        Semantic::FullName => format!("{} {}", pick(words.first_names), pick(words.last_names)),
+        Semantic::UserName => user_name(pick(words.first_names), pick(words.last_names)),
+        Semantic::Email => format!("{}@{}", user_name(pick(words.first_names), pick(words.last_names)), pick(DOMAINS)),
+        Semantic::City => pick(words.cities).to_owned(),
+        Semantic::Country => pick(words.countries).to_owned(),
+        Semantic::CountryCode => pick(words.country_codes).to_owned(),
+        Semantic::Phone => words.phone.chars()
+            .map(|c| if c == '#' { std::char::from_digit(rng().gen_range(0, 10), 10).unwrap() } else { c })
+            .collect(),
+        Semantic::Currency => pick(words.currencies).to_owned(),
+        Semantic::Url => format!("https://www.{}/{}/{}", pick(DOMAINS), pick(PATHS), rng().gen_range(1, 10000)),
+        Semantic::Ip => format!("{}.{}.{}.{}", rng().gen_range(1, 224), rng().gen_range(0, 256), rng().gen_range(0, 256), rng().gen_range(1, 255)),
+        Semantic::UserAgent => pick(USER_AGENTS).to_owned(),
+        Semantic::Price => format!("{:.2}", price()),
+        Semantic::Latitude => format!("{:.6}", coordinate(90.0)),
+        Semantic::Longitude => format!("{:.6}", coordinate(180.0)),
+    }
+}
+
+/// ASCII only, so it can be used in email addresses.
+fn user_name(first: &str, last: &str) -> String {
+    let ascii = |s: &str| s.chars()
+        .filter_map(|c| match c.to_lowercase().next().unwrap() {
+            'ä' | 'á' | 'à' => Some('a'),
+            'é' | 'è' | 'ë' => Some('e'),
+            'í' => Some('i'),
+            'ö' | 'ó' => Some('o'),
+            'ü' | 'ú' => Some('u'),
+            'ñ' => Some('n'),
+            c if c.is_ascii_alphanumeric() => Some(c),
+            _ => None,
+        })
+        .collect::<String>();
+    format!("{}.{}{}", ascii(first), ascii(last), rng().gen_range(1, 100))
+}
+
+fn price() -> f64 {
+    (rng().gen_range(1.0, 500.0) * 100.0_f64).round() / 100.0
+}
+
+fn coordinate(limit: f64) -> f64 {
+    (rng().gen_range(-limit, limit) * 1e6_f64).round() / 1e6
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::schema_parser::{Schema, SchemaSet};
+    use crate::verifier::Verifier;
+    use crate::Chimpsky;
+
+    use super::*;
+
+    #[test]
+    fn field_names() {
+        assert_eq!(words("customerEmailAddress"), vec!["customer", "email", "address"]);
+        assert_eq!(words("user_agent"), vec!["user", "agent"]);
+        assert_eq!(semantic("the_name"), Some(Semantic::FullName));
+        assert_eq!(semantic("firstName"), Some(Semantic::FirstName));
+        assert_eq!(semantic("user-name"), Some(Semantic::UserName));
+        assert_eq!(semantic("file_name"), None);
+        assert_eq!(semantic("contact_email"), Some(Semantic::Email));
+        assert_eq!(semantic("billing_country_code"), Some(Semantic::CountryCode));
+        assert_eq!(semantic("userAgent"), Some(Semantic::UserAgent));
+        assert_eq!(semantic("client_ip"), Some(Semantic::Ip));
+        assert_eq!(semantic("lat"), Some(Semantic::Latitude));
+        assert_eq!(semantic("lng"), Some(Semantic::Longitude));
+        assert_eq!(semantic("unit_price"), Some(Semantic::Price));
+        assert_eq!(semantic("room_number"), None);
+        assert_eq!("xx".parse::<Locale>().unwrap_err(), "unknown locale xx, expected en, de, fr or es");
+    }
+
+    #[test]
+    fn nothing_changes_without_a_locale() {
+        within("email", || assert_eq!(string(), None));
+        assert_eq!(format("email"), None);
+        with_locale(Some(Locale::En), || within("room_number", || assert_eq!(string(), None)));
+    }
+
+    #[test]
+    fn realistic_payloads() {
+        let mut set = SchemaSet::default();
+        set.add(Schema::new(&json!({
+            "definitions": {
+                "customer": {
+                    "type": "object",
+                    "properties": {
+                        "the_name": { "type": "string" },
+                        "contact": { "type": "string", "format": "email" },
+                        "website": { "type": "string", "format": "uri" },
+                        "client_ip": { "type": "string", "format": "ipv4" },
+                        "city": { "type": ["string", "null"] },
+                        "phone": { "type": "string" },
+                        "currency": { "type": "string" },
+                        "price": { "type": "number" },
+                        "location": { "$ref": "#/definitions/location" }
+                    },
+                    "required": ["the_name", "contact", "website", "client_ip", "city", "phone", "currency", "price", "location"]
+                },
+                "location": {
+                    "type": "object",
+                    "properties": { "lat": { "type": "number" }, "lon": { "type": "number" } },
+                    "required": ["lat", "lon"]
+                }
+            },
+            "allOf": [ { "$ref": "#/definitions/customer" } ]
+        }), Some("customer.json".to_owned()))).unwrap();
+        let chimpsky = Chimpsky::from(set);
+        let key = "customer.json#/definitions/customer";
+        let verifier = Verifier::new(&chimpsky.schema_set().documents, key).unwrap();
+
+        assert!(chimpsky.generator(key).is_err());
+        let mut generator = chimpsky.realistic_generator(key, Locale::De).unwrap();
+        for payload in (0..20).map(|_| generator.generate()) {
+            assert_eq!(verifier.verify(&payload), vec![]);
+
+            let name = payload["the_name"].as_str().unwrap().split(' ').collect::<Vec<_>>();
+            assert!(DE.first_names.contains(&name[0]) && DE.last_names.contains(&name[1]));
+            assert!(payload["city"].is_null() || DE.cities.contains(&payload["city"].as_str().unwrap()));
+            assert!(payload["phone"].as_str().unwrap().starts_with("+49 "));
+            assert!(DE.currencies.contains(&payload["currency"].as_str().unwrap()));
+            assert!(payload["contact"].as_str().unwrap().is_ascii());
+            assert!(payload["location"]["lat"].as_f64().unwrap().abs() <= 90.0);
+            let price = payload["price"].as_f64().unwrap();
+            assert_eq!((price * 100.0).round() / 100.0, price);
+        }
+    }
+}
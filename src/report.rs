@@ -0,0 +1,263 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::field_definitions::FieldDefinition;
+use crate::object_definitions::ObjectDefinition;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(ReportFormat::Text),
+            "json" => Ok(ReportFormat::Json),
+            _ => Err(format!("unknown report format {}, expected text or json", s)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ObjectReport {
+    pub key: String,
+    pub name: String,
+    pub kind: String,
+    pub unsupported: Vec<String>,
+    pub includes: Vec<ReferenceReport>,
+    pub fields: Vec<FieldReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FieldReport {
+    pub name: String,
+    pub kind: Option<String>,
+    pub format: Option<String>,
+    pub pattern: Option<String>,
+    pub required: bool,
+    pub unsupported: Vec<String>,
+    pub references: Vec<ReferenceReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReferenceReport {
+    pub target: String,
+    pub resolved: bool,
+    pub recursive: bool,
+    pub object: Option<ObjectReport>,
+}
+
+/// Builds reports for every object in the reference map, or only the ones whose key or name
+/// matches `filter`. Referenced objects are expanded in place until a reference loops back.
+pub fn build_reports(reference_map: &BTreeMap<String, ObjectDefinition>, filter: Option<&str>) -> Vec<ObjectReport> {
+    reference_map.iter()
+        .filter(|(key, definition)| filter.is_none_or(|f| *key == f || definition.name == f))
+        .map(|(key, definition)| object_report(key, definition, reference_map, &mut vec![]))
+        .collect()
+}
+
+pub fn render_text(reports: &[ObjectReport]) -> String {
+    let mut out = String::new();
+    for report in reports {
+        out.push_str(&format!("{} in file {}\n", report.name, report.key));
+        render_object_body(report, 1, &mut out);
+    }
+    out
+}
+
+pub fn render_json(reports: &[ObjectReport]) -> String {
+    serde_json::to_string_pretty(reports).unwrap()
+}
+
+fn object_report(key: &str,
+                 definition: &ObjectDefinition,
+                 reference_map: &BTreeMap<String, ObjectDefinition>,
+                 stack: &mut Vec<String>) -> ObjectReport {
+    stack.push(key.to_owned());
+
+    let includes = definition.references.iter()
+        .flatten()
+        .map(|r| reference_report(r, reference_map, stack))
+        .collect();
+
+    let fields = definition.field_definitions.iter()
+        .flatten()
+        .map(|field| field_report(field, definition, reference_map, stack))
+        .collect();
+
+    stack.pop();
+
+    ObjectReport {
+        key: key.to_owned(),
+        name: definition.name.to_owned(),
+        kind: definition.kind.to_owned(),
+        unsupported: definition.unsupported.clone(),
+        includes,
+        fields,
+    }
+}
+
+fn field_report(field: &FieldDefinition,
+                parent: &ObjectDefinition,
+                reference_map: &BTreeMap<String, ObjectDefinition>,
+                stack: &mut Vec<String>) -> FieldReport {
+    let targets = field.kind.as_ref().map(|k| k.references()).unwrap_or_default();
+
+    FieldReport {
+        name: field.name.to_owned(),
+        kind: field.kind.as_ref().map(|k| k.to_string()),
+        format: field.format.clone(),
+        pattern: field.pattern.clone(),
+        required: parent.is_required(&field.name),
+        unsupported: field.unsupported.clone(),
+        references: targets.into_iter()
+            .map(|r| reference_report(r, reference_map, stack))
+            .collect(),
+    }
+}
+
+fn reference_report(target: &str,
+                    reference_map: &BTreeMap<String, ObjectDefinition>,
+                    stack: &mut Vec<String>) -> ReferenceReport {
+    let recursive = stack.iter().any(|k| k == target);
+    let definition = reference_map.get(target);
+
+    ReferenceReport {
+        target: target.to_owned(),
+        resolved: definition.is_some(),
+        recursive,
+        object: match definition {
+            Some(definition) if !recursive => Some(object_report(target, definition, reference_map, stack)),
+            _ => None,
+        },
+    }
+}
+
+fn render_object_body(report: &ObjectReport, depth: usize, out: &mut String) {
+    let indent = "    ".repeat(depth);
+    if !report.kind.is_empty() {
+        out.push_str(&format!("{}kind: {}\n", indent, report.kind));
+    }
+
+    if !report.unsupported.is_empty() {
+        out.push_str(&format!("{}unsupported: {}\n", indent, report.unsupported.join(", ")));
+    }
+
+    for include in &report.includes {
+        out.push_str(&format!("{}includes {}{}\n", indent, include.target, reference_status(include)));
+        if let Some(object) = &include.object {
+            render_object_body(object, depth + 1, out);
+        }
+    }
+
+    for field in &report.fields {
+        render_field(field, depth, out);
+    }
+}
+
+fn render_field(field: &FieldReport, depth: usize, out: &mut String) {
+    let indent = "    ".repeat(depth);
+    let mut details = vec![field.kind.clone().unwrap_or_else(|| "any".to_owned())];
+
+    if let Some(format) = &field.format {
+        details.push(format!("format {}", format));
+    }
+
+    if let Some(pattern) = &field.pattern {
+        details.push(format!("pattern {}", pattern));
+    }
+
+    details.push(if field.required { "required" } else { "optional" }.to_owned());
+
+    if !field.unsupported.is_empty() {
+        details.push(format!("unsupported: {}", field.unsupported.join(", ")));
+    }
+
+    out.push_str(&format!("{}{}: {}\n", indent, field.name, details.join(", ")));
+
+    for reference in &field.references {
+        out.push_str(&format!("{}    -> {}{}\n", indent, reference.target, reference_status(reference)));
+        if let Some(object) = &reference.object {
+            render_object_body(object, depth + 2, out);
+        }
+    }
+}
+
+fn reference_status(reference: &ReferenceReport) -> &'static str {
+    if !reference.resolved {
+        " (unresolved)"
+    } else if reference.recursive {
+        " (recursive)"
+    } else {
+        ""
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn reference_map() -> BTreeMap<String, ObjectDefinition> {
+        let mut m = BTreeMap::new();
+        m.insert("inner.json#/definitions/inner".to_owned(), ObjectDefinition::new("inner", &json!({
+            "type": "object",
+            "properties": {
+                "version": { "type": "number", "multipleOf": 1 },
+                "parent": { "$ref": "outer.json#/definitions/outer" }
+            },
+            "required": ["version"]
+        })));
+        m.insert("outer.json#/definitions/outer".to_owned(), ObjectDefinition::new("outer", &json!({
+            "type": "object",
+            "properties": {
+                "id": { "type": "string", "format": "uuid" },
+                "inner": { "$ref": "inner.json#/definitions/inner" },
+                "missing": { "$ref": "missing.json#/definitions/missing" }
+            },
+            "required": ["id"]
+        })));
+        m
+    }
+
+    fn field<'a>(report: &'a ObjectReport, name: &str) -> &'a FieldReport {
+        report.fields.iter().find(|f| f.name == name).unwrap()
+    }
+
+    #[test]
+    fn reports_are_filtered_and_expanded() {
+        let reports = build_reports(&reference_map(), Some("outer"));
+        assert_eq!(reports.len(), 1);
+
+        let outer = &reports[0];
+        assert!(field(outer, "id").required);
+        assert!(!field(outer, "inner").required);
+
+        let inner = field(outer, "inner").references[0].object.as_ref().unwrap();
+        assert_eq!(field(inner, "version").unsupported, vec!["multipleOf"]);
+        assert!(field(inner, "parent").references[0].recursive);
+        assert!(!field(outer, "missing").references[0].resolved);
+    }
+
+    #[test]
+    fn text_report_lists_fields() {
+        let text = render_text(&build_reports(&reference_map(), Some("inner.json#/definitions/inner")));
+        assert!(text.starts_with("inner in file inner.json#/definitions/inner\n"));
+        assert!(text.contains("version: number, required, unsupported: multipleOf"));
+        assert!(text.contains("-> missing.json#/definitions/missing (unresolved)"));
+        assert!(text.contains("-> inner.json#/definitions/inner (recursive)"));
+    }
+
+    #[test]
+    fn report_format_parsing() {
+        assert_eq!("json".parse::<ReportFormat>().unwrap(), ReportFormat::Json);
+        assert!("yaml".parse::<ReportFormat>().is_err());
+    }
+}
@@ -0,0 +1,408 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde_json::Value;
+
+use crate::codegen::{self, CodegenOptions};
+use crate::field_definitions::FieldDefinition;
+use crate::field_kinds::FieldKind;
+use crate::object_definitions::ObjectDefinition;
+
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn", "else", "enum",
+    "extern", "false", "final", "fn", "for", "if", "impl", "in", "let", "loop", "macro", "match", "mod", "move", "mut",
+    "override", "priv", "pub", "ref", "return", "static", "struct", "trait", "true", "try", "type", "typeof", "unsafe",
+    "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Keywords that cannot be written as raw identifiers.
+const RESERVED: &[&str] = &["crate", "self", "super", "Self"];
+
+const JSON_VALUE: &str = "serde_json::Value";
+const JSON_MAP: &str = "serde_json::Map<String, serde_json::Value>";
+
+#[derive(Debug)]
+struct RustField {
+    name: String,
+    rename: Option<String>,
+    ty: String,
+    optional: bool,
+}
+
+#[derive(Debug)]
+enum Item {
+    Struct { key: String, name: String, fields: Vec<RustField> },
+    /// An enum of the string values listed by an `enum` keyword.
+    Values { name: String, variants: Vec<(String, String)> },
+    /// An untagged enum of the types a field can hold.
+    Untagged { name: String, variants: Vec<(String, String)> },
+}
+
+/// Serde types mirroring object definitions. Every object becomes a struct, fields of several
+/// types become untagged enums and string `enum` keywords become enums of their values.
+pub struct RustModule<'a> {
+    roots: Vec<String>,
+    names: BTreeMap<String, String>,
+    taken: BTreeSet<String>,
+    items: Vec<Item>,
+    reference_map: &'a BTreeMap<String, ObjectDefinition>,
+}
+
+impl<'a> RustModule<'a> {
+    pub fn new(keys: &[&str], reference_map: &'a BTreeMap<String, ObjectDefinition>) -> Result<Self, String> {
+        let reachable = codegen::reachable_keys(keys, reference_map)?;
+        let names = codegen::type_names(&reachable, reference_map);
+
+        let mut module = RustModule {
+            roots: keys.iter().map(|k| k.to_string()).collect(),
+            taken: names.values().cloned().collect(),
+            names,
+            items: vec![],
+            reference_map,
+        };
+
+        for key in &reachable {
+            let name = module.names[key].to_owned();
+            let fields = reference_map[key].all_fields(reference_map)?
+                .into_iter()
+                .map(|(field, optional)| module.field(key, &name, field, optional))
+                .collect();
+            module.items.push(Item::Struct { key: key.to_owned(), name, fields });
+        }
+
+        Ok(module)
+    }
+
+    pub fn render(&self, options: &CodegenOptions) -> String {
+        let mut out = String::from("// Generated by chimpsky, do not edit.\n\nuse serde::{Deserialize, Serialize};\n");
+
+        for item in &self.items {
+            out += "\n";
+            match item {
+                Item::Struct { key, name, fields } => {
+                    out += &format!("/// {}\n#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]\npub struct {} {{\n", key, name);
+                    for field in fields {
+                        if let Some(rename) = &field.rename {
+                            out += &format!("    #[serde(rename = {:?})]\n", rename);
+                        }
+                        if field.optional {
+                            out += "    #[serde(default, skip_serializing_if = \"Option::is_none\")]\n";
+                        }
+                        // a nullable field that may also be left out is still a single Option
+                        if field.optional && !field.ty.starts_with("Option<") {
+                            out += &format!("    pub {}: Option<{}>,\n", field.name, field.ty);
+                        } else {
+                            out += &format!("    pub {}: {},\n", field.name, field.ty);
+                        }
+                    }
+                    out += "}\n";
+                }
+                Item::Values { name, variants } => {
+                    out += &format!("#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]\npub enum {} {{\n", name);
+                    for (variant, value) in variants {
+                        if variant != value {
+                            out += &format!("    #[serde(rename = {:?})]\n", value);
+                        }
+                        out += &format!("    {},\n", variant);
+                    }
+                    out += "}\n";
+                }
+                Item::Untagged { name, variants } => {
+                    out += &format!("#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]\n#[serde(untagged)]\npub enum {} {{\n", name);
+                    for (variant, ty) in variants {
+                        out += &format!("    {}({}),\n", variant, ty);
+                    }
+                    out += "}\n";
+                }
+            }
+        }
+
+        if options.tests {
+            out += &self.render_tests(options.test_payloads);
+        }
+
+        out
+    }
+
+    /// Tests deserializing payloads generated now into the generated types and serializing them
+    /// back. Null and absent fields are treated as equal since both become `None`. Objects whose
+    /// payloads cannot be generated get no test, only a comment saying so.
+    fn render_tests(&self, payloads: u64) -> String {
+        let mut out = String::from(r#"
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn without_nulls(v: serde_json::Value) -> serde_json::Value {
+        match v {
+            serde_json::Value::Object(m) => m.into_iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k, without_nulls(v)))
+                .collect(),
+            serde_json::Value::Array(a) => a.into_iter().map(without_nulls).collect(),
+            v => v,
+        }
+    }
+
+    fn round_trip<T: Serialize + serde::de::DeserializeOwned>(payload: &str) {
+        let value: serde_json::Value = serde_json::from_str(payload).unwrap();
+        let typed: T = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(without_nulls(serde_json::to_value(&typed).unwrap()), without_nulls(value));
+    }
+"#);
+
+        for key in &self.roots {
+            let name = &self.names[key];
+            if !crate::can_generate(key, self.reference_map) {
+                out += &format!("\n    // no round-trip test for {}, its payloads cannot be generated\n", key);
+                continue;
+            }
+
+            out += &format!("\n    #[test]\n    fn {}_round_trips() {{\n        for payload in &[\n", codegen::snake_case(name));
+            for _ in 0..payloads {
+                let payload = self.reference_map[key].generate_json(Some(self.reference_map)).unwrap_or(Value::Null);
+                out += &format!("            {:?},\n", payload.to_string());
+            }
+            out += &format!("        ] {{\n            round_trip::<{}>(payload);\n        }}\n    }}\n", name);
+        }
+
+        out + "}\n"
+    }
+
+    fn field(&mut self, key: &str, owner: &str, field: &FieldDefinition, optional: bool) -> RustField {
+        let snake = codegen::snake_case(&field.name);
+        let (name, renamed) = if RESERVED.contains(&snake.as_str()) {
+            (format!("{}_", snake), true)
+        } else if KEYWORDS.contains(&snake.as_str()) {
+            (format!("r#{}", snake), snake != field.name)
+        } else {
+            (snake.clone(), snake != field.name)
+        };
+
+        let type_name = format!("{}{}", owner, codegen::pascal_case(&field.name));
+        let values = field.const_value.as_ref().map(std::slice::from_ref).or(field.enum_values.as_deref());
+        let ty = if let Some(values) = values {
+            self.values_type(&type_name, values)
+        } else if field.format.is_some() || field.pattern.is_some() {
+            "String".to_owned()
+        } else {
+            match &field.kind {
+                None => JSON_VALUE.to_owned(),
+                Some(kind) => self.kind_type(key, &type_name, kind),
+            }
+        };
+
+        RustField { name, rename: Some(field.name.to_owned()).filter(|_| renamed), ty, optional }
+    }
+
+    fn values_type(&mut self, type_name: &str, values: &[Value]) -> String {
+        let strings = values.iter().filter_map(|v| v.as_str()).collect::<Vec<_>>();
+        let nulls = values.iter().filter(|v| v.is_null()).count();
+        if strings.is_empty() || strings.len() + nulls < values.len() {
+            return JSON_VALUE.to_owned();
+        }
+
+        let mut variants: Vec<(String, String)> = vec![];
+        for (i, value) in strings.iter().enumerate() {
+            let mut variant = codegen::pascal_case(value);
+            if variants.iter().any(|(v, _)| *v == variant) {
+                variant = format!("Value{}", i);
+            }
+            variants.push((variant, value.to_string()));
+        }
+
+        let name = self.unique_name(type_name);
+        self.items.push(Item::Values { name: name.clone(), variants });
+        if nulls > 0 {
+            format!("Option<{}>", name)
+        } else {
+            name
+        }
+    }
+
+    fn kind_type(&mut self, key: &str, type_name: &str, kind: &FieldKind) -> String {
+        match kind {
+            FieldKind::Str => "String".to_owned(),
+            FieldKind::Int => "i64".to_owned(),
+            FieldKind::Float => "f64".to_owned(),
+            FieldKind::Bool => "bool".to_owned(),
+            FieldKind::Object => JSON_MAP.to_owned(),
+            FieldKind::Null => "()".to_owned(),
+            FieldKind::Unknown(_) => JSON_VALUE.to_owned(),
+            FieldKind::Reference(r) if codegen::reaches(r, key, self.reference_map) => format!("Box<{}>", self.names[r]),
+            FieldKind::Reference(r) => self.names[r].to_owned(),
+            FieldKind::OneOf(kinds) => {
+                let (non_null, nullable) = without_null(kinds);
+                let ty = match non_null.as_slice() {
+                    [] => "()".to_owned(),
+                    [kind] => self.kind_type(key, type_name, kind),
+                    kinds => self.untagged(key, type_name, kinds),
+                };
+                if nullable && !non_null.is_empty() {
+                    format!("Option<{}>", ty)
+                } else {
+                    ty
+                }
+            }
+            FieldKind::ListOf(items) => {
+                let (non_null, nullable) = without_null(items);
+                let item_name = format!("{}Item", type_name);
+                let ty = match non_null.as_slice() {
+                    [] => JSON_VALUE.to_owned(),
+                    [kind] => self.kind_type(key, &item_name, kind),
+                    kinds => self.untagged(key, &item_name, kinds),
+                };
+                if nullable && !non_null.is_empty() {
+                    format!("Vec<Option<{}>>", ty)
+                } else {
+                    format!("Vec<{}>", ty)
+                }
+            }
+        }
+    }
+
+    /// Variants are ordered so serde tries the most specific type first: records before
+    /// free-form objects and integers before floats.
+    fn untagged(&mut self, key: &str, type_name: &str, kinds: &[&FieldKind]) -> String {
+        let mut kinds = kinds.to_vec();
+        kinds.sort_by_key(|k| match k {
+            FieldKind::Reference(_) => 0,
+            FieldKind::Bool => 1,
+            FieldKind::Int => 2,
+            FieldKind::Float => 3,
+            FieldKind::Str => 4,
+            FieldKind::ListOf(_) => 5,
+            FieldKind::Object => 6,
+            _ => 7,
+        });
+
+        let name = self.unique_name(type_name);
+        let variants = kinds.into_iter()
+            .map(|kind| {
+                let variant = match kind {
+                    FieldKind::Str => "String".to_owned(),
+                    FieldKind::Int => "Integer".to_owned(),
+                    FieldKind::Float => "Number".to_owned(),
+                    FieldKind::Bool => "Boolean".to_owned(),
+                    FieldKind::Object => "Object".to_owned(),
+                    FieldKind::ListOf(_) => "List".to_owned(),
+                    FieldKind::Reference(r) => self.names[r].to_owned(),
+                    _ => "Value".to_owned(),
+                };
+                (variant, self.kind_type(key, &format!("{}List", name), kind))
+            })
+            .collect();
+
+        self.items.push(Item::Untagged { name: name.clone(), variants });
+        name
+    }
+
+    fn unique_name(&mut self, name: &str) -> String {
+        let mut candidate = name.to_owned();
+        let mut n = 2;
+        while !self.taken.insert(candidate.clone()) {
+            candidate = format!("{}{}", name, n);
+            n += 1;
+        }
+        candidate
+    }
+}
+
+fn without_null(kinds: &[FieldKind]) -> (Vec<&FieldKind>, bool) {
+    let non_null = kinds.iter().filter(|k| !matches!(k, FieldKind::Null)).collect::<Vec<_>>();
+    let nullable = non_null.len() < kinds.len();
+    (non_null, nullable)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::object_definitions::parse_definitions;
+    use crate::random_values;
+
+    use super::*;
+
+    fn reference_map() -> BTreeMap<String, ObjectDefinition> {
+        parse_definitions(&json!({
+            "message": {
+                "type": "object",
+                "properties": {
+                    "type": { "type": "string" },
+                    "category-of": { "type": ["string", "null"] },
+                    "value": { "type": ["string", "integer"] },
+                    "state": { "type": "string", "enum": ["on", "off"] },
+                    "tags": { "type": "array", "items": { "type": "string" } },
+                    "parent": { "$ref": "message" },
+                    "carried": { "$ref": "carried" }
+                },
+                "required": ["type", "category-of", "value", "state", "tags", "carried"]
+            },
+            "carried": { "type": "object", "properties": { "version": { "type": "number" } } },
+            "reading": {
+                "type": "object",
+                "properties": {
+                    "sensor": { "type": "string" },
+                    "unit": { "type": "string", "enum": ["celsius", "fahrenheit"] },
+                    "value": { "type": ["integer", "string", "null"] },
+                    "samples": { "type": "array", "items": { "type": "number" } },
+                    "carried": { "$ref": "carried" }
+                },
+                "required": ["sensor", "unit", "value", "carried"]
+            }
+        }))
+    }
+
+    fn render(key: &str, tests: bool) -> String {
+        let m = reference_map();
+        RustModule::new(&[key], &m).unwrap().render(&CodegenOptions { tests, test_payloads: 2 })
+    }
+
+    #[test]
+    fn structs_and_fields() {
+        let code = render("message", false);
+        assert!(code.contains("pub struct Message {\n    pub r#type: String,\n"));
+        assert!(code.contains("    #[serde(rename = \"category-of\")]\n    pub category_of: Option<String>,\n"));
+        assert!(code.contains("    pub value: MessageValue,\n"));
+        assert!(code.contains("    pub state: MessageState,\n"));
+        assert!(code.contains("    pub tags: Vec<String>,\n"));
+        assert!(code.contains("    #[serde(default, skip_serializing_if = \"Option::is_none\")]\n    pub parent: Option<Box<Message>>,\n"));
+        assert!(code.contains("    pub carried: Carried,\n"));
+        assert!(code.contains("    #[serde(default, skip_serializing_if = \"Option::is_none\")]\n    pub version: Option<f64>,\n"));
+    }
+
+    #[test]
+    fn enums() {
+        let code = render("message", false);
+        assert!(code.contains("#[serde(untagged)]\npub enum MessageValue {\n    Integer(i64),\n    String(String),\n}\n"));
+        assert!(code.contains("pub enum MessageState {\n    #[serde(rename = \"on\")]\n    On,\n    #[serde(rename = \"off\")]\n    Off,\n}\n"));
+    }
+
+    #[test]
+    fn round_trip_tests() {
+        assert!(!render("carried", false).contains("mod tests"));
+
+        let code = render("carried", true);
+        assert!(code.contains("    fn carried_round_trips() {\n"));
+        assert!(code.contains("            round_trip::<Carried>(payload);\n"));
+        assert_eq!(code.matches("\\\"version\\\"").count(), 2);
+
+        // refers to itself
+        let code = render("message", true);
+        assert!(!code.contains("fn message_round_trips"));
+        assert!(code.contains("    // no round-trip test for message, its payloads cannot be generated\n"));
+    }
+
+    /// `tests/codegen_rust.rs` compiles the fixture and runs its round-trip tests.
+    #[test]
+    fn generated_code_compiles() {
+        let m = reference_map();
+        let module = RustModule::new(&["message", "reading"], &m).unwrap();
+        let code = random_values::with_rng(&mut Some(StdRng::seed_from_u64(3)), || {
+            module.render(&CodegenOptions { tests: true, test_payloads: 3 })
+        });
+        assert_eq!(code, include_str!("../tests/fixtures/codegen_rust.rs"));
+    }
+}
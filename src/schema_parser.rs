@@ -1,50 +1,82 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
+use std::fs::DirEntry;
+use std::io;
 use std::path::{Path, PathBuf};
 
-
 use serde_json::{json, Map, Value};
 
 use crate::object_definitions::{ObjectDefinition, parse_definitions};
+use crate::Error;
 
 #[derive(Debug)]
 pub struct Schema {
-    pub definitions: HashMap<String, ObjectDefinition>,
+    pub definitions: BTreeMap<String, ObjectDefinition>,
     pub all_of: Option<Vec<String>>,
+    pub document: Value,
     filename: Option<String>,
 }
 
 impl Schema {
     pub fn new(v: &Value, filename: Option<String>) -> Self {
-        assert!(v.is_object(), format!("schema {} not an object", v));
+        assert!(v.is_object(), "schema {} not an object", v);
+        let document = v.clone();
         let v = v.as_object().unwrap();
 
         if !v.contains_key("definitions") {
-            Self::parse_schema_with_single_definition(v, filename)
+            Self::parse_schema_with_single_definition(v, filename, document)
         } else {
-            Self::parse_schema_with_embedded_definitions(v, filename)
+            Self::parse_schema_with_embedded_definitions(v, filename, document)
         }
     }
 
-    pub fn export_definitions(self) -> Option<HashMap<String, ObjectDefinition>> {
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    /// Local references such as `#/definitions/x` are qualified with the schema filename so that
+    /// every reference can be looked up in the merged reference map.
+    pub fn export_definitions(self) -> Option<BTreeMap<String, ObjectDefinition>> {
         self.filename.clone().map(|filename| {
-            self.definitions.into_iter().map(|(defname, def)| {
+            self.definitions.into_iter().map(|(defname, mut def)| {
                 let tag = format!("{}#/definitions/{}", filename.clone(), defname);
+                def.qualify_references(&filename);
+                def.own_fields(&tag);
                 (tag, def)
             })
                 .collect()
         })
     }
 
-    fn parse_schema_with_single_definition(v: &Map<String, Value>, filename: Option<String>) -> Self {
+    /// Keys of the definitions this schema document stands for: the single definition of a
+    /// standalone schema, or the local definitions its top level `allOf` refers to.
+    pub fn root_keys(&self) -> Vec<String> {
+        let filename = match &self.filename {
+            Some(filename) => filename,
+            None => return vec![],
+        };
+
+        match &self.all_of {
+            None if !self.document.as_object().is_some_and(|d| d.contains_key("definitions")) =>
+                self.definitions.keys()
+                    .map(|name| format!("{}#/definitions/{}", filename, name))
+                    .collect(),
+            None => vec![],
+            Some(all_of) => all_of.iter()
+                .map(|r| qualify_reference(filename, r))
+                .collect(),
+        }
+    }
+
+    fn parse_schema_with_single_definition(v: &Map<String, Value>, filename: Option<String>, document: Value) -> Self {
         let p = PathBuf::from(filename.clone().unwrap());
         let name = p.file_stem().unwrap().to_string_lossy();
         let temp = json!({name: v});
         let definition = parse_definitions(&temp);
-        Schema { definitions: definition, all_of: None, filename }
+        Schema { definitions: definition, all_of: None, document, filename }
     }
 
-    fn parse_schema_with_embedded_definitions(v: &Map<String, Value>, filename: Option<String>) -> Self {
+    fn parse_schema_with_embedded_definitions(v: &Map<String, Value>, filename: Option<String>, document: Value) -> Self {
         let mut definitions = None;
         let mut all_of = None;
 
@@ -58,7 +90,7 @@ impl Schema {
             }
         }
 
-        Schema { definitions: definitions.unwrap(), all_of, filename }
+        Schema { definitions: definitions.unwrap(), all_of, document, filename }
     }
 
     fn parse_references_in_allof_field(v: &Value) -> Vec<String> {
@@ -90,9 +122,86 @@ impl Schema {
     }
 }
 
+/// Every schema file found directly under a directory, merged into one reference map keyed by
+/// `file#/definitions/name`.
+#[derive(Debug, Default)]
+pub struct SchemaSet {
+    pub reference_map: BTreeMap<String, ObjectDefinition>,
+    pub documents: BTreeMap<String, Value>,
+    pub roots: BTreeSet<String>,
+}
+
+impl SchemaSet {
+    pub fn load_dir(schema_root: &Path) -> io::Result<Self> {
+        let mut set = SchemaSet::default();
+
+        for entry in fs::read_dir(schema_root)? {
+            // schemas read from files always have a filename
+            if let Some(schema) = schema_from_entry(entry) {
+                set.add(schema).expect("schema files have a filename");
+            }
+        }
+
+        Ok(set)
+    }
+
+    /// Definitions are keyed by the filename of their schema, so a schema without one is refused.
+    pub fn add(&mut self, schema: Schema) -> Result<(), Error> {
+        let filename = match schema.filename() {
+            Some(filename) => filename.to_owned(),
+            None => return Err(Error::InvalidSchema("a schema needs a filename to be added to a set".to_owned())),
+        };
+        self.roots.extend(schema.root_keys());
+        self.documents.insert(filename, schema.document.clone());
+        self.reference_map.extend(schema.export_definitions().unwrap());
+        Ok(())
+    }
+
+    /// Reference key of an object given either its key or its definition name.
+    pub fn object_key<'a>(&'a self, object: &'a str) -> Option<&'a str> {
+        if self.reference_map.contains_key(object) {
+            return Some(object);
+        }
+
+        self.reference_map.iter()
+            .find(|(_, definition)| definition.name == object)
+            .map(|(key, _)| key.as_str())
+    }
+
+    /// The schema node a definition was parsed from. Single definition files are keyed as
+    /// `file#/definitions/name` even though the definition is the document itself.
+    pub fn definition_node(&self, key: &str) -> Option<&Value> {
+        let mut parts = key.splitn(2, '#');
+        let document = self.documents.get(parts.next().unwrap())?;
+        parts.next()
+            .and_then(|fragment| document.pointer(fragment))
+            .or(Some(document))
+    }
+}
+
+fn schema_from_entry(entry: io::Result<DirEntry>) -> Option<Schema> {
+    let entry = entry.ok()?;
+    let path = entry.path();
+
+    if entry.file_type().ok()?.is_file() && path.extension()? == "json" {
+        Schema::from_file(&path)
+    } else {
+        None
+    }
+}
+
+pub fn qualify_reference(filename: &str, reference: &str) -> String {
+    if reference.starts_with('#') {
+        format!("{}{}", filename, reference)
+    } else {
+        reference.to_owned()
+    }
+}
 
 #[cfg(test)]
 mod tests {
+    use crate::field_kinds::FieldKind;
+
     use super::*;
 
     #[test]
@@ -162,5 +271,45 @@ mod tests {
         let def = &exported["a-file-somwehere#/definitions/root_message_format"];
         assert_eq!(def.kind, "object");
     }
+
+    #[test]
+    fn local_references_are_qualified() {
+        let v: Value = serde_json::from_str(r###" { "definitions": {
+            "inner": { "type": "object", "properties": { "n": { "type": "integer" } } },
+            "outer": {
+              "type": "object",
+              "properties": { "inner": { "$ref": "#/definitions/inner" } }
+            }
+          },
+          "allOf": [ { "$ref": "#/definitions/outer" } ]
+          }"###).unwrap();
+        let schema = Schema::new(&v, Some("local.json".to_owned()));
+        assert_eq!(schema.root_keys(), vec!["local.json#/definitions/outer"]);
+
+        let exported = schema.export_definitions().unwrap();
+        let field = &exported["local.json#/definitions/outer"].field_definitions.as_ref().unwrap()[0];
+        match field.kind.as_ref().unwrap() {
+            FieldKind::Reference(r) => assert_eq!(r, "local.json#/definitions/inner"),
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn single_definition_is_its_own_root() {
+        let v = json!({ "type": "object", "properties": { "n": { "type": "integer" } } });
+        let schema = Schema::new(&v, Some("single.schema.json".to_owned()));
+        assert_eq!(schema.root_keys(), vec!["single.schema.json#/definitions/single.schema"]);
+    }
+
+    #[test]
+    fn schemas_without_filename_are_refused() {
+        let v = json!({ "type": "object", "properties": { "n": { "type": "integer" } } });
+        let mut set = SchemaSet::default();
+        assert!(matches!(set.add(Schema::new(&v, Some("named.json".to_owned()))), Ok(())));
+
+        let v = json!({ "definitions": { "n": { "type": "object" } } });
+        assert!(matches!(set.add(Schema::new(&v, None)), Err(Error::InvalidSchema(_))));
+        assert_eq!(set.reference_map.len(), 1);
+    }
 }
 
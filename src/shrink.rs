@@ -0,0 +1,267 @@
+use std::collections::BTreeMap;
+
+use serde_json::{json, Map, Value};
+
+use crate::field_definitions::{Bounds, FieldDefinition};
+use crate::field_kinds::FieldKind;
+use crate::object_definitions::ObjectDefinition;
+
+/// Simpler versions of generated payloads that still satisfy their definition, for property
+/// testing. Optional fields are dropped, arrays and strings shortened, numbers moved toward
+/// their minimum, or toward zero, the smallest number chimpsky generates, when the field has
+/// none. Strings constrained by a format or pattern are kept as they are, and enum values move
+/// toward the first one.
+pub struct Shrinker<'a> {
+    reference_map: &'a BTreeMap<String, ObjectDefinition>,
+}
+
+impl<'a> Shrinker<'a> {
+    pub fn new(reference_map: &'a BTreeMap<String, ObjectDefinition>) -> Self {
+        Shrinker { reference_map }
+    }
+
+    /// Candidates differing from `value` in one place each, the largest simplifications first.
+    pub fn object(&self, definition: &ObjectDefinition, value: &Value) -> Vec<Value> {
+        let (object, fields) = match (value.as_object(), definition.all_fields(self.reference_map)) {
+            (Some(object), Ok(fields)) => (object, fields),
+            _ => return vec![],
+        };
+
+        let mut candidates = vec![];
+        for (field, _) in fields.iter().filter(|(f, optional)| *optional && object.contains_key(&f.name)) {
+            let mut smaller = object.clone();
+            smaller.remove(&field.name);
+            candidates.push(Value::Object(smaller));
+        }
+
+        for (field, _) in &fields {
+            if let Some(field_value) = object.get(&field.name) {
+                for simpler in self.field(field, field_value) {
+                    let mut smaller = object.clone();
+                    smaller.insert(field.name.to_owned(), simpler);
+                    candidates.push(Value::Object(smaller));
+                }
+            }
+        }
+
+        candidates
+    }
+
+    fn field(&self, field: &FieldDefinition, value: &Value) -> Vec<Value> {
+        if field.const_value.is_some() {
+            return vec![];
+        }
+        if let Some(values) = &field.enum_values {
+            return values.first().filter(|first| *first != value).cloned().into_iter().collect();
+        }
+        if field.format.is_some() || field.pattern.is_some() {
+            return vec![];
+        }
+
+        field.kind.as_ref().map(|kind| self.kind(kind, value, field.bounds)).unwrap_or_default()
+    }
+
+    /// Kinds that do not match the type of `value` have no candidates, so a value of a field
+    /// with several types only shrinks within its own type. Numbers stay within `bounds`.
+    fn kind(&self, kind: &FieldKind, value: &Value, bounds: Bounds) -> Vec<Value> {
+        match (kind, value) {
+            (FieldKind::OneOf(kinds), value) => kinds.iter().flat_map(|k| self.kind(k, value, bounds)).collect(),
+            (FieldKind::Str, Value::String(s)) => strings(s),
+            (FieldKind::Int, Value::Number(n)) if n.is_u64() || n.is_i64() => integers(n.as_i64().unwrap_or(i64::MAX), bounds),
+            (FieldKind::Float, Value::Number(n)) => floats(n.as_f64().unwrap(), bounds),
+            (FieldKind::Bool, Value::Bool(true)) => vec![json!(false)],
+            (FieldKind::Object, Value::Object(object)) => free_form(object),
+            (FieldKind::Reference(r), value) => match self.reference_map.get(r) {
+                Some(definition) => self.object(definition, value),
+                None => vec![],
+            },
+            (FieldKind::ListOf(items), Value::Array(values)) => self.array(items, values),
+            _ => vec![],
+        }
+    }
+
+    fn array(&self, items: &[FieldKind], values: &[Value]) -> Vec<Value> {
+        let mut candidates = vec![];
+        if values.is_empty() {
+            return candidates;
+        }
+
+        candidates.push(json!([]));
+        if values.len() > 2 {
+            candidates.push(Value::Array(values[..values.len() / 2].to_vec()));
+        }
+        for i in 0..values.len() {
+            let mut smaller = values.to_vec();
+            smaller.remove(i);
+            candidates.push(Value::Array(smaller));
+        }
+
+        for (i, value) in values.iter().enumerate() {
+            for simpler in items.iter().flat_map(|item| self.kind(item, value, Bounds::default())) {
+                let mut smaller = values.to_vec();
+                smaller[i] = simpler;
+                candidates.push(Value::Array(smaller));
+            }
+        }
+
+        candidates
+    }
+}
+
+fn strings(s: &str) -> Vec<Value> {
+    let chars = s.chars().collect::<Vec<_>>();
+    let mut candidates = vec![];
+    if chars.is_empty() {
+        return candidates;
+    }
+
+    candidates.push(json!(""));
+    if chars.len() > 2 {
+        candidates.push(json!(chars[..chars.len() / 2].iter().collect::<String>()));
+    }
+    if chars.len() > 1 {
+        candidates.push(json!(chars[..chars.len() - 1].iter().collect::<String>()));
+    }
+    candidates
+}
+
+fn integers(n: i64, bounds: Bounds) -> Vec<Value> {
+    // a positive target is the minimum and a negative one the maximum, rounded into the bounds
+    let target = bounds.target();
+    let target = (if target > 0.0 { target.ceil() } else { target.floor() }) as i128;
+    let n = n as i128;
+
+    let mut candidates: Vec<i128> = vec![];
+    for candidate in [target, (n + target) / 2, n - (n - target).signum()] {
+        if candidate != n && !candidates.contains(&candidate) && bounds.contains(candidate as f64) {
+            candidates.push(candidate);
+        }
+    }
+    candidates.into_iter().map(|c| json!(c)).collect()
+}
+
+fn floats(n: f64, bounds: Bounds) -> Vec<Value> {
+    let target = bounds.target();
+    let mut candidates: Vec<f64> = vec![];
+    for candidate in [target, n.trunc(), target + (n - target) / 2.0] {
+        if candidate != n && !candidates.contains(&candidate) && bounds.contains(candidate) {
+            candidates.push(candidate);
+        }
+    }
+    candidates.into_iter().map(|c| json!(c)).collect()
+}
+
+fn free_form(object: &Map<String, Value>) -> Vec<Value> {
+    if object.is_empty() {
+        return vec![];
+    }
+
+    let mut candidates = vec![json!({})];
+    for key in object.keys() {
+        let mut smaller = object.clone();
+        smaller.remove(key);
+        candidates.push(Value::Object(smaller));
+    }
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schema_parser::{Schema, SchemaSet};
+    use crate::verifier::Verifier;
+    use crate::Chimpsky;
+
+    use super::*;
+
+    const KEY: &str = "message.json#/definitions/message";
+
+    fn chimpsky() -> Chimpsky {
+        let mut set = SchemaSet::default();
+        set.add(Schema::new(&json!({
+            "definitions": {
+                "message": {
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "string", "format": "uuid" },
+                        "name": { "type": "string" },
+                        "state": { "type": "string", "enum": ["on", "off"] },
+                        "level": { "type": "integer", "minimum": 3, "maximum": 9 },
+                        "code": { "type": "string", "pattern": "^[A-Z]{3}$" },
+                        "count": { "type": "integer" },
+                        "ratio": { "type": "number" },
+                        "tags": { "type": "array", "items": { "type": ["string", "integer"] } },
+                        "data": { "type": "object" },
+                        "carried": { "$ref": "#/definitions/carried" }
+                    },
+                    "required": ["id", "name", "state", "level", "code", "carried"]
+                },
+                "carried": {
+                    "type": "object",
+                    "properties": { "version": { "type": "integer" }, "note": { "type": "string" } },
+                    "required": ["version"]
+                }
+            },
+            "allOf": [ { "$ref": "#/definitions/message" } ]
+        }), Some("message.json".to_owned()))).unwrap();
+        Chimpsky::from(set)
+    }
+
+    #[test]
+    fn candidates_satisfy_the_schema() {
+        let chimpsky = chimpsky();
+        let verifier = Verifier::new(&chimpsky.schema_set().documents, KEY).unwrap();
+        let shrinker = Shrinker::new(chimpsky.reference_map());
+        let definition = &chimpsky.reference_map()[KEY];
+
+        for payload in chimpsky.generator(KEY).unwrap().seed(1).take(5) {
+            let candidates = shrinker.object(definition, &payload);
+            assert!(!candidates.is_empty());
+            for candidate in candidates {
+                assert_ne!(candidate, payload);
+                assert_eq!(verifier.verify(&candidate), vec![]);
+            }
+        }
+    }
+
+    #[test]
+    fn shrinks_to_a_minimal_payload() {
+        let chimpsky = chimpsky();
+        let shrinker = Shrinker::new(chimpsky.reference_map());
+        let definition = &chimpsky.reference_map()[KEY];
+
+        let mut payload = chimpsky.generator(KEY).unwrap().seed(2).generate();
+        while let Some(simpler) = shrinker.object(definition, &payload).into_iter().next() {
+            payload = simpler;
+        }
+
+        let mut fields = payload.as_object().unwrap().keys().collect::<Vec<_>>();
+        fields.sort();
+        assert_eq!(fields, vec!["carried", "code", "id", "level", "name", "state"]);
+        assert_eq!(payload["name"], "");
+        assert_eq!(payload["state"], "on");
+        assert_eq!(payload["level"], 3);
+        assert_eq!(payload["carried"], json!({ "version": 0 }));
+        assert_eq!(payload["code"].as_str().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn scalars_and_arrays() {
+        let unbounded = Bounds::default();
+        assert_eq!(integers(10, unbounded), vec![json!(0), json!(5), json!(9)]);
+        assert_eq!(integers(1, unbounded), vec![json!(0)]);
+        assert!(integers(0, unbounded).is_empty());
+        assert_eq!(floats(2.5, unbounded), vec![json!(0.0), json!(2.0), json!(1.25)]);
+
+        let bounds = Bounds { minimum: Some(2.5), maximum: Some(20.0) };
+        assert_eq!(integers(10, bounds), vec![json!(3), json!(6), json!(9)]);
+        assert_eq!(integers(-10, Bounds { minimum: None, maximum: Some(-3.0) }), vec![json!(-3), json!(-6), json!(-9)]);
+        assert!(integers(3, bounds).is_empty());
+        assert_eq!(floats(4.5, bounds), vec![json!(2.5), json!(4.0), json!(3.5)]);
+        assert_eq!(strings("abcd"), vec![json!(""), json!("ab"), json!("abc")]);
+
+        let set = SchemaSet::default();
+        let shrinker = Shrinker::new(&set.reference_map);
+        let candidates = shrinker.kind(&FieldKind::ListOf(vec![FieldKind::Bool]), &json!([true, false]), unbounded);
+        assert_eq!(candidates, vec![json!([]), json!([false]), json!([true]), json!([false, false])]);
+    }
+}
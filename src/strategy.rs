@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+
+use proptest::prelude::Rng;
+use proptest::strategy::{NewTree, Strategy, ValueTree};
+use proptest::test_runner::TestRunner;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde_json::{json, Value};
+
+use crate::object_definitions::ObjectDefinition;
+use crate::random_values;
+use crate::shrink::Shrinker;
+
+/// Payloads of one object as a proptest strategy. Values are drawn from the runner's random
+/// number generator, so failing cases can be replayed from proptest's persisted seeds.
+#[derive(Debug, Clone, Copy)]
+pub struct SchemaStrategy<'a> {
+    definition: &'a ObjectDefinition,
+    reference_map: &'a BTreeMap<String, ObjectDefinition>,
+}
+
+impl<'a> SchemaStrategy<'a> {
+    /// The definition must be one payloads can be generated for, `Chimpsky::strategy` checks this.
+    pub fn new(definition: &'a ObjectDefinition, reference_map: &'a BTreeMap<String, ObjectDefinition>) -> Self {
+        SchemaStrategy { definition, reference_map }
+    }
+}
+
+impl<'a> Strategy for SchemaStrategy<'a> {
+    type Tree = SchemaValueTree<'a>;
+    type Value = Value;
+
+    fn new_tree(&self, runner: &mut TestRunner) -> NewTree<Self> {
+        let mut rng = Some(StdRng::seed_from_u64(runner.rng().next_u64()));
+        let (definition, reference_map) = (self.definition, self.reference_map);
+        let value = random_values::with_rng(&mut rng, || definition.generate_json(Some(reference_map)))
+            .unwrap_or_else(|| json!({}));
+
+        Ok(SchemaValueTree::new(self.definition, self.reference_map, value))
+    }
+}
+
+/// Shrinks by trying the candidates of the smallest payload still failing one at a time, moving
+/// on to the candidates of a candidate as soon as it fails too.
+pub struct SchemaValueTree<'a> {
+    definition: &'a ObjectDefinition,
+    shrinker: Shrinker<'a>,
+    current: Value,
+    trial: Option<Value>,
+    candidates: Vec<Value>,
+    next: usize,
+}
+
+impl<'a> SchemaValueTree<'a> {
+    fn new(definition: &'a ObjectDefinition, reference_map: &'a BTreeMap<String, ObjectDefinition>, value: Value) -> Self {
+        let shrinker = Shrinker::new(reference_map);
+        let candidates = shrinker.object(definition, &value);
+        SchemaValueTree { definition, shrinker, current: value, trial: None, candidates, next: 0 }
+    }
+
+    fn try_next(&mut self) -> bool {
+        match self.candidates.get(self.next) {
+            Some(candidate) => {
+                self.trial = Some(candidate.clone());
+                self.next += 1;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl ValueTree for SchemaValueTree<'_> {
+    type Value = Value;
+
+    fn current(&self) -> Value {
+        self.trial.as_ref().unwrap_or(&self.current).clone()
+    }
+
+    fn simplify(&mut self) -> bool {
+        // simplifying again means the last candidate failed as well, so it is kept
+        if let Some(trial) = self.trial.take() {
+            self.candidates = self.shrinker.object(self.definition, &trial);
+            self.next = 0;
+            self.current = trial;
+        }
+        self.try_next()
+    }
+
+    fn complicate(&mut self) -> bool {
+        self.trial = None;
+        self.try_next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::test_runner::{Config, TestCaseError, TestError};
+
+    use crate::schema_parser::{Schema, SchemaSet};
+    use crate::verifier::Verifier;
+    use crate::Chimpsky;
+
+    use super::*;
+
+    const KEY: &str = "message.json#/definitions/message";
+
+    fn chimpsky() -> Chimpsky {
+        let mut set = SchemaSet::default();
+        set.add(Schema::new(&json!({
+            "definitions": {
+                "message": {
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "string", "format": "uuid" },
+                        "name": { "type": "string" },
+                        "count": { "type": "integer" },
+                        "tags": { "type": "array", "items": { "type": "string" } }
+                    },
+                    "required": ["id", "count"]
+                }
+            },
+            "allOf": [ { "$ref": "#/definitions/message" } ]
+        }), Some("message.json".to_owned()))).unwrap();
+        Chimpsky::from(set)
+    }
+
+    #[test]
+    fn generated_values_satisfy_the_schema() {
+        let chimpsky = chimpsky();
+        let verifier = Verifier::new(&chimpsky.schema_set().documents, KEY).unwrap();
+        let mut runner = TestRunner::new(Config::with_cases(20));
+        runner.run(&chimpsky.strategy("message").unwrap(), |payload| {
+            let violations = verifier.verify(&payload);
+            if violations.is_empty() {
+                Ok(())
+            } else {
+                Err(TestCaseError::fail(format!("{:?}", violations)))
+            }
+        }).unwrap();
+    }
+
+    #[test]
+    fn failures_shrink_to_the_smallest_counterexample() {
+        let chimpsky = chimpsky();
+        let mut runner = TestRunner::new(Config::with_cases(100));
+        let result = runner.run(&chimpsky.strategy("message").unwrap(), |payload| {
+            if payload["count"].as_u64().unwrap() >= 10 {
+                Err(TestCaseError::fail("count too large"))
+            } else {
+                Ok(())
+            }
+        });
+
+        match result {
+            Err(TestError::Fail(_, payload)) => {
+                assert_eq!(payload["count"], 10);
+                assert_eq!(payload.as_object().unwrap().keys().collect::<Vec<_>>(), vec!["id", "count"]);
+            }
+            other => panic!("expected a failure, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde_json::Value;
+
+use crate::codegen;
+use crate::field_definitions::FieldDefinition;
+use crate::field_kinds::FieldKind;
+use crate::object_definitions::ObjectDefinition;
+
+/// TypeScript interfaces mirroring object definitions, one module per schema file. References to
+/// objects of other schema files become imports of their modules.
+pub struct TypeScriptModules<'a> {
+    keys: Vec<String>,
+    names: BTreeMap<String, String>,
+    reference_map: &'a BTreeMap<String, ObjectDefinition>,
+}
+
+impl<'a> TypeScriptModules<'a> {
+    pub fn new(keys: &[&str], reference_map: &'a BTreeMap<String, ObjectDefinition>) -> Result<Self, String> {
+        let keys = codegen::reachable_keys(keys, reference_map)?;
+        let names = codegen::type_names(&keys, reference_map);
+        Ok(TypeScriptModules { keys, names, reference_map })
+    }
+
+    /// Module file names with their source, in the order their schema files were first referred to.
+    pub fn render(&self) -> Result<Vec<(String, String)>, String> {
+        let mut files: Vec<&str> = vec![];
+        for key in &self.keys {
+            if !files.contains(&schema_file(key)) {
+                files.push(schema_file(key));
+            }
+        }
+
+        files.into_iter()
+            .map(|file| Ok((format!("{}.ts", module_name(file)), self.render_module(file)?)))
+            .collect()
+    }
+
+    fn render_module(&self, file: &str) -> Result<String, String> {
+        let mut imports: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+        let mut body = String::new();
+
+        for key in self.keys.iter().filter(|k| schema_file(k) == file) {
+            body += &format!("\n/** {} */\nexport interface {} {{\n", key, self.names[key]);
+            for (field, optional) in self.reference_map[key].all_fields(self.reference_map)? {
+                let ty = self.field_type(field, &mut |r| {
+                    if schema_file(r) != file {
+                        imports.entry(schema_file(r)).or_default().insert(&self.names[r]);
+                    }
+                });
+                body += &format!("  {}{}: {};\n", property_name(&field.name), if optional { "?" } else { "" }, ty);
+            }
+            body += "}\n";
+        }
+
+        let mut out = String::from("// Generated by chimpsky, do not edit.\n");
+        if !imports.is_empty() {
+            out += "\n";
+        }
+        for (other, names) in imports {
+            let names = names.into_iter().collect::<Vec<_>>().join(", ");
+            out += &format!("import type {{ {} }} from \"./{}\";\n", names, module_name(other));
+        }
+
+        Ok(out + &body)
+    }
+
+    fn field_type<'s>(&'s self, field: &FieldDefinition, import: &mut dyn FnMut(&'s str)) -> String {
+        if let Some(value) = &field.const_value {
+            return literal(value);
+        }
+        if let Some(values) = field.enum_values.as_ref().filter(|values| !values.is_empty()) {
+            return values.iter().map(literal).collect::<Vec<_>>().join(" | ");
+        }
+        if field.format.is_some() || field.pattern.is_some() {
+            return "string".to_owned();
+        }
+
+        match &field.kind {
+            None => "unknown".to_owned(),
+            Some(kind) => self.kind_type(kind, import),
+        }
+    }
+
+    fn kind_type<'s>(&'s self, kind: &FieldKind, import: &mut dyn FnMut(&'s str)) -> String {
+        match kind {
+            FieldKind::Str => "string".to_owned(),
+            FieldKind::Int | FieldKind::Float => "number".to_owned(),
+            FieldKind::Bool => "boolean".to_owned(),
+            FieldKind::Object => "Record<string, unknown>".to_owned(),
+            FieldKind::Null => "null".to_owned(),
+            FieldKind::Unknown(_) => "unknown".to_owned(),
+            FieldKind::Reference(r) => {
+                let (key, name) = self.names.get_key_value(r).unwrap();
+                import(key);
+                name.to_owned()
+            }
+            FieldKind::OneOf(kinds) => self.union(kinds, import),
+            FieldKind::ListOf(items) => match items.as_slice() {
+                [] => "unknown[]".to_owned(),
+                [item] if !matches!(item, FieldKind::OneOf(_)) => format!("{}[]", self.kind_type(item, import)),
+                items => format!("({})[]", self.union(items, import)),
+            },
+        }
+    }
+
+    fn union<'s>(&'s self, kinds: &[FieldKind], import: &mut dyn FnMut(&'s str)) -> String {
+        let mut members: Vec<String> = vec![];
+        for kind in kinds {
+            let member = self.kind_type(kind, import);
+            if !members.contains(&member) {
+                members.push(member);
+            }
+        }
+        members.join(" | ")
+    }
+}
+
+fn schema_file(key: &str) -> &str {
+    key.split('#').next().unwrap()
+}
+
+/// `bigmessage.schema.json` becomes the module `bigmessage.schema`.
+fn module_name(file: &str) -> &str {
+    file.strip_suffix(".json").unwrap_or(file)
+}
+
+fn property_name(name: &str) -> String {
+    let identifier = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+    if identifier {
+        name.to_owned()
+    } else {
+        Value::from(name).to_string()
+    }
+}
+
+/// JSON scalars are valid TypeScript literal types; arrays and objects fall back to their shape.
+fn literal(value: &Value) -> String {
+    match value {
+        Value::Array(_) => "unknown[]".to_owned(),
+        Value::Object(_) => "Record<string, unknown>".to_owned(),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::schema_parser::{Schema, SchemaSet};
+
+    use super::*;
+
+    fn schema_set() -> SchemaSet {
+        let mut set = SchemaSet::default();
+        set.add(Schema::new(&json!({
+            "definitions": {
+                "message": {
+                    "type": "object",
+                    "properties": {
+                        "kind": { "type": "string", "const": "event" },
+                        "state": { "type": "string", "enum": ["on", "off", null] },
+                        "category-of": { "type": ["string", "null"] },
+                        "tags": { "type": "array", "items": { "type": "string" } },
+                        "carried": { "$ref": "carried.json#/definitions/carried" },
+                        "parent": { "$ref": "#/definitions/message" }
+                    },
+                    "required": ["kind", "state", "category-of", "carried"]
+                }
+            },
+            "allOf": [ { "$ref": "#/definitions/message" } ]
+        }), Some("message.json".to_owned()))).unwrap();
+        set.add(Schema::new(&json!({
+            "type": "object",
+            "properties": { "version": { "type": "number" }, "data": { "type": "object" } },
+            "required": ["version"]
+        }), Some("carried.json".to_owned()))).unwrap();
+        set
+    }
+
+    fn modules() -> Vec<(String, String)> {
+        let set = schema_set();
+        TypeScriptModules::new(&["message.json#/definitions/message"], &set.reference_map).unwrap().render().unwrap()
+    }
+
+    #[test]
+    fn one_module_per_schema_file() {
+        let modules = modules();
+        let files = modules.iter().map(|(f, _)| f.as_str()).collect::<Vec<_>>();
+        assert_eq!(files, vec!["message.ts", "carried.ts"]);
+
+        let carried = &modules[1].1;
+        assert!(carried.contains("export interface Carried {\n  version: number;\n  data?: Record<string, unknown>;\n}\n"));
+        assert!(!carried.contains("import"));
+    }
+
+    #[test]
+    fn interfaces_and_imports() {
+        let message = &modules()[0].1;
+        assert!(message.contains("import type { Carried } from \"./carried\";\n"));
+        assert!(message.contains("/** message.json#/definitions/message */\nexport interface Message {\n"));
+        assert!(message.contains("  kind: \"event\";\n"));
+        assert!(message.contains("  state: \"on\" | \"off\" | null;\n"));
+        assert!(message.contains("  \"category-of\": string | null;\n"));
+        assert!(message.contains("  tags?: string[];\n"));
+        assert!(message.contains("  carried: Carried;\n"));
+        assert!(message.contains("  parent?: Message;\n"));
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+
+use jsonschema::{Draft, Validator};
+use serde_json::{json, Value};
+
+use crate::schema_parser::SchemaSet;
+
+const BASE_URI: &str = "file:///chimpsky/";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub keyword: String,
+    pub instance_path: String,
+    pub message: String,
+}
+
+pub struct Verifier {
+    validator: Validator,
+}
+
+impl Verifier {
+    pub fn new(documents: &BTreeMap<String, Value>, key: &str) -> Result<Self, String> {
+        let root = json!({"$ref": Self::reference_uri(documents, key)?});
+
+        let resources = Self::referenced_documents(documents, key).into_iter()
+            .map(|(filename, document)| {
+                (format!("{}{}", BASE_URI, filename), Draft::Draft7.create_resource(document.clone()))
+            });
+
+        jsonschema::options()
+            .with_draft(Draft::Draft7)
+            .should_validate_formats(true)
+            .with_resources(resources)
+            .build(&root)
+            .map(|validator| Verifier { validator })
+            .map_err(|e| format!("cannot build validator for {}: {}", key, e))
+    }
+
+    pub fn verify(&self, payload: &Value) -> Vec<Violation> {
+        self.validator.iter_errors(payload)
+            .map(|e| Violation {
+                keyword: e.kind().keyword().to_owned(),
+                instance_path: e.instance_path().to_string(),
+                message: e.to_string(),
+            })
+            .collect()
+    }
+
+    /// Documents reachable from the object through `$ref`, so that a dangling reference in an
+    /// unrelated document does not keep the validator from being built.
+    fn referenced_documents<'a>(documents: &'a BTreeMap<String, Value>, key: &str) -> BTreeMap<&'a str, &'a Value> {
+        let mut reached = BTreeMap::new();
+        let mut pending = vec![key.split('#').next().unwrap().to_owned()];
+
+        while let Some(filename) = pending.pop() {
+            if let Some((filename, document)) = documents.get_key_value(&filename) {
+                if reached.insert(filename.as_str(), document).is_none() {
+                    collect_referenced_files(document, &mut pending);
+                }
+            }
+        }
+        reached
+    }
+
+    /// Single definition schemas are keyed as `file#/definitions/name` even though the definition
+    /// is the document itself, so the fragment is only kept when it points into the document.
+    fn reference_uri(documents: &BTreeMap<String, Value>, key: &str) -> Result<String, String> {
+        let mut parts = key.splitn(2, '#');
+        let filename = parts.next().unwrap();
+        let fragment = parts.next().unwrap_or("");
+
+        let document = documents.get(filename)
+            .ok_or_else(|| format!("no schema document {} for {}", filename, key))?;
+
+        if !fragment.is_empty() && document.pointer(fragment).is_some() {
+            Ok(format!("{}{}#{}", BASE_URI, filename, fragment))
+        } else {
+            Ok(format!("{}{}", BASE_URI, filename))
+        }
+    }
+}
+
+fn collect_referenced_files(v: &Value, files: &mut Vec<String>) {
+    match v {
+        Value::Object(o) => {
+            if let Some(Value::String(reference)) = o.get("$ref") {
+                let filename = reference.split('#').next().unwrap();
+                if !filename.is_empty() {
+                    files.push(filename.to_owned());
+                }
+            }
+            o.values().for_each(|v| collect_referenced_files(v, files));
+        }
+        Value::Array(a) => a.iter().for_each(|v| collect_referenced_files(v, files)),
+        _ => {}
+    }
+}
+
+/// Outcome of checking the payloads of one object against its schema.
+#[derive(Debug)]
+pub enum ObjectCheck {
+    /// `invalid` of the generated payloads had violations, counted by keyword and instance path.
+    Checked { key: String, invalid: u64, violations: BTreeMap<(String, String), u64> },
+    /// The object could not be checked, for the reason given.
+    Skipped { key: String, reason: String },
+}
+
+/// Generates `iterations` payloads for every object and validates them. Objects whose validator
+/// cannot be built or whose payloads cannot be generated are skipped rather than ending the check.
+pub fn selfcheck(schema_set: &SchemaSet, iterations: u64) -> Vec<ObjectCheck> {
+    let reference_map = &schema_set.reference_map;
+
+    reference_map.keys()
+        .map(|key| {
+            let verifier = match Verifier::new(&schema_set.documents, key) {
+                Ok(verifier) => verifier,
+                Err(e) => return ObjectCheck::Skipped { key: key.to_owned(), reason: e },
+            };
+            if !crate::can_generate(key, reference_map) {
+                let reason = "payloads cannot be generated, see lint".to_owned();
+                return ObjectCheck::Skipped { key: key.to_owned(), reason };
+            }
+
+            let mut invalid = 0;
+            let mut violations = BTreeMap::new();
+            for _ in 0..iterations {
+                let payload = reference_map[key].generate_json(Some(reference_map)).unwrap();
+                let found = verifier.verify(&payload);
+                if !found.is_empty() {
+                    invalid += 1;
+                }
+                for v in found {
+                    *violations.entry((v.keyword, v.instance_path)).or_default() += 1;
+                }
+            }
+            ObjectCheck::Checked { key: key.to_owned(), invalid, violations }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schema_parser::Schema;
+
+    use super::*;
+
+    fn documents() -> BTreeMap<String, Value> {
+        let mut documents = BTreeMap::new();
+        documents.insert("carried.schema.json".to_owned(), json!({
+            "definitions": {
+                "carried": {
+                    "type": "object",
+                    "properties": {
+                        "version": { "type": "number", "minimum": 1, "maximum": 1 }
+                    },
+                    "required": ["version"]
+                }
+            }
+        }));
+        documents.insert("outer.schema.json".to_owned(), json!({
+            "type": "object",
+            "properties": {
+                "carried": { "$ref": "carried.schema.json#/definitions/carried" },
+                "at": { "type": "string", "format": "date-time" }
+            }
+        }));
+        documents
+    }
+
+    #[test]
+    fn valid_payload_has_no_violations() {
+        let verifier = Verifier::new(&documents(), "carried.schema.json#/definitions/carried").unwrap();
+        assert!(verifier.verify(&json!({"version": 1})).is_empty());
+    }
+
+    #[test]
+    fn violations_report_keyword_and_path() {
+        let verifier = Verifier::new(&documents(), "outer.schema.json#/definitions/outer").unwrap();
+        let violations = verifier.verify(&json!({"carried": {"version": 4.5}, "at": "yesterday"}));
+
+        let mut keywords = violations.iter().map(|v| v.keyword.as_str()).collect::<Vec<_>>();
+        keywords.sort();
+        assert_eq!(keywords, vec!["format", "maximum"]);
+        assert!(violations.iter().any(|v| v.instance_path == "/carried/version"));
+    }
+
+    #[test]
+    fn unknown_document_is_an_error() {
+        assert!(Verifier::new(&documents(), "missing.schema.json#/definitions/missing").is_err());
+    }
+
+    #[test]
+    fn selfcheck_skips_objects_it_cannot_check() {
+        let mut set = SchemaSet::default();
+        set.add(Schema::new(&json!({
+            "definitions": {
+                "plain": {
+                    "type": "object",
+                    "properties": { "n": { "type": "integer", "minimum": 0, "maximum": 9 } },
+                    "required": ["n"]
+                },
+                "contact": {
+                    "type": "object",
+                    "properties": { "email": { "type": "string", "format": "email" } },
+                    "required": ["email"]
+                }
+            }
+        }), Some("set.json".to_owned()))).unwrap();
+        set.add(Schema::new(&json!({
+            "type": "object",
+            "properties": { "other": { "$ref": "missing.json#/definitions/missing" } }
+        }), Some("dangling.json".to_owned()))).unwrap();
+
+        let checks = selfcheck(&set, 5);
+        assert_eq!(checks.len(), 3);
+        for check in checks {
+            match check {
+                ObjectCheck::Checked { key, invalid, .. } => {
+                    assert_eq!(key, "set.json#/definitions/plain");
+                    assert_eq!(invalid, 0);
+                }
+                ObjectCheck::Skipped { key, reason } if key == "set.json#/definitions/contact" =>
+                    assert!(reason.contains("cannot be generated"), "{}", reason),
+                ObjectCheck::Skipped { key, reason } => {
+                    assert_eq!(key, "dangling.json#/definitions/dangling");
+                    assert!(reason.starts_with("cannot build validator"), "{}", reason);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,4 @@
+//! Compiles the output of `codegen rust --tests` kept in `fixtures/codegen_rust.rs` and runs
+//! its round-trip tests. The fixture is checked against the generator in `rust_codegen`.
+
+include!("fixtures/codegen_rust.rs");
@@ -0,0 +1,100 @@
+// Generated by chimpsky, do not edit.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MessageValue {
+    Integer(i64),
+    String(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MessageState {
+    #[serde(rename = "on")]
+    On,
+    #[serde(rename = "off")]
+    Off,
+}
+
+/// message
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    pub r#type: String,
+    #[serde(rename = "category-of")]
+    pub category_of: Option<String>,
+    pub value: MessageValue,
+    pub state: MessageState,
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<Box<Message>>,
+    pub carried: Carried,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReadingUnit {
+    #[serde(rename = "celsius")]
+    Celsius,
+    #[serde(rename = "fahrenheit")]
+    Fahrenheit,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ReadingValue {
+    Integer(i64),
+    String(String),
+}
+
+/// reading
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reading {
+    pub sensor: String,
+    pub unit: ReadingUnit,
+    pub value: Option<ReadingValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub samples: Option<Vec<f64>>,
+    pub carried: Carried,
+}
+
+/// carried
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Carried {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<f64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn without_nulls(v: serde_json::Value) -> serde_json::Value {
+        match v {
+            serde_json::Value::Object(m) => m.into_iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k, without_nulls(v)))
+                .collect(),
+            serde_json::Value::Array(a) => a.into_iter().map(without_nulls).collect(),
+            v => v,
+        }
+    }
+
+    fn round_trip<T: Serialize + serde::de::DeserializeOwned>(payload: &str) {
+        let value: serde_json::Value = serde_json::from_str(payload).unwrap();
+        let typed: T = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(without_nulls(serde_json::to_value(&typed).unwrap()), without_nulls(value));
+    }
+
+    // no round-trip test for message, its payloads cannot be generated
+
+    #[test]
+    fn reading_round_trips() {
+        for payload in &[
+            "{\"sensor\":\"nn2BXDprF90w85JZvdBw\",\"unit\":\"celsius\",\"value\":\"vfMwxjiydbkilZdJEEep\",\"samples\":[129.9784899417349,839.9175357971947,436.36548670359423,479.49599566414093,469.1628239220733,781.2903883270787,153.84470186315812,950.4828943628382,126.41555813073579],\"carried\":{\"version\":393.08972999870684}}",
+            "{\"sensor\":\"PXuJK75l9trmVf66Ww85\",\"unit\":\"fahrenheit\",\"value\":3299,\"samples\":[],\"carried\":{\"version\":656.9542723868183}}",
+            "{\"sensor\":\"ao8C0tbMsLGaAeX30bZb\",\"unit\":\"celsius\",\"value\":null,\"samples\":[],\"carried\":{\"version\":387.3163586871331}}",
+        ] {
+            round_trip::<Reading>(payload);
+        }
+    }
+}